@@ -0,0 +1,243 @@
+use std::path::PathBuf;
+
+use crate::model::error::ErrorPolicy;
+use crate::model::urban_network::{
+    Connectivity, GeoJsonNetworkOptions, ImportOptions, RouteChoice, RouteChoiceModel, RouteMetric,
+    RouteWeights, SyntheticLayout, WalkabilityFilter,
+};
+
+/// Extract simulated when none is given on the command line, relative to the working directory.
+const DEFAULT_EXTRACT: &str = "src/data/middlebury.osm.pbf";
+/// Study area the default extract is clipped to.
+const DEFAULT_BOUNDARY: &str = "src/data/middlebury.poly";
+/// Where imported networks are cached unless told otherwise.
+const DEFAULT_CACHE_DIR: &str = "target/network-cache";
+
+pub const USAGE: &str = "\
+Usage: flaneur-abm [options] [extract...]
+
+Simulate pedestrians on the street network of one or more OSM extracts, PBF or XML
+(src/data/middlebury.osm.pbf, clipped to src/data/middlebury.poly, unless others are given).
+Extracts are merged in order, so later ones override the nodes and ways of earlier ones.
+
+Options:
+  --geojson PATH    Build the network from the line features of a GeoJSON file instead
+  --snap METRES     Merge GeoJSON vertices closer than this (default: 0, identical only)
+  --default-highway VALUE
+                    highway tag for GeoJSON features that have none
+  --graphml PATH    Load the network from a GraphML file saved by OSMnx instead
+  --save-graphml PATH
+                    Save the imported network as GraphML that OSMnx can load
+  --synthetic LAYOUT
+                    Generate the network instead, as grid:COLUMNSxROWS, radial:RINGSxSPOKES,
+                    organic:CELLS or cul-de-sac:BRANCHESxDEPTH
+  --export DIR      Write the network's nodes and edges to DIR as GeoJSON and FlatGeobuf
+  --boundary PATH   Clip the extract to the study area in an Osmosis .poly or GeoJSON file
+  --zones PATH      Tally agents by the zones in a GeoJSON file
+  --zone-name PROP  Feature property naming each zone (default: name)
+  --cache-dir PATH  Cache imported networks in this directory (default: target/network-cache)
+  --no-cache        Import the extract afresh, without reading or writing the cache
+  --component KIND  Keep only the largest strong or weak component, or all of them (default: strong)
+  --no-hierarchy    Route by A* search, without building a contraction hierarchy
+  --route-choice MODEL
+                    How agents pick their routes: shortest, logit (path-size logit over the five
+                    least-cost routes) or perturbed:SIGMA (least cost under random noise)
+                    (default: shortest)
+  --route-metric METRIC
+                    What routes minimise: distance, angle (total change of direction) or
+                    turns:DEGREES (turns sharper than DEGREES) (default: distance)
+  --no-simplify     Keep every OSM node, rather than merging chains of segments into single edges
+  --skip-errors     Skip ways with missing nodes or too few nodes, rather than failing the import
+  --all-ways        Import every way in the extract, not only those a pedestrian could walk
+  --help            Print this message";
+
+/// Where the street network comes from.
+#[derive(Clone, Debug)]
+pub enum NetworkSource {
+    /// OSM extracts to import and merge, relative to the working directory unless absolute
+    Osm(Vec<PathBuf>),
+    /// GeoJSON line features, and how to node and tag them
+    GeoJson(PathBuf, GeoJsonNetworkOptions),
+    /// A street graph saved by OSMnx
+    GraphMl(PathBuf),
+    /// A generated street network
+    Synthetic(SyntheticLayout),
+}
+
+/// What to simulate and how to build its street network, as given on the command line.
+#[derive(Clone, Debug)]
+pub struct RunOptions {
+    pub source: NetworkSource,
+    /// Boundary file to clip the extract to
+    pub boundary: Option<PathBuf>,
+    /// GeoJSON zone layer, and the feature property its zones are named by
+    pub zones: Option<(PathBuf, String)>,
+    /// Where to save the network as GraphML once imported
+    pub save_graphml: Option<PathBuf>,
+    /// Directory to export the network's layers to, for inspection in GIS tools
+    pub export_dir: Option<PathBuf>,
+    pub import_options: ImportOptions,
+    /// What agents' routes minimise
+    pub route_metric: RouteMetric,
+    /// How agents pick their routes, when minimising distance
+    pub route_choice: RouteChoice,
+}
+
+impl RunOptions {
+    /// Read options from command-line arguments, not counting the program name. Returns `None` if
+    /// help was asked for, and a message naming the offending argument if one is not understood.
+    pub fn parse(args: impl IntoIterator<Item = String>) -> Result<Option<Self>, String> {
+        let mut args = args.into_iter();
+        let mut extracts = Vec::new();
+        let mut geojson = None;
+        let mut geojson_options = GeoJsonNetworkOptions::default();
+        let mut graphml = None;
+        let mut synthetic = None;
+        let mut save_graphml = None;
+        let mut export_dir = None;
+        let mut boundary = None;
+        let mut zones = None;
+        let mut zone_name = String::from("name");
+        let mut route_metric = RouteMetric::default();
+        let mut route_choice = RouteChoice::default();
+        let mut import_options = ImportOptions {
+            simplify: true,
+            largest_component: Some(Connectivity::Strong),
+            contraction_hierarchy: true,
+            cache_dir: Some(PathBuf::from(DEFAULT_CACHE_DIR)),
+            ..Default::default()
+        };
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--help" | "-h" => return Ok(None),
+                "--geojson" => geojson = Some(PathBuf::from(value_of(&arg, &mut args)?)),
+                "--snap" => {
+                    let value = value_of(&arg, &mut args)?;
+                    geojson_options.snap_tolerance = value
+                        .parse()
+                        .map_err(|_| format!("Invalid snapping tolerance {}", value))?;
+                }
+                "--default-highway" => {
+                    geojson_options.default_highway = Some(value_of(&arg, &mut args)?)
+                }
+                "--graphml" => graphml = Some(PathBuf::from(value_of(&arg, &mut args)?)),
+                "--save-graphml" => save_graphml = Some(PathBuf::from(value_of(&arg, &mut args)?)),
+                "--synthetic" => synthetic = Some(parse_layout(&value_of(&arg, &mut args)?)?),
+                "--export" => export_dir = Some(PathBuf::from(value_of(&arg, &mut args)?)),
+                "--boundary" => boundary = Some(PathBuf::from(value_of(&arg, &mut args)?)),
+                "--zones" => zones = Some(PathBuf::from(value_of(&arg, &mut args)?)),
+                "--zone-name" => zone_name = value_of(&arg, &mut args)?,
+                "--cache-dir" => {
+                    import_options.cache_dir = Some(PathBuf::from(value_of(&arg, &mut args)?))
+                }
+                "--no-cache" => import_options.cache_dir = None,
+                "--component" => {
+                    import_options.largest_component = match value_of(&arg, &mut args)?.as_str() {
+                        "strong" => Some(Connectivity::Strong),
+                        "weak" => Some(Connectivity::Weak),
+                        "all" => None,
+                        other => return Err(format!("Unknown connectivity {}", other)),
+                    }
+                }
+                "--skip-errors" => import_options.on_error = ErrorPolicy::SkipAndReport,
+                "--no-simplify" => import_options.simplify = false,
+                "--no-hierarchy" => import_options.contraction_hierarchy = false,
+                "--route-metric" => route_metric = parse_route_metric(&value_of(&arg, &mut args)?)?,
+                "--route-choice" => route_choice = parse_route_choice(&value_of(&arg, &mut args)?)?,
+                "--all-ways" => import_options.walkability = WalkabilityFilter::permissive(),
+                flag if flag.starts_with('-') => return Err(format!("Unknown option {}", flag)),
+                path => extracts.push(PathBuf::from(path)),
+            }
+        }
+        let sources = [geojson.is_some(), graphml.is_some(), synthetic.is_some()];
+        match sources.iter().filter(|&&given| given).count() {
+            0 => {}
+            1 if extracts.is_empty() => {}
+            1 => {
+                return Err(String::from(
+                    "GeoJSON, GraphML and synthetic networks cannot be merged with OSM extracts",
+                ))
+            }
+            _ => {
+                return Err(String::from(
+                    "Give only one of --geojson, --graphml and --synthetic",
+                ))
+            }
+        }
+        let source = if let Some(path) = geojson {
+            NetworkSource::GeoJson(path, geojson_options)
+        } else if let Some(path) = graphml {
+            NetworkSource::GraphMl(path)
+        } else if let Some(layout) = synthetic {
+            NetworkSource::Synthetic(layout)
+        } else if extracts.is_empty() {
+            // The bundled boundary only fits the bundled extract
+            boundary = boundary.or_else(|| Some(PathBuf::from(DEFAULT_BOUNDARY)));
+            NetworkSource::Osm(vec![PathBuf::from(DEFAULT_EXTRACT)])
+        } else {
+            NetworkSource::Osm(extracts)
+        };
+        Ok(Some(RunOptions {
+            source,
+            boundary,
+            zones: zones.map(|path| (path, zone_name)),
+            save_graphml,
+            export_dir,
+            import_options,
+            route_metric,
+            route_choice,
+        }))
+    }
+}
+
+/// The value following an option that takes one.
+fn value_of(option: &str, args: &mut impl Iterator<Item = String>) -> Result<String, String> {
+    args.next()
+        .ok_or_else(|| format!("Option {} needs a value", option))
+}
+
+/// A synthetic layout given as its kind and size, e.g. `grid:10x8` or `organic:200`.
+fn parse_layout(value: &str) -> Result<SyntheticLayout, String> {
+    let invalid = || format!("Invalid layout {}", value);
+    let (kind, size) = value.split_once(':').ok_or_else(invalid)?;
+    let dimensions: Vec<u32> = size
+        .split('x')
+        .map(|n| n.parse().map_err(|_| invalid()))
+        .collect::<Result<_, _>>()?;
+    match (kind, dimensions.as_slice()) {
+        ("grid", &[columns, rows]) => Ok(SyntheticLayout::Grid { columns, rows }),
+        ("radial", &[rings, spokes]) => Ok(SyntheticLayout::Radial { rings, spokes }),
+        ("organic", &[cells]) => Ok(SyntheticLayout::Organic { cells }),
+        ("cul-de-sac", &[branches, depth]) => Ok(SyntheticLayout::CulDeSac { branches, depth }),
+        _ => Err(invalid()),
+    }
+}
+
+/// A route choice model given by name, e.g. `logit` or `perturbed:0.3`.
+fn parse_route_choice(value: &str) -> Result<RouteChoice, String> {
+    let invalid = || format!("Invalid route choice {}", value);
+    match value.split_once(':') {
+        None if value == "shortest" => Ok(RouteChoice::shortest()),
+        None if value == "logit" => Ok(RouteChoice::path_size_logit()),
+        Some(("perturbed", sigma)) => Ok(RouteChoice {
+            weights: RouteWeights::default(),
+            model: RouteChoiceModel::Perturbed {
+                sigma: sigma.parse().map_err(|_| invalid())?,
+            },
+        }),
+        _ => Err(invalid()),
+    }
+}
+
+/// A route metric given by name, e.g. `angle` or `turns:45`.
+fn parse_route_metric(value: &str) -> Result<RouteMetric, String> {
+    let invalid = || format!("Invalid route metric {}", value);
+    match value.split_once(':') {
+        None if value == "distance" => Ok(RouteMetric::Distance),
+        None if value == "angle" => Ok(RouteMetric::LeastAngle),
+        Some(("turns", threshold)) => Ok(RouteMetric::FewestTurns {
+            threshold: threshold.parse().map_err(|_| invalid())?,
+        }),
+        _ => Err(invalid()),
+    }
+}
@@ -2,17 +2,27 @@ extern crate krabmaga;
 use krabmaga::*;
 
 use crate::model::state::network_state::UrbanNetworkState;
+use crate::model::urban_network::{
+    export_network, read_boundary, write_graphml, ImportOptions, NetworkMetrics,
+};
 #[cfg(any(feature = "visualization", feature = "visualization_wasm"))]
 use {
-    crate::visualization::vis_state::VisState, krabmaga::bevy::prelude::Color,
-    krabmaga::bevy::prelude::FixedUpdate, krabmaga::visualization::fields::network::NetworkRender,
+    crate::model::urban_network::SyntheticLayout, crate::visualization::vis_state::VisState,
+    krabmaga::bevy::prelude::Color, krabmaga::bevy::prelude::FixedUpdate,
+    krabmaga::visualization::fields::network::NetworkRender,
     krabmaga::visualization::visualization::Visualization,
 };
 
+#[cfg(not(any(feature = "visualization", feature = "visualization_wasm")))]
+mod cli;
 mod model;
 
 static DISCRETIZATION: f32 = 10.0 / 1.5;
 static TOROIDAL: bool = false;
+/// Simulated seconds per step
+pub static STEP_SECONDS: f32 = 1.0;
+/// Walking speed, in metres per second
+pub static WALKING_SPEED: f32 = 1.4;
 ///Initial infected nodes
 pub static INITIAL_INFECTED_PROB: f64 = 0.01;
 pub static INIT_EDGES: usize = 2;
@@ -30,21 +40,120 @@ fn main() -> Result<(), std::io::Error> {
     };
 
     let step: u64 = 110;
-    let dim: (f32, f32) = (100., 100.);
+    // Extent of generated street networks, in metres
+    let dim: (f32, f32) = (1000., 1000.);
     let num_nodes = 3_000;
     let num_agents = 5_000;
     // let urban_network =
     //     UrbanNetworkState::new(dim, num_agents, num_nodes, DISCRETIZATION, TOROIDAL);
-    let mut osm_file_path = env::current_dir()?;
-    osm_file_path.push("src/data/middlebury.osm.pbf");
-    print!("{:?}", &osm_file_path);
-    match UrbanNetworkState::from_osm_file(&osm_file_path, num_agents, DISCRETIZATION, TOROIDAL) {
-        Ok(urban_network) => {
+    let run = match cli::RunOptions::parse(env::args().skip(1)) {
+        Ok(Some(run)) => run,
+        Ok(None) => {
+            println!("{}", cli::USAGE);
+            return Ok(());
+        }
+        Err(e) => {
+            eprintln!("{}\n\n{}", e, cli::USAGE);
+            std::process::exit(2);
+        }
+    };
+    let current_dir = env::current_dir()?;
+    let boundary = match &run.boundary {
+        Some(boundary_file_path) => {
+            match read_boundary(&env::current_dir()?.join(boundary_file_path)) {
+                Ok(boundary) => Some(boundary),
+                Err(e) => {
+                    println!("{}; importing the full extract", e);
+                    None
+                }
+            }
+        }
+        None => None,
+    };
+    let import_options = ImportOptions {
+        boundary,
+        ..run.import_options
+    };
+    let urban_network = match &run.source {
+        cli::NetworkSource::Osm(extracts) => {
+            let osm_file_paths: Vec<_> = extracts.iter().map(|e| current_dir.join(e)).collect();
+            print!("{:?}", &osm_file_paths);
+            UrbanNetworkState::from_osm_file(
+                &osm_file_paths,
+                num_agents,
+                DISCRETIZATION,
+                TOROIDAL,
+                &import_options,
+            )
+        }
+        cli::NetworkSource::GeoJson(geojson_file_path, geojson_options) => {
+            UrbanNetworkState::from_geojson_file(
+                &current_dir.join(geojson_file_path),
+                geojson_options,
+                num_agents,
+                DISCRETIZATION,
+                TOROIDAL,
+                &import_options,
+            )
+        }
+        cli::NetworkSource::GraphMl(graphml_file_path) => UrbanNetworkState::from_graphml_file(
+            &current_dir.join(graphml_file_path),
+            num_agents,
+            DISCRETIZATION,
+            TOROIDAL,
+            &import_options,
+        ),
+        cli::NetworkSource::Synthetic(layout) => Ok(UrbanNetworkState::new(
+            layout,
+            dim,
+            num_agents,
+            DISCRETIZATION,
+            TOROIDAL,
+        )),
+    };
+    match urban_network {
+        Ok(mut urban_network) => {
+            urban_network.route_metric = run.route_metric;
+            urban_network.route_choice = run.route_choice;
+            if let Some(report) = &urban_network.import_report {
+                print!("{}", report);
+                let report_path = env::current_dir()?.join("target/import-report.json");
+                if let Err(e) = report.write_json(&report_path) {
+                    println!("Unable to write import report to {:?}: {}", report_path, e);
+                }
+            }
+            if let (Some(graphml_file_path), Some(projection)) =
+                (&run.save_graphml, &urban_network.projection)
+            {
+                let graphml_file_path = current_dir.join(graphml_file_path);
+                if let Err(e) =
+                    write_graphml(&urban_network.network, projection, &graphml_file_path)
+                {
+                    println!("{}", e);
+                }
+            }
+            if let (Some(export_dir), Some(projection)) =
+                (&run.export_dir, &urban_network.projection)
+            {
+                let export_dir = current_dir.join(export_dir);
+                let metrics = NetworkMetrics::default();
+                if let Err(e) =
+                    export_network(&urban_network.network, projection, &metrics, &export_dir)
+                {
+                    println!("Unable to export network to {:?}: {}", export_dir, e);
+                }
+            }
+            if let Some((zones_file_path, name_property)) = &run.zones {
+                let zones_file_path = env::current_dir()?.join(zones_file_path);
+                if let Err(e) = urban_network.load_zones(&zones_file_path, name_property) {
+                    println!("{}", e);
+                }
+            }
             simulate!(urban_network, step, 1, false);
         }
 
         Err(e) => {
-            print!("{:?}", e);
+            println!("{}", e);
         }
     }
     Ok(())
@@ -59,8 +168,13 @@ mod visualization;
 fn main() {
     // Initialize the simulation and its visualization here.
     let dim: (f32, f32) = (500., 500.);
-    let num_nodes = 100;
-    let epidemic_network = UrbanNetworkState::new(dim, num_nodes, DISCRETIZATION, TOROIDAL);
+    let num_agents = 100;
+    let layout = SyntheticLayout::Grid {
+        columns: 10,
+        rows: 10,
+    };
+    let epidemic_network =
+        UrbanNetworkState::new(&layout, dim, num_agents, DISCRETIZATION, TOROIDAL);
 
     let mut app = Visualization::default()
         .with_window_dimensions(1000., 700.)
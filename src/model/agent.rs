@@ -1,13 +1,15 @@
 use std::fmt::Display;
+use std::hash::{Hash, Hasher};
 
-use crate::model::urban_network::StreetNetworkPosition;
+use crate::model::urban_network::{Route, RouteChoice, RouteMetric, StreetNetworkPosition};
 use krabmaga::engine::agent::Agent;
 use krabmaga::engine::fields::field_2d::Location2D;
 use krabmaga::engine::location::Real2D;
 use krabmaga::engine::state::State;
 use krabmaga::rand::rngs::ThreadRng;
+use krabmaga::rand::Rng;
 
-use crate::UrbanNetworkState;
+use crate::{UrbanNetworkState, STEP_SECONDS, WALKING_SPEED};
 
 use super::urban_network::StreetNode;
 
@@ -17,13 +19,20 @@ use super::urban_network::StreetNode;
 //     loc: AgentLoc,
 // }
 
-#[derive(Clone, Debug, Hash, PartialEq)]
+#[derive(Clone, Debug, PartialEq)]
 pub struct PedAgent {
     pub id: u32,
     pub loc: StreetNetworkPosition,
     pub dest: Option<StreetNetworkPosition>,
-    pub path: Option<Vec<StreetNode>>, //pub status: AgentStatus,
-                                       //pub encounters: Vec<AgentEncounter>,
+    pub path: Option<Vec<StreetNode>>,
+    /// Seconds left to wait at a crossing or signal before walking on
+    pub wait: f32,
+    /// What the agent's routes minimise
+    pub route_metric: RouteMetric,
+    /// How the agent weighs street attributes and picks among routes, when minimising distance
+    pub route_choice: RouteChoice,
+    //pub status: AgentStatus,
+    //pub encounters: Vec<AgentEncounter>,
 }
 
 impl PedAgent {
@@ -32,46 +41,145 @@ impl PedAgent {
             id,
             loc: init_loc,
             dest: None,
-            path: None, // status: init_status,
-                        // encounters: Vec::<AgentEncounter>::new(),
+            path: None,
+            wait: 0.0,
+            route_metric: RouteMetric::default(),
+            route_choice: RouteChoice::default(),
+            // status: init_status,
+            // encounters: Vec::<AgentEncounter>::new(),
         }
     }
 
-    pub fn update_network_loc(&mut self, state: &mut UrbanNetworkState) {
-        // Get random number gen from state
-        let rng = ThreadRng::default();
+    /// Start waiting on reaching a node, for as long as its crossing or signal holds the agent up.
+    pub fn arrive_at_node(&mut self, node_id: u32, state: &UrbanNetworkState, rng: &mut impl Rng) {
+        if let Some(node) = state.network.0.get_object(node_id) {
+            self.wait = state.intersection_delays.sample_delay(node.control, rng);
+        }
+    }
+
+    /// Choose a route to the agent's destination by its route metric and route choice model,
+    /// filling `path` with the nodes along it. Leaves `path` empty if the agent has no destination
+    /// or cannot reach it.
+    pub fn plan_path(&mut self, state: &UrbanNetworkState, rng: &mut impl Rng) -> Option<Route> {
+        let network = &state.network;
+        let dest = self.dest?;
+        let route = match self.route_metric {
+            RouteMetric::Distance => state.choose_route(&self.route_choice, &self.loc, &dest, rng),
+            metric => network.route_by_turn_cost(
+                &self.loc,
+                &dest,
+                &|angle| metric.turn_cost(angle),
+                Some(&state.intersection_delays),
+            ),
+        };
+        self.path = route.as_ref().map(|route| {
+            route
+                .nodes
+                .iter()
+                .filter_map(|id| network.0.get_object(*id))
+                .collect()
+        });
+        route
+    }
 
-        // Placeholder for next location
-        let next_loc = Some(StreetNetworkPosition::default());
+    /// Walk one step's distance along `path` towards `dest`, dropping nodes from the front of the
+    /// path as they are passed. On reaching a node the agent stops there for as long as its
+    /// crossing or signal holds it up; on reaching the destination it clears its destination and
+    /// path.
+    pub fn update_network_loc(&mut self, state: &UrbanNetworkState, rng: &mut impl Rng) {
+        let (Some(dest), Some(mut path)) = (self.dest, self.path.take()) else {
+            return;
+        };
+        let network = &state.network;
+        let mut remaining = WALKING_SPEED * STEP_SECONDS;
+
+        while let Some(next) = path.first().and_then(|node| network.0.get_id(*node)) {
+            let Some(loc) = self.loc.heading_to(next, network) else {
+                // The path no longer starts from here
+                return;
+            };
+            let left = network.edge_length(loc.from_node, loc.to_node) - loc.edge_dist;
+            if remaining < left {
+                self.loc = StreetNetworkPosition {
+                    edge_dist: loc.edge_dist + remaining,
+                    ..loc
+                };
+                self.path = Some(path);
+                return;
+            }
+            remaining -= left;
+            path.remove(0);
+
+            // Stand at the node, facing the next node or the destination's street
+            let ahead = match path.first().and_then(|node| network.0.get_id(*node)) {
+                Some(after) => after,
+                None if dest.from_node == next => dest.to_node,
+                None => dest.from_node,
+            };
+            self.loc = StreetNetworkPosition::new(next, ahead, 0.0);
+            self.arrive_at_node(next, state, rng);
+            if self.wait > 0.0 {
+                self.path = Some(path);
+                return;
+            }
+        }
 
-        // If agent has changed location, reassign to new location
-        if let Some(loc) = next_loc {
-            // Set own location to new location
-            self.loc = loc;
+        // On the destination's street, turning round if it lies behind
+        if self
+            .loc
+            .distance_along(&dest, network)
+            .is_some_and(|target| target < self.loc.edge_dist)
+        {
+            let Some(reversed) = self.loc.reversed(network) else {
+                return;
+            };
+            self.loc = reversed;
+        }
+        let Some(target) = self.loc.distance_along(&dest, network) else {
+            return;
+        };
+        if self.loc.edge_dist + remaining >= target {
+            self.loc.edge_dist = target;
+            self.dest = None;
+        } else {
+            self.loc.edge_dist += remaining;
+            self.path = Some(path);
         }
     }
 }
 
 impl Agent for PedAgent {
     fn step(&mut self, state: &mut dyn State) {
-        println!("Agent {} on step {}", self.id, "???");
         let state = state
             .as_any_mut()
             .downcast_mut::<UrbanNetworkState>()
             .unwrap();
+        println!("Agent {} on step {}", self.id, state.step);
+
+        // Agents held at a crossing stay put until their wait runs out
+        if self.wait > 0.0 {
+            self.wait = (self.wait - STEP_SECONDS).max(0.0);
+            return;
+        }
 
         // // Check and see if agent can/will move; if so, update location
 
-        match self.dest {
-            Some(destination) => {}
-            None => {}
+        let mut rng = ThreadRng::default();
+        if self.dest.is_some() && self.path.is_none() {
+            self.plan_path(state, &mut rng);
         }
-        // self.update_network_loc(state);
+        self.update_network_loc(state, &mut rng);
     }
 }
 
 impl Eq for PedAgent {}
 
+impl Hash for PedAgent {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.id.hash(state);
+    }
+}
+
 impl Location2D<Real2D> for PedAgent {
     fn get_location(self) -> Real2D {
         todo!()
@@ -91,3 +199,81 @@ impl Display for PedAgent {
         f.write_str(rep.as_str())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use krabmaga::engine::fields::field::Field;
+    use krabmaga::engine::fields::network::{EdgeOptions, Network};
+    use krabmaga::rand::rngs::StdRng;
+    use krabmaga::rand::SeedableRng;
+
+    use crate::model::urban_network::{
+        IntersectionDelays, NodeControl, StreetEdgeLabel, StreetNetwork,
+    };
+
+    use super::*;
+
+    #[test]
+    fn agents_wait_out_the_signal_at_a_node_they_reach() {
+        let mut network = Network::new(true);
+        let a = StreetNode::new(1, Real2D { x: 0.0, y: 0.0 });
+        let b = StreetNode {
+            control: NodeControl::TrafficSignals,
+            ..StreetNode::new(2, Real2D { x: 10.0, y: 0.0 })
+        };
+        let c = StreetNode::new(3, Real2D { x: 20.0, y: 0.0 });
+        for node in [a, b, c] {
+            network.add_node(node);
+        }
+        for (u, v, way_id) in [(a, b, 1), (b, a, 1), (b, c, 2), (c, b, 2)] {
+            let label = StreetEdgeLabel::new(10.0, way_id, 0);
+            network.add_edge(u, v, EdgeOptions::WeightedLabeled(label, 10.0));
+        }
+        network.lazy_update();
+        let ids = |node: StreetNode| network.nodes2id[network.read].borrow()[&node];
+        let (a_id, b_id, c_id) = (ids(a), ids(b), ids(c));
+
+        // No walk phase, so every agent reaching the signal has to wait
+        let intersection_delays = IntersectionDelays {
+            walk_phase: 0.0,
+            ..IntersectionDelays::default()
+        };
+        let mut state = UrbanNetworkState {
+            step: 0,
+            network: StreetNetwork(network),
+            discretization: 1.0,
+            toroidal: false,
+            dim: (20.0, 0.0),
+            projection: None,
+            zones: None,
+            import_report: None,
+            intersection_delays,
+            route_metric: RouteMetric::default(),
+            route_choice: RouteChoice::default(),
+            hierarchy: None,
+            num_agents: 1,
+        };
+        let expected_wait = state
+            .intersection_delays
+            .sample_delay(NodeControl::TrafficSignals, &mut StdRng::seed_from_u64(7));
+        assert!(expected_wait > 0.0);
+
+        let mut agent = PedAgent::new(0, StreetNetworkPosition::new(a_id, b_id, 9.0));
+        agent.dest = Some(StreetNetworkPosition::new(b_id, c_id, 5.0));
+        agent.path = Some(vec![b]);
+        agent.update_network_loc(&state, &mut StdRng::seed_from_u64(7));
+        let at_signal = StreetNetworkPosition::new(b_id, c_id, 0.0);
+        assert_eq!(agent.loc, at_signal);
+        assert_eq!(agent.wait, expected_wait);
+
+        for _ in 0..(expected_wait / STEP_SECONDS).ceil() as usize {
+            agent.step(&mut state);
+            assert_eq!(agent.loc, at_signal);
+        }
+        agent.step(&mut state);
+        assert_eq!(
+            agent.loc,
+            StreetNetworkPosition::new(b_id, c_id, WALKING_SPEED * STEP_SECONDS)
+        );
+    }
+}
@@ -1 +1,281 @@
+use std::fmt::Display;
 
+use serde::{Deserialize, Serialize};
+
+use crate::model::urban_network::BoundaryError;
+
+/// How the importer treats recoverable problems in an extract (missing node references, degenerate
+/// ways).
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum ErrorPolicy {
+    /// Abort the import with the first problem found
+    #[default]
+    Fail,
+    /// Skip the offending way or segment, recording the problem for later inspection
+    SkipAndReport,
+}
+
+/// A problem with the contents of an OSM extract.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum ImportError {
+    /// A way refers to a node that is not in the extract, typically because the extract was
+    /// truncated at its boundary
+    MissingNodeRef { way_id: i64, node_id: i64 },
+    /// A way that cannot form any street segment
+    DegenerateWay { way_id: i64, reason: String },
+    /// A way ID or segment index too large to pack into a segment ID
+    SegmentIdOverflow { way_id: i64, segment: u32 },
+    /// No walkable ways or nodes were found
+    EmptyExtract,
+}
+
+impl Display for ImportError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ImportError::MissingNodeRef { way_id, node_id } => {
+                write!(f, "Way {} references missing node {}", way_id, node_id)
+            }
+            ImportError::DegenerateWay { way_id, reason } => {
+                write!(f, "Way {} is degenerate: {}", way_id, reason)
+            }
+            ImportError::SegmentIdOverflow { way_id, segment } => {
+                write!(
+                    f,
+                    "Way {} segment {} has no room in a segment ID",
+                    way_id, segment
+                )
+            }
+            ImportError::EmptyExtract => write!(f, "Extract contains no walkable streets"),
+        }
+    }
+}
+
+impl std::error::Error for ImportError {}
+
+/// A problem reading an OSM XML file.
+#[derive(Debug)]
+pub enum OsmXmlError {
+    Io(std::io::Error),
+    Xml(xml::reader::Error),
+    /// An element is missing a required attribute, or has one that cannot be parsed
+    InvalidElement {
+        line: u64,
+        message: String,
+    },
+}
+
+impl Display for OsmXmlError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            OsmXmlError::Io(e) => write!(f, "Unable to read OSM XML file: {}", e),
+            OsmXmlError::Xml(e) => write!(f, "Malformed OSM XML: {}", e),
+            OsmXmlError::InvalidElement { line, message } => {
+                write!(f, "Invalid OSM XML on line {}: {}", line, message)
+            }
+        }
+    }
+}
+
+impl std::error::Error for OsmXmlError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            OsmXmlError::Io(e) => Some(e),
+            OsmXmlError::Xml(e) => Some(e),
+            OsmXmlError::InvalidElement { .. } => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for OsmXmlError {
+    fn from(e: std::io::Error) -> Self {
+        OsmXmlError::Io(e)
+    }
+}
+
+impl From<xml::reader::Error> for OsmXmlError {
+    fn from(e: xml::reader::Error) -> Self {
+        OsmXmlError::Xml(e)
+    }
+}
+
+/// A problem reading or writing a GraphML street graph.
+#[derive(Debug)]
+pub enum GraphMlError {
+    Io(std::io::Error),
+    Xml(xml::reader::Error),
+    Write(xml::writer::Error),
+    /// An element is missing a required attribute
+    InvalidElement {
+        line: u64,
+        message: String,
+    },
+    /// Nodes or edges lack the attributes needed to build a street network
+    InvalidGraph(String),
+    /// The graph's coordinates are in a projected CRS rather than lon/lat
+    Projected(String),
+}
+
+impl Display for GraphMlError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            GraphMlError::Io(e) => write!(f, "Unable to access GraphML file: {}", e),
+            GraphMlError::Xml(e) => write!(f, "Malformed GraphML: {}", e),
+            GraphMlError::Write(e) => write!(f, "Unable to write GraphML: {}", e),
+            GraphMlError::InvalidElement { line, message } => {
+                write!(f, "Invalid GraphML on line {}: {}", line, message)
+            }
+            GraphMlError::InvalidGraph(message) => write!(f, "Invalid street graph: {}", message),
+            GraphMlError::Projected(crs) => write!(
+                f,
+                "Graph is projected to {}; save it unprojected (EPSG:4326) instead",
+                crs
+            ),
+        }
+    }
+}
+
+impl std::error::Error for GraphMlError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            GraphMlError::Io(e) => Some(e),
+            GraphMlError::Xml(e) => Some(e),
+            GraphMlError::Write(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for GraphMlError {
+    fn from(e: std::io::Error) -> Self {
+        GraphMlError::Io(e)
+    }
+}
+
+impl From<xml::reader::Error> for GraphMlError {
+    fn from(e: xml::reader::Error) -> Self {
+        GraphMlError::Xml(e)
+    }
+}
+
+impl From<xml::writer::Error> for GraphMlError {
+    fn from(e: xml::writer::Error) -> Self {
+        GraphMlError::Write(e)
+    }
+}
+
+#[derive(Debug)]
+pub enum StreetNetworkError {
+    Io(std::io::Error),
+    Parse(osmpbf::Error),
+    Xml(OsmXmlError),
+    GeoJson(Box<geojson::Error>),
+    GraphMl(GraphMlError),
+    Import(ImportError),
+    Boundary(BoundaryError),
+}
+
+impl Display for StreetNetworkError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            StreetNetworkError::Io(e) => write!(f, "Unable to read street data: {}", e),
+            StreetNetworkError::Parse(e) => write!(f, "Unable to parse OSM extract: {}", e),
+            StreetNetworkError::Xml(e) => write!(f, "{}", e),
+            StreetNetworkError::GeoJson(e) => write!(f, "Invalid GeoJSON street data: {}", e),
+            StreetNetworkError::GraphMl(e) => write!(f, "{}", e),
+            StreetNetworkError::Import(e) => write!(f, "Unable to import OSM extract: {}", e),
+            StreetNetworkError::Boundary(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for StreetNetworkError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            StreetNetworkError::Io(e) => Some(e),
+            StreetNetworkError::Parse(e) => Some(e),
+            StreetNetworkError::Xml(e) => Some(e),
+            StreetNetworkError::GeoJson(e) => Some(e.as_ref()),
+            StreetNetworkError::GraphMl(e) => Some(e),
+            StreetNetworkError::Import(e) => Some(e),
+            StreetNetworkError::Boundary(e) => Some(e),
+        }
+    }
+}
+
+impl From<std::io::Error> for StreetNetworkError {
+    fn from(e: std::io::Error) -> Self {
+        StreetNetworkError::Io(e)
+    }
+}
+
+impl From<geojson::Error> for StreetNetworkError {
+    fn from(e: geojson::Error) -> Self {
+        StreetNetworkError::GeoJson(Box::new(e))
+    }
+}
+
+impl From<GraphMlError> for StreetNetworkError {
+    fn from(e: GraphMlError) -> Self {
+        StreetNetworkError::GraphMl(e)
+    }
+}
+
+impl From<osmpbf::Error> for StreetNetworkError {
+    fn from(e: osmpbf::Error) -> Self {
+        StreetNetworkError::Parse(e)
+    }
+}
+
+impl From<OsmXmlError> for StreetNetworkError {
+    fn from(e: OsmXmlError) -> Self {
+        StreetNetworkError::Xml(e)
+    }
+}
+
+impl From<ImportError> for StreetNetworkError {
+    fn from(e: ImportError) -> Self {
+        StreetNetworkError::Import(e)
+    }
+}
+
+impl From<BoundaryError> for StreetNetworkError {
+    fn from(e: BoundaryError) -> Self {
+        StreetNetworkError::Boundary(e)
+    }
+}
+
+#[derive(Debug)]
+pub enum UrbanNetworkStateError {
+    OSMLoadingError(StreetNetworkError),
+    ZoneLoadingError(BoundaryError),
+    /// Zones are defined in lon/lat, so they can only be loaded onto a georeferenced network
+    MissingProjection,
+}
+
+impl Display for UrbanNetworkStateError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            UrbanNetworkStateError::OSMLoadingError(e) => write!(f, "{}", e),
+            UrbanNetworkStateError::ZoneLoadingError(e) => write!(f, "Unable to load zones: {}", e),
+            UrbanNetworkStateError::MissingProjection => {
+                write!(f, "Network has no projection to georeference zones against")
+            }
+        }
+    }
+}
+
+impl std::error::Error for UrbanNetworkStateError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            UrbanNetworkStateError::OSMLoadingError(e) => Some(e),
+            UrbanNetworkStateError::ZoneLoadingError(e) => Some(e),
+            UrbanNetworkStateError::MissingProjection => None,
+        }
+    }
+}
+
+impl From<StreetNetworkError> for UrbanNetworkStateError {
+    fn from(e: StreetNetworkError) -> Self {
+        UrbanNetworkStateError::OSMLoadingError(e)
+    }
+}
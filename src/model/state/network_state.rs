@@ -1,29 +1,22 @@
 use crate::model::agent::PedAgent;
-use crate::model::urban_network::node::StreetNode;
+pub use crate::model::error::UrbanNetworkStateError;
 use crate::model::urban_network::{
-    street_network_from_osm, StreetEdgeLabel, StreetNetwork, StreetNetworkError,
-    StreetNetworkPosition, StreetNetworkSpec,
+    cached_street_network_from_osm, street_network_from_geojson, street_network_from_graphml,
+    ContractionHierarchy, GeoJsonNetworkOptions, ImportOptions, ImportReport, IntersectionDelays,
+    LocalProjection, Route, RouteChoice, RouteMetric, RoutingAlgorithm, StreetNetwork,
+    StreetNetworkPosition, StreetNetworkSpec, SyntheticLayout, Zone, ZoneLayer,
 };
-use crate::INIT_EDGES;
 use krabmaga::engine::fields::field::Field;
-use krabmaga::engine::fields::field_2d::Field2D;
-use krabmaga::engine::fields::network::{Edge, Network};
-use krabmaga::engine::location::Real2D;
 use krabmaga::engine::schedule::Schedule;
 use krabmaga::engine::state::State;
-use krabmaga::rand::{distributions::Uniform, prelude::SliceRandom, rngs::ThreadRng, Rng};
-use krabmaga::Distribution;
-use krabmaga::{__Deref, rand};
-use serde::de::value;
+use krabmaga::rand::rngs::ThreadRng;
+use krabmaga::rand::Rng;
 use std::any::Any;
 use std::collections::HashMap;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 
-#[derive(Debug)]
-pub enum UrbanNetworkStateError {
-    OSMLoadingError(StreetNetworkError),
-    OsmPbf(osmpbf::Error),
-}
+/// Random destinations tried for each agent before giving up on finding one it can reach.
+const DESTINATION_CANDIDATES: usize = 8;
 
 pub struct UrbanNetworkState {
     pub step: u64,
@@ -33,81 +26,230 @@ pub struct UrbanNetworkState {
     pub discretization: f32,
     pub toroidal: bool,
     pub dim: (f32, f32),
+    /// Projection between WGS84 and the network's metric coordinates, if built from real-world data
+    pub projection: Option<LocalProjection>,
+    /// Named areas used to aggregate results by neighbourhood, census block, etc.
+    pub zones: Option<ZoneLayer>,
+    /// What was loaded from the source extract, for networks imported from OSM
+    pub import_report: Option<ImportReport>,
+    /// Waits agents face at crossings and signals
+    pub intersection_delays: IntersectionDelays,
+    /// What agents' routes minimise
+    pub route_metric: RouteMetric,
+    /// Route choice given to agents, with street preferences varied from one agent to the next;
+    /// shortest paths unless set otherwise before the simulation starts
+    pub route_choice: RouteChoice,
+    /// Contraction hierarchy over `network`, used for shortest routes when present
+    pub hierarchy: Option<ContractionHierarchy>,
     //pub num_nodes: u32,
     pub num_agents: u32,
     //pub rng: StdRng,
 }
 
 impl UrbanNetworkState {
+    /// Build the state on a generated street network of the given layout, filling `dim` metres.
     pub fn new(
-        num_nodes: i64,
+        layout: &SyntheticLayout,
         dim: (f32, f32),
         num_agents: u32,
         d: f32,
         t: bool,
     ) -> UrbanNetworkState {
-        let mut state = UrbanNetworkState {
+        let mut rng = ThreadRng::default();
+        UrbanNetworkState {
             step: 0,
             //field: Field2D::new(dim.0, dim.1, d, t),
-            network: StreetNetwork(Network::new(false)),
+            network: StreetNetwork::synthetic(layout, dim, &mut rng),
             discretization: d,
             toroidal: t,
             dim,
+            projection: None,
+            zones: None,
+            import_report: None,
+            intersection_delays: IntersectionDelays::default(),
+            route_metric: RouteMetric::default(),
+            route_choice: RouteChoice::default(),
+            hierarchy: None,
             //num_nodes,
             num_agents,
             //rng: StdRng::from_entropy(),
-        };
-
-        // Initialize Nodes and Network
-        let mut rng = ThreadRng::default(); //&mut state.rng;
-        let mut node_set = Vec::new();
-
-        for node_id in 0_i64..num_nodes {
-            let r1: f32 = rng.gen();
-            let r2: f32 = rng.gen();
-
-            let node = StreetNode::new(
-                node_id,
-                Real2D {
-                    x: dim.0 * r1,
-                    y: dim.1 * r2,
-                },
-            );
-            //self.field1.set_object_location(node, node.loc);
-            node_set.push(node);
-            state.network.0.add_node(node);
-            //schedule.schedule_repeating(Box::new(PedAgent), 0.0, 0);
         }
-        state
-            .network
-            .0
-            .preferential_attachment_BA(&node_set, INIT_EDGES);
-
-        state
     }
 
+    /// Build the state from one or more OSM extracts, PBF or XML, merged into a single network.
     pub fn from_osm_file(
+        filepaths: &[PathBuf],
+        num_agents: u32,
+        discretization: f32,
+        toroidal: bool,
+        import_options: &ImportOptions,
+    ) -> Result<UrbanNetworkState, UrbanNetworkStateError> {
+        let network_spec = cached_street_network_from_osm(filepaths, import_options)?;
+        Ok(Self::from_spec(
+            network_spec,
+            num_agents,
+            discretization,
+            toroidal,
+            import_options,
+        ))
+    }
+
+    /// Build the state from the line features of a GeoJSON file, such as a sidewalk dataset.
+    pub fn from_geojson_file(
         filepath: &Path,
+        geojson_options: &GeoJsonNetworkOptions,
         num_agents: u32,
         discretization: f32,
         toroidal: bool,
+        import_options: &ImportOptions,
     ) -> Result<UrbanNetworkState, UrbanNetworkStateError> {
-        match street_network_from_osm(filepath) {
-            Ok(network_spec) => {
-                let StreetNetworkSpec { network, dim } = network_spec;
-                return Ok(UrbanNetworkState {
-                    step: 0,
-                    //field: Field2D::new(dim.0, dim.1, discretization, toroidal),
-                    network,
-                    discretization,
-                    toroidal,
-                    dim,
-                    num_agents,
-                    //rng: StdRng::from_entropy(),
-                });
-            }
-            Err(e) => return Err(UrbanNetworkStateError::OSMLoadingError(e)),
-        };
+        let network_spec = street_network_from_geojson(filepath, geojson_options, import_options)?;
+        Ok(Self::from_spec(
+            network_spec,
+            num_agents,
+            discretization,
+            toroidal,
+            import_options,
+        ))
+    }
+
+    /// Build the state from a street graph saved by OSMnx.
+    pub fn from_graphml_file(
+        filepath: &Path,
+        num_agents: u32,
+        discretization: f32,
+        toroidal: bool,
+        import_options: &ImportOptions,
+    ) -> Result<UrbanNetworkState, UrbanNetworkStateError> {
+        let network_spec = street_network_from_graphml(filepath, import_options)?;
+        Ok(Self::from_spec(
+            network_spec,
+            num_agents,
+            discretization,
+            toroidal,
+            import_options,
+        ))
+    }
+
+    fn from_spec(
+        network_spec: StreetNetworkSpec,
+        num_agents: u32,
+        discretization: f32,
+        toroidal: bool,
+        import_options: &ImportOptions,
+    ) -> UrbanNetworkState {
+        let StreetNetworkSpec {
+            network,
+            dim,
+            projection,
+            report,
+            hierarchy,
+            ..
+        } = network_spec;
+        UrbanNetworkState {
+            step: 0,
+            //field: Field2D::new(dim.0, dim.1, discretization, toroidal),
+            network,
+            discretization,
+            toroidal,
+            dim,
+            projection: Some(projection),
+            zones: None,
+            import_report: Some(report),
+            intersection_delays: import_options.intersection_delays.clone(),
+            route_metric: RouteMetric::default(),
+            route_choice: RouteChoice::default(),
+            hierarchy,
+            num_agents,
+            //rng: StdRng::from_entropy(),
+        }
+    }
+
+    /// Load a GeoJSON zone layer, naming each zone by the given feature property.
+    pub fn load_zones(
+        &mut self,
+        filepath: &Path,
+        name_property: &str,
+    ) -> Result<(), UrbanNetworkStateError> {
+        let projection = self
+            .projection
+            .ok_or(UrbanNetworkStateError::MissingProjection)?;
+        let zones = ZoneLayer::from_geojson(filepath, name_property, &projection)
+            .map_err(UrbanNetworkStateError::ZoneLoadingError)?;
+        self.zones = Some(zones);
+        Ok(())
+    }
+
+    /// Shortest routes from one position to each of several others, sharing the search from the
+    /// start when a contraction hierarchy was built.
+    pub fn shortest_routes(
+        &self,
+        from: &StreetNetworkPosition,
+        targets: &[StreetNetworkPosition],
+    ) -> Vec<Option<Route>> {
+        match &self.hierarchy {
+            Some(hierarchy) => hierarchy.one_to_many(&self.network, from, targets),
+            None => targets
+                .iter()
+                .map(|to| self.shortest_route(from, to))
+                .collect(),
+        }
+    }
+
+    /// The zone a position on the street network falls in, if zones are loaded.
+    pub fn zone_of(&self, position: &StreetNetworkPosition) -> Option<&Zone> {
+        self.zones
+            .as_ref()
+            .and_then(|zones| zones.zone_of(position, &self.network))
+    }
+
+    /// Shortest route between two positions, through the contraction hierarchy if one was built
+    /// and otherwise by A* search counting the expected waits at crossings and signals.
+    pub fn shortest_route(
+        &self,
+        from: &StreetNetworkPosition,
+        to: &StreetNetworkPosition,
+    ) -> Option<Route> {
+        if let Some(hierarchy) = &self.hierarchy {
+            return hierarchy.route(&self.network, from, to);
+        }
+        self.network.route(
+            from,
+            to,
+            RoutingAlgorithm::AStar {
+                max_scale: self.max_scale_factor(),
+            },
+            Some(&self.intersection_delays),
+        )
+    }
+
+    /// Route between two positions drawn by a route choice model, counting the expected waits at
+    /// crossings and signals. Plain shortest paths go through `shortest_route`.
+    pub fn choose_route(
+        &self,
+        choice: &RouteChoice,
+        from: &StreetNetworkPosition,
+        to: &StreetNetworkPosition,
+        rng: &mut impl Rng,
+    ) -> Option<Route> {
+        if choice.is_shortest() {
+            return self.shortest_route(from, to);
+        }
+        choice.choose(
+            &self.network,
+            from,
+            to,
+            Some(&self.intersection_delays),
+            self.max_scale_factor(),
+            rng,
+        )
+    }
+
+    /// Largest factor by which the projection stretches distances across the network, which
+    /// directed searches shrink their straight-line estimates by.
+    fn max_scale_factor(&self) -> f32 {
+        self.projection
+            .map_or(1.0, |projection| projection.max_scale_factor(self.dim.0))
     }
 }
 
@@ -150,13 +292,37 @@ impl State for UrbanNetworkState {
             .unwrap_or_default();
 
         // Place agents at a random point on a random street segment
+        let mut agents_per_zone: HashMap<String, u32> = HashMap::new();
         for agent_id in 0..self.num_agents {
             let starting_loc = StreetNetworkPosition::rand_from_edge_list(&edge_list, &mut rng);
+            if let Some(zone) = self.zone_of(&starting_loc) {
+                *agents_per_zone.entry(zone.name.clone()).or_default() += 1;
+            }
 
-            let agent = PedAgent::new(agent_id, starting_loc);
+            // Head for the first of a few random destinations that can be reached from the start,
+            // as not every part of a network kept whole need be
+            let mut agent = PedAgent::new(agent_id, starting_loc);
+            let candidates: Vec<StreetNetworkPosition> = (0..DESTINATION_CANDIDATES)
+                .map(|_| StreetNetworkPosition::rand_from_edge_list(&edge_list, &mut rng))
+                .collect();
+            // Preferences vary from agent to agent, spreading route choices as observed ones are;
+            // distance-only weights stay so
+            agent.route_metric = self.route_metric;
+            agent.route_choice = RouteChoice {
+                weights: self.route_choice.weights.varied(0.5, &mut rng),
+                ..self.route_choice
+            };
+            agent.dest = self
+                .shortest_routes(&starting_loc, &candidates)
+                .into_iter()
+                .zip(candidates)
+                .find_map(|(route, dest)| route.map(|_| dest));
             print!("{:?}", &agent);
             schedule.schedule_repeating(Box::new(agent), 0.0, 0);
         }
+        for (zone, count) in agents_per_zone {
+            println!("{} agents start in {}", count, zone);
+        }
     }
 
     fn update(&mut self, step: u64) {
@@ -0,0 +1,338 @@
+use std::cmp::Ordering;
+use std::collections::{BinaryHeap, HashMap, HashSet};
+
+use super::routing::PartialLeg;
+use super::{IntersectionDelays, Route, StreetEdgeLabel, StreetNetwork, StreetNetworkPosition};
+
+/// What a route minimises: distance, or turning as space-syntax studies find pedestrians do.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub enum RouteMetric {
+    /// Distance walked, weighted by street attributes as the agent's route choice says
+    #[default]
+    Distance,
+    /// Cumulative change of direction at intersections, in degrees
+    LeastAngle,
+    /// Number of turns sharper than `threshold` degrees
+    FewestTurns { threshold: f32 },
+}
+
+impl RouteMetric {
+    /// Cost of changing direction by `angle` degrees at an intersection.
+    pub fn turn_cost(&self, angle: f32) -> f32 {
+        match *self {
+            RouteMetric::Distance => 0.0,
+            RouteMetric::LeastAngle => angle,
+            RouteMetric::FewestTurns { threshold } => {
+                if angle > threshold {
+                    1.0
+                } else {
+                    0.0
+                }
+            }
+        }
+    }
+}
+
+/// Heap entry for a directed edge, popping the lowest cost first and the shortest of equal cost.
+#[derive(Copy, Clone, Debug, PartialEq)]
+struct TurnEntry {
+    cost: f32,
+    /// Distance walked, plus the distance that could be walked in the time spent waiting at nodes
+    length: f32,
+    edge: (u32, u32),
+}
+
+impl Eq for TurnEntry {}
+
+impl Ord for TurnEntry {
+    fn cmp(&self, other: &Self) -> Ordering {
+        other
+            .cost
+            .total_cmp(&self.cost)
+            .then_with(|| other.length.total_cmp(&self.length))
+            .then_with(|| other.edge.cmp(&self.edge))
+    }
+}
+
+impl PartialOrd for TurnEntry {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl StreetNetwork {
+    /// Change of direction, in degrees from 0 to 180, when walking from node `u` through `v` to
+    /// `w`. Directions are taken from the last segment of the street walked in on and the first
+    /// segment of the street walked out on where their geometry has them, and from the straight
+    /// line between the streets' end nodes otherwise.
+    pub fn turn_angle(&self, u: u32, v: u32, w: u32) -> f32 {
+        let (Some(u_node), Some(v_node), Some(w_node)) = (
+            self.0.get_object(u),
+            self.0.get_object(v),
+            self.0.get_object(w),
+        ) else {
+            return 0.0;
+        };
+        let (a_start, a_end) = match self.edge_label(u, v) {
+            Some(label) if label.geometry.len() >= 2 => {
+                let n = label.geometry.len();
+                (label.geometry[n - 2], label.geometry[n - 1])
+            }
+            _ => (u_node.loc, v_node.loc),
+        };
+        let (b_start, b_end) = match self.edge_label(v, w) {
+            Some(label) if label.geometry.len() >= 2 => (label.geometry[0], label.geometry[1]),
+            _ => (v_node.loc, w_node.loc),
+        };
+        let (ax, ay) = (a_end.x - a_start.x, a_end.y - a_start.y);
+        let (bx, by) = (b_end.x - b_start.x, b_end.y - b_start.y);
+        if (ax == 0.0 && ay == 0.0) || (bx == 0.0 && by == 0.0) {
+            return 0.0;
+        }
+        (ax * by - ay * bx)
+            .atan2(ax * bx + ay * by)
+            .abs()
+            .to_degrees()
+    }
+
+    /// Route between two positions minimising the summed cost of the turns made at intersections,
+    /// with ties broken by distance, counting the expected waits at nodes as `route_by_cost` does
+    /// when `delays` are given. Turn costs depend on the street walked in on as well as the one
+    /// walked out on, so the search runs over directed edges rather than nodes. Leaving the start
+    /// position or reaching the destination from a node costs no turn, and a walk along a single
+    /// street makes none.
+    pub fn route_by_turn_cost(
+        &self,
+        from: &StreetNetworkPosition,
+        to: &StreetNetworkPosition,
+        turn_cost: &dyn Fn(f32) -> f32,
+        delays: Option<&IntersectionDelays>,
+    ) -> Option<Route> {
+        if let Some(direct) = self.direct_route(from, to, &|_| 0.0) {
+            return Some(direct);
+        }
+        let distance = |label: &StreetEdgeLabel| label.len;
+        let arrivals = self.arrivals(to, &distance);
+
+        // Search states are directed edges, each standing for having just walked it
+        let mut costs: HashMap<(u32, u32), (f32, f32)> = HashMap::new();
+        let mut previous: HashMap<(u32, u32), Result<(u32, u32), PartialLeg>> = HashMap::new();
+        // Departures from a node of the start position, which set no direction to turn from
+        let mut unheaded: HashSet<(u32, u32)> = HashSet::new();
+        let mut queue = BinaryHeap::new();
+        for leg in self.departures(from, &distance) {
+            if costs.get(&leg.edge).is_none_or(|(_, l)| leg.length < *l) {
+                costs.insert(leg.edge, (0.0, leg.length));
+                previous.insert(leg.edge, Err(leg));
+                if leg.length == 0.0 {
+                    unheaded.insert(leg.edge);
+                }
+                queue.push(TurnEntry {
+                    cost: 0.0,
+                    length: leg.length,
+                    edge: leg.edge,
+                });
+            }
+        }
+        let turn = |(u, v): (u32, u32), w: u32| {
+            if unheaded.contains(&(u, v)) {
+                0.0
+            } else {
+                turn_cost(self.turn_angle(u, v, w))
+            }
+        };
+
+        let mut best: Option<(TurnEntry, PartialLeg)> = None;
+        let network = &self.0;
+        let edges = network.edges[network.read].borrow();
+        while let Some(entry) = queue.pop() {
+            if best.is_some_and(|(best, _)| (best.cost, best.length) <= (entry.cost, entry.length))
+            {
+                break;
+            }
+            if costs[&entry.edge] != (entry.cost, entry.length) {
+                // Stale entry for an edge since reached more cheaply
+                continue;
+            }
+            let (_, node) = entry.edge;
+            let leaving = entry.length + self.delay_cost(node, delays);
+
+            for leg in arrivals.iter().filter(|leg| leg.node == node) {
+                let total = TurnEntry {
+                    cost: entry.cost
+                        + if leg.length > 0.0 {
+                            turn(entry.edge, leg.edge.1)
+                        } else {
+                            0.0
+                        },
+                    length: leaving + leg.length,
+                    edge: entry.edge,
+                };
+                if best
+                    .is_none_or(|(best, _)| (total.cost, total.length) < (best.cost, best.length))
+                {
+                    best = Some((total, *leg));
+                }
+            }
+
+            for edge in edges.get(&node).map(Vec::as_slice).unwrap_or_default() {
+                let Some(label) = edge.label.as_ref() else {
+                    continue;
+                };
+                let next = TurnEntry {
+                    cost: entry.cost + turn(entry.edge, edge.v),
+                    length: leaving + label.len,
+                    edge: (node, edge.v),
+                };
+                if costs
+                    .get(&next.edge)
+                    .is_none_or(|(c, l)| (next.cost, next.length) < (*c, *l))
+                {
+                    costs.insert(next.edge, (next.cost, next.length));
+                    previous.insert(next.edge, Ok(entry.edge));
+                    queue.push(next);
+                }
+            }
+        }
+
+        // Walk back from the edge the destination was reached from
+        let (total, arrival) = best?;
+        let mut walked = vec![total.edge];
+        let departure = loop {
+            match previous[walked.last().unwrap()] {
+                Ok(prev) => walked.push(prev),
+                Err(leg) => break leg,
+            }
+        };
+        walked.reverse();
+
+        let mut nodes = vec![departure.node];
+        nodes.extend(walked[1..].iter().map(|(_, v)| *v));
+        let mut route_edges = Vec::with_capacity(walked.len() + 1);
+        if departure.length > 0.0 {
+            route_edges.push(departure.edge);
+        }
+        route_edges.extend_from_slice(&walked[1..]);
+        if arrival.length > 0.0 {
+            route_edges.push(arrival.edge);
+        }
+        let length = departure.length
+            + walked[1..]
+                .iter()
+                .filter_map(|(u, v)| self.edge_label(*u, *v))
+                .map(|label| label.len)
+                .sum::<f32>()
+            + arrival.length;
+        Some(Route {
+            edges: route_edges,
+            nodes,
+            length,
+            cost: total.cost,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use krabmaga::engine::fields::{field::Field, network::Network};
+    use krabmaga::engine::location::Real2D;
+
+    use krabmaga::rand::{rngs::StdRng, SeedableRng};
+
+    use super::*;
+    use crate::model::urban_network::test_support::{
+        assert_close, assert_connected, organic_network, random_pairs,
+    };
+    use crate::model::urban_network::{network::edge_options, RoutingAlgorithm, StreetNode};
+
+    /// Three nodes at the corners of a right angle, joined by a straight street from `a` to `b`
+    /// and a street from `b` to `c` that leaves `b` heading straight on before curving round,
+    /// with the network IDs of `a`, `b` and `c`.
+    fn bent_corner() -> (StreetNetwork, [u32; 3]) {
+        let mut network = Network::new(true);
+        let a = StreetNode::new(1, Real2D { x: 0.0, y: 0.0 });
+        let b = StreetNode::new(2, Real2D { x: 100.0, y: 0.0 });
+        let c = StreetNode::new(3, Real2D { x: 100.0, y: 100.0 });
+        for node in [a, b, c] {
+            network.add_node(node);
+        }
+        let straight = StreetEdgeLabel::new(100.0, 1, 0);
+        let curved = StreetEdgeLabel {
+            geometry: vec![
+                b.loc,
+                Real2D { x: 150.0, y: 0.0 },
+                Real2D { x: 150.0, y: 100.0 },
+                c.loc,
+            ],
+            ..StreetEdgeLabel::new(200.0, 2, 0)
+        };
+        for (u, v, label) in [(a, b, straight), (b, c, curved)] {
+            let reverse_label = label.reversed();
+            let len = label.len;
+            network.add_edge(u, v, edge_options(Some(label), Some(len)));
+            network.add_edge(v, u, edge_options(Some(reverse_label), Some(len)));
+        }
+        network.lazy_update();
+        let network = StreetNetwork(network);
+        let ids = network.node_ids();
+        (network, [ids[0], ids[1], ids[2]])
+    }
+
+    #[test]
+    fn turn_angle_follows_street_geometry() {
+        let (network, [a, b, c]) = bent_corner();
+        // Walking on from the straight street into the curved one, which leaves heading east
+        assert!(network.turn_angle(a, b, c).abs() < 1e-3);
+        // Walking back, the curved street arrives heading west, and the straight one carries on
+        assert!(network.turn_angle(c, b, a).abs() < 1e-3);
+    }
+
+    #[test]
+    fn turn_angle_falls_back_to_end_nodes() {
+        let (network, [a, b, c]) = bent_corner();
+        // The straight street has no geometry, and nodes not joined by a street are taken as joined
+        // by a straight line
+        assert!((network.turn_angle(b, a, c) - 135.0).abs() < 1e-3);
+    }
+
+    /// Summed cost of the turns a route makes where one of its edges follows on from another.
+    fn turns_along(network: &StreetNetwork, route: &Route, turn_cost: &dyn Fn(f32) -> f32) -> f32 {
+        route
+            .edges
+            .windows(2)
+            .map(|pair| turn_cost(network.turn_angle(pair[0].0, pair[0].1, pair[1].1)))
+            .sum()
+    }
+
+    #[test]
+    fn routes_turn_no_more_than_the_shortest() {
+        let mut rng = StdRng::seed_from_u64(25);
+        let network = organic_network(&mut rng);
+        for metric in [
+            RouteMetric::LeastAngle,
+            RouteMetric::FewestTurns { threshold: 45.0 },
+        ] {
+            let turn_cost = |angle| metric.turn_cost(angle);
+            for (from, to) in random_pairs(&network, 30, &mut rng) {
+                let route = network
+                    .route_by_turn_cost(&from, &to, &turn_cost, None)
+                    .unwrap();
+                assert_connected(&route);
+                assert_close(route.cost, turns_along(&network, &route, &turn_cost));
+                let lengths: f32 = route
+                    .edges
+                    .iter()
+                    .filter_map(|(u, v)| network.edge_label(*u, *v))
+                    .map(|label| label.len)
+                    .sum();
+                assert!(route.length <= lengths + 1e-3);
+
+                let shortest = network
+                    .route(&from, &to, RoutingAlgorithm::Dijkstra, None)
+                    .unwrap();
+                assert!(route.cost <= turns_along(&network, &shortest, &turn_cost) + 1e-3);
+                assert!(route.length + 1e-3 >= shortest.length);
+            }
+        }
+    }
+}
@@ -0,0 +1,275 @@
+use std::{fmt::Display, fs, path::Path};
+
+use geo::{
+    line_intersection::{line_intersection, LineIntersection},
+    Contains, Coord, Geometry, Line, LineString, MultiPolygon, Point, Polygon,
+};
+use geojson::GeoJson;
+
+#[derive(Debug)]
+pub enum BoundaryError {
+    Io(std::io::Error),
+    /// Boxed, as `geojson::Error` can carry a whole JSON value
+    GeoJson(Box<geojson::Error>),
+    /// The file parsed, but contained no polygons
+    NoPolygons,
+    /// Malformed boundary file, with the 1-based line number where parsing failed
+    Parse {
+        line: usize,
+        message: String,
+    },
+}
+
+impl Display for BoundaryError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BoundaryError::Io(e) => write!(f, "Unable to read boundary file: {}", e),
+            BoundaryError::GeoJson(e) => write!(f, "Invalid GeoJSON boundary: {}", e),
+            BoundaryError::NoPolygons => write!(f, "Boundary file contains no polygons"),
+            BoundaryError::Parse { line, message } => {
+                write!(f, "Invalid boundary file at line {}: {}", line, message)
+            }
+        }
+    }
+}
+
+impl std::error::Error for BoundaryError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            BoundaryError::Io(e) => Some(e),
+            BoundaryError::GeoJson(e) => Some(e.as_ref()),
+            _ => None,
+        }
+    }
+}
+
+impl From<geojson::Error> for BoundaryError {
+    fn from(e: geojson::Error) -> Self {
+        BoundaryError::GeoJson(Box::new(e))
+    }
+}
+
+/// Read a study-area boundary, choosing the parser from the file extension: `.poly` files are read
+/// as Osmosis polygon filters, anything else as GeoJSON.
+pub fn read_boundary(filepath: &Path) -> Result<MultiPolygon<f64>, BoundaryError> {
+    match filepath.extension().and_then(|ext| ext.to_str()) {
+        Some("poly") => read_poly(filepath),
+        _ => read_geojson_boundary(filepath),
+    }
+}
+
+/// Read every Polygon and MultiPolygon in a GeoJSON file (bare geometry, feature or feature
+/// collection) as a single lon/lat `MultiPolygon`.
+pub fn read_geojson_boundary(filepath: &Path) -> Result<MultiPolygon<f64>, BoundaryError> {
+    let contents = fs::read_to_string(filepath).map_err(BoundaryError::Io)?;
+    let geojson = contents.parse::<GeoJson>().map_err(BoundaryError::from)?;
+    let geometry = Geometry::<f64>::try_from(geojson).map_err(BoundaryError::from)?;
+
+    let polygons = polygons_of(geometry);
+    if polygons.is_empty() {
+        return Err(BoundaryError::NoPolygons);
+    }
+    Ok(MultiPolygon::new(polygons))
+}
+
+/// Polygons contained in a geometry, descending into collections; other geometry types are ignored.
+pub(crate) fn polygons_of(geometry: Geometry<f64>) -> Vec<Polygon<f64>> {
+    match geometry {
+        Geometry::Polygon(p) => vec![p],
+        Geometry::MultiPolygon(mp) => mp.0,
+        Geometry::GeometryCollection(gc) => gc.into_iter().flat_map(polygons_of).collect(),
+        _ => vec![],
+    }
+}
+
+/// Read an Osmosis polygon filter (`.poly`) file as a lon/lat `MultiPolygon`.
+///
+/// Sections whose name starts with `!` are holes, and are subtracted from the outer ring that
+/// precedes them.
+pub fn read_poly(filepath: &Path) -> Result<MultiPolygon<f64>, BoundaryError> {
+    let contents = fs::read_to_string(filepath).map_err(BoundaryError::Io)?;
+    parse_poly(&contents)
+}
+
+pub fn parse_poly(contents: &str) -> Result<MultiPolygon<f64>, BoundaryError> {
+    let mut lines = contents
+        .lines()
+        .enumerate()
+        .map(|(i, l)| (i + 1, l.trim()))
+        .filter(|(_, l)| !l.is_empty());
+
+    // First line is the polygon name
+    lines.next().ok_or(BoundaryError::Parse {
+        line: 1,
+        message: "File is empty".to_string(),
+    })?;
+
+    let mut polygons: Vec<Polygon<f64>> = Vec::new();
+    loop {
+        let (section_line, section) = lines.next().ok_or(BoundaryError::Parse {
+            line: contents.lines().count(),
+            message: "Missing final END".to_string(),
+        })?;
+        if section == "END" {
+            break;
+        }
+
+        let mut ring: Vec<Coord<f64>> = Vec::new();
+        for (line_no, line) in lines.by_ref() {
+            if line == "END" {
+                break;
+            }
+            let mut values = line.split_whitespace().map(|v| v.parse::<f64>());
+            match (values.next(), values.next()) {
+                (Some(Ok(x)), Some(Ok(y))) => ring.push(Coord { x, y }),
+                _ => {
+                    return Err(BoundaryError::Parse {
+                        line: line_no,
+                        message: format!("Expected a coordinate pair, found '{}'", line),
+                    })
+                }
+            }
+        }
+
+        if ring.len() < 3 {
+            return Err(BoundaryError::Parse {
+                line: section_line,
+                message: format!("Ring '{}' has fewer than three points", section),
+            });
+        }
+
+        if section.starts_with('!') {
+            match polygons.last_mut() {
+                Some(outer) => outer.interiors_push(LineString::new(ring)),
+                None => {
+                    return Err(BoundaryError::Parse {
+                        line: section_line,
+                        message: format!("Hole '{}' has no enclosing ring", section),
+                    })
+                }
+            }
+        } else {
+            polygons.push(Polygon::new(LineString::new(ring), vec![]));
+        }
+    }
+
+    Ok(MultiPolygon::new(polygons))
+}
+
+/// Split the straight segment `a`-`b` where it crosses the boundary, returning the pieces that lie
+/// inside it as fractions `(t_start, t_end)` of the way from `a` to `b`.
+pub fn clip_segment(boundary: &MultiPolygon<f64>, a: Coord<f64>, b: Coord<f64>) -> Vec<(f64, f64)> {
+    let segment = Line::new(a, b);
+    let length = ((b.x - a.x).powi(2) + (b.y - a.y).powi(2)).sqrt();
+
+    let mut cuts = vec![0.0, 1.0];
+    if length > 0.0 {
+        let rings = boundary
+            .iter()
+            .flat_map(|p| std::iter::once(p.exterior()).chain(p.interiors()));
+        for ring in rings {
+            for edge in ring.lines() {
+                if let Some(LineIntersection::SinglePoint { intersection, .. }) =
+                    line_intersection(segment, edge)
+                {
+                    let t = ((intersection.x - a.x).powi(2) + (intersection.y - a.y).powi(2))
+                        .sqrt()
+                        / length;
+                    cuts.push(t.clamp(0.0, 1.0));
+                }
+            }
+        }
+    }
+    cuts.sort_by(|x, y| x.total_cmp(y));
+    cuts.dedup_by(|x, y| (*x - *y).abs() < 1e-12);
+
+    let inside =
+        |t: f64| boundary.contains(&Point::new(a.x + (b.x - a.x) * t, a.y + (b.y - a.y) * t));
+
+    let mut pieces: Vec<(f64, f64)> = Vec::new();
+    for pair in cuts.windows(2) {
+        let (start, end) = (pair[0], pair[1]);
+        if inside((start + end) / 2.0) {
+            // Merge with the previous piece if they touch
+            match pieces.last_mut() {
+                Some(last) if last.1 == start => last.1 = end,
+                _ => pieces.push((start, end)),
+            }
+        }
+    }
+    pieces
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SQUARE_WITH_HOLE: &str = "\
+study_area
+1
+    0.0E+00 0.0E+00
+    1.0E+01 0.0E+00
+    1.0E+01 1.0E+01
+    0.0E+00 1.0E+01
+END
+!hole
+    4.0 4.0
+    6.0 4.0
+    6.0 6.0
+    4.0 6.0
+END
+END
+";
+
+    #[test]
+    fn poly_files_parse_with_holes() {
+        let boundary = parse_poly(SQUARE_WITH_HOLE).unwrap();
+        assert_eq!(boundary.0.len(), 1);
+        assert_eq!(boundary.0[0].interiors().len(), 1);
+        assert!(boundary.contains(&Point::new(2.0, 2.0)));
+        assert!(!boundary.contains(&Point::new(5.0, 5.0)));
+        assert!(!boundary.contains(&Point::new(12.0, 2.0)));
+    }
+
+    #[test]
+    fn malformed_poly_files_report_the_offending_line() {
+        let line_of = |contents: &str| match parse_poly(contents) {
+            Err(BoundaryError::Parse { line, .. }) => line,
+            other => panic!("expected a parse error, got {:?}", other),
+        };
+        assert_eq!(line_of("area\n1\n0 0\n1 x\n1 1\nEND\nEND\n"), 4);
+        assert_eq!(line_of("area\n1\n0 0\n1 0\nEND\nEND\n"), 2);
+        assert_eq!(line_of("area\n!hole\n0 0\n1 0\n1 1\nEND\nEND\n"), 2);
+        assert_eq!(line_of("area\n1\n0 0\n1 0\n1 1\nEND\n"), 6);
+    }
+
+    #[test]
+    fn segments_are_clipped_where_they_cross_the_boundary() {
+        let boundary = parse_poly(SQUARE_WITH_HOLE).unwrap();
+        let pieces = |a: (f64, f64), b: (f64, f64)| {
+            clip_segment(
+                &boundary,
+                Coord { x: a.0, y: a.1 },
+                Coord { x: b.0, y: b.1 },
+            )
+        };
+        let assert_pieces = |actual: Vec<(f64, f64)>, expected: &[(f64, f64)]| {
+            assert_eq!(actual.len(), expected.len(), "{:?}", actual);
+            for (a, e) in actual.iter().zip(expected) {
+                assert!(
+                    (a.0 - e.0).abs() < 1e-9 && (a.1 - e.1).abs() < 1e-9,
+                    "{:?}",
+                    actual
+                );
+            }
+        };
+
+        // Wholly inside, wholly outside
+        assert_pieces(pieces((1.0, 1.0), (3.0, 1.0)), &[(0.0, 1.0)]);
+        assert_pieces(pieces((11.0, 1.0), (13.0, 1.0)), &[]);
+        // Leaving the square
+        assert_pieces(pieces((5.0, 1.0), (15.0, 1.0)), &[(0.0, 0.5)]);
+        // Crossing the hole leaves the pieces either side of it
+        assert_pieces(pieces((0.0, 5.0), (10.0, 5.0)), &[(0.0, 0.4), (0.6, 1.0)]);
+    }
+}
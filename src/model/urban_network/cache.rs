@@ -0,0 +1,214 @@
+use std::{
+    fs::{self, File},
+    io::{self, BufReader, BufWriter},
+    path::{Path, PathBuf},
+};
+
+use sha2::{Digest, Sha256};
+
+use super::{street_network_from_osm, ImportOptions, StreetNetworkError, StreetNetworkSpec};
+
+/// Bumped whenever the serialized layout of `StreetNetworkSpec` changes, invalidating old caches.
+const CACHE_FORMAT_VERSION: u32 = 8;
+
+/// Cache file for a set of extracts: named after the first source file, and keyed on a hash of
+/// their contents in order together with the import options and cache format version, so any change
+/// to either produces a fresh import.
+pub fn cache_path(
+    cache_dir: &Path,
+    filepaths: &[PathBuf],
+    options: &ImportOptions,
+) -> io::Result<PathBuf> {
+    let mut hasher = Sha256::new();
+    for filepath in filepaths {
+        io::copy(&mut File::open(filepath)?, &mut hasher)?;
+    }
+    hasher.update(CACHE_FORMAT_VERSION.to_le_bytes());
+    hasher.update(format!("{:?}", options).as_bytes());
+    let digest = hasher.finalize();
+    let key: String = digest[..12].iter().map(|b| format!("{:02x}", b)).collect();
+
+    let stem = filepaths
+        .first()
+        .and_then(|filepath| filepath.file_name())
+        .and_then(|name| name.to_str())
+        .unwrap_or("network");
+    Ok(cache_dir.join(format!("{}-{}.bin", stem, key)))
+}
+
+pub fn read_cached_network(path: &Path) -> Result<StreetNetworkSpec, bincode::Error> {
+    let reader = BufReader::new(File::open(path)?);
+    bincode::deserialize_from(reader)
+}
+
+pub fn write_cached_network(path: &Path, spec: &StreetNetworkSpec) -> Result<(), bincode::Error> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let writer = BufWriter::new(File::create(path)?);
+    bincode::serialize_into(writer, spec)
+}
+
+/// Import a street network, reusing a cached copy from `options.cache_dir` when one exists for these
+/// exact source files and set of options. Cache failures are reported but never fatal: the network
+/// is imported from source instead.
+pub fn cached_street_network_from_osm(
+    filepaths: &[PathBuf],
+    options: &ImportOptions,
+) -> Result<StreetNetworkSpec, StreetNetworkError> {
+    let Some(cache_dir) = &options.cache_dir else {
+        return street_network_from_osm(filepaths, options);
+    };
+
+    let cache_file = match cache_path(cache_dir, filepaths, options) {
+        Ok(path) => path,
+        Err(e) => {
+            println!("Unable to hash {:?} for caching: {}", filepaths, e);
+            return street_network_from_osm(filepaths, options);
+        }
+    };
+
+    if cache_file.exists() {
+        match read_cached_network(&cache_file) {
+            Ok(spec) => {
+                println!("Loaded street network from cache {:?}", cache_file);
+                return Ok(spec);
+            }
+            Err(e) => println!("Ignoring unreadable cache {:?}: {}", cache_file, e),
+        }
+    }
+
+    let spec = street_network_from_osm(filepaths, options)?;
+    match write_cached_network(&cache_file, &spec) {
+        Ok(()) => println!("Cached street network to {:?}", cache_file),
+        Err(e) => println!("Unable to write cache {:?}: {}", cache_file, e),
+    }
+    Ok(spec)
+}
+
+#[cfg(test)]
+mod tests {
+    use krabmaga::engine::fields::{
+        field::Field,
+        network::{EdgeOptions, Network},
+    };
+    use krabmaga::engine::location::Real2D;
+
+    use super::*;
+    use crate::model::urban_network::{
+        import::OsmNetworkComponents, LocalProjection, Sidewalk, StreetEdgeLabel, StreetNetwork,
+        StreetNode,
+    };
+
+    fn scratch_dir(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("flaneur-{}-{}", name, std::process::id()))
+    }
+
+    #[test]
+    fn cached_networks_round_trip() {
+        let mut network = Network::new(true);
+        let nodes = [(7, 0.0, 0.0), (3, 12.0, 0.0), (9, 12.0, 5.0)]
+            .map(|(osm_id, x, y)| StreetNode::new(osm_id, Real2D { x, y }));
+        for node in nodes {
+            network.add_node(node);
+        }
+        let label = StreetEdgeLabel {
+            name: Some("Main Street".to_string()),
+            sidewalk: Sidewalk::Left,
+            incline: Some(4.0),
+            geometry: vec![
+                Real2D { x: 0.0, y: 0.0 },
+                Real2D { x: 6.0, y: 1.0 },
+                Real2D { x: 12.0, y: 0.0 },
+            ],
+            ..StreetEdgeLabel::new(12.5, 1, 0)
+        };
+        network.add_edge(
+            nodes[0],
+            nodes[1],
+            EdgeOptions::WeightedLabeled(label.reversed(), 12.5),
+        );
+        network.add_edge(
+            nodes[1],
+            nodes[0],
+            EdgeOptions::WeightedLabeled(label, 12.5),
+        );
+        network.add_edge(
+            nodes[1],
+            nodes[2],
+            EdgeOptions::WeightedLabeled(StreetEdgeLabel::new(5.0, 2, 0), 5.0),
+        );
+        network.lazy_update();
+        let spec = StreetNetworkSpec {
+            network: StreetNetwork(network),
+            dim: (12.0, 5.0),
+            projection: LocalProjection::new(-73.17, 44.01),
+            component_report: None,
+            skipped: Vec::new(),
+            report: OsmNetworkComponents::new().report(),
+            hierarchy: None,
+        };
+
+        let dir = scratch_dir("cache");
+        let path = dir.join("network.bin");
+        write_cached_network(&path, &spec).unwrap();
+        let cached = read_cached_network(&path);
+        fs::remove_dir_all(&dir).unwrap();
+        let cached = cached.unwrap();
+
+        assert_eq!(cached.dim, spec.dim);
+        assert_eq!(cached.network.node_ids(), spec.network.node_ids());
+        for id in spec.network.node_ids() {
+            let (original, copy) = (
+                spec.network.0.get_object(id).unwrap(),
+                cached.network.0.get_object(id).unwrap(),
+            );
+            assert_eq!(copy.osm_id, original.osm_id);
+            assert_eq!(copy.loc, original.loc);
+        }
+        let edges = |network: &StreetNetwork| {
+            let mut edges: Vec<String> = network
+                .edge_list()
+                .iter()
+                .map(|e| format!("{} {} {:?} {:?}", e.u, e.v, e.weight, e.label))
+                .collect();
+            edges.sort();
+            edges
+        };
+        assert_eq!(edges(&cached.network), edges(&spec.network));
+        assert_eq!(
+            cached.projection.project_f64(-73.16, 44.02),
+            spec.projection.project_f64(-73.16, 44.02)
+        );
+    }
+
+    #[test]
+    fn cache_keys_change_with_contents_and_options() {
+        let dir = scratch_dir("cache-key");
+        fs::create_dir_all(&dir).unwrap();
+        let extract = dir.join("extract.osm.pbf");
+        let key = |options: &ImportOptions| {
+            cache_path(&dir, std::slice::from_ref(&extract), options).unwrap()
+        };
+
+        fs::write(&extract, b"first").unwrap();
+        let defaults = ImportOptions::default();
+        let first = key(&defaults);
+        let simplified = key(&ImportOptions {
+            simplify: true,
+            ..Default::default()
+        });
+        fs::write(&extract, b"second").unwrap();
+        let second = key(&defaults);
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert!(first
+            .file_name()
+            .unwrap()
+            .to_str()
+            .unwrap()
+            .starts_with("extract.osm.pbf-"));
+        assert_ne!(first, simplified);
+        assert_ne!(first, second);
+    }
+}
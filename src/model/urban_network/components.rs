@@ -0,0 +1,269 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fmt::Display,
+};
+
+use krabmaga::engine::fields::{field::Field, network::Network};
+use krabmaga::engine::location::Real2D;
+use petgraph::{algo::tarjan_scc, graphmap::DiGraphMap, unionfind::UnionFind};
+use serde::{Deserialize, Serialize};
+
+use super::{network::edge_options, node::Real2DDef, StreetNetwork};
+
+/// Notion of connectivity used when splitting a network into components.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Connectivity {
+    /// Nodes are connected if a path links them ignoring edge direction
+    Weak,
+    /// Nodes are connected only if each is reachable from the other
+    Strong,
+}
+
+/// Summary of a component dropped from the network.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct FragmentInfo {
+    pub node_count: usize,
+    pub edge_count: usize,
+    /// Summed length of the fragment's edges, in metres
+    pub total_length: f32,
+    /// Mean location of the fragment's nodes
+    #[serde(with = "Real2DDef")]
+    pub centroid: Real2D,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ComponentReport {
+    pub connectivity: Connectivity,
+    pub kept_nodes: usize,
+    pub kept_edges: usize,
+    /// Discarded components, largest first
+    pub discarded: Vec<FragmentInfo>,
+}
+
+impl Display for ComponentReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(
+            f,
+            "Kept largest {:?} component: {} nodes, {} edges; discarded {} fragments",
+            self.connectivity,
+            self.kept_nodes,
+            self.kept_edges,
+            self.discarded.len()
+        )?;
+        for fragment in self.discarded.iter() {
+            writeln!(
+                f,
+                "  {} nodes, {} edges, {:.1} m around ({:.1}, {:.1})",
+                fragment.node_count,
+                fragment.edge_count,
+                fragment.total_length,
+                fragment.centroid.x,
+                fragment.centroid.y
+            )?;
+        }
+        Ok(())
+    }
+}
+
+impl StreetNetwork {
+    /// Connected components of the network as lists of node IDs, largest first.
+    pub fn connected_components(&self, connectivity: Connectivity) -> Vec<Vec<u32>> {
+        let node_ids = self.node_ids();
+        let edges = self.edge_list();
+
+        let mut components: Vec<Vec<u32>> = match connectivity {
+            Connectivity::Weak => {
+                // Node IDs are not necessarily dense, so union-find over their positions
+                let position = |id: u32| node_ids.binary_search(&id).ok();
+                let mut sets = UnionFind::<usize>::new(node_ids.len());
+                for edge in edges.iter() {
+                    if let (Some(u), Some(v)) = (position(edge.u), position(edge.v)) {
+                        sets.union(u, v);
+                    }
+                }
+                let labels = sets.into_labeling();
+                let mut grouped: Vec<Vec<u32>> = vec![Vec::new(); node_ids.len()];
+                for (i, id) in node_ids.iter().enumerate() {
+                    grouped[labels[i]].push(*id);
+                }
+                grouped.into_iter().filter(|c| !c.is_empty()).collect()
+            }
+            Connectivity::Strong => {
+                let mut graph = DiGraphMap::<u32, ()>::with_capacity(node_ids.len(), edges.len());
+                for id in node_ids.iter() {
+                    graph.add_node(*id);
+                }
+                for edge in edges.iter() {
+                    graph.add_edge(edge.u, edge.v, ());
+                }
+                tarjan_scc(&graph)
+            }
+        };
+
+        components.sort_by_key(|c| std::cmp::Reverse(c.len()));
+        components
+    }
+
+    /// Network restricted to the given nodes and the edges between them.
+    pub fn subnetwork(&self, keep: &HashSet<u32>) -> StreetNetwork {
+        let mut network = Network::new(self.0.direct);
+        for id in self.node_ids() {
+            if keep.contains(&id) {
+                if let Some(node) = self.0.get_object(id) {
+                    network.add_node(node);
+                }
+            }
+        }
+        for edge in self.edge_list() {
+            if !(keep.contains(&edge.u) && keep.contains(&edge.v)) {
+                continue;
+            }
+            // Undirected networks re-add the reverse edge themselves
+            if !self.0.direct && edge.u > edge.v {
+                continue;
+            }
+            if let (Some(u), Some(v)) = (self.0.get_object(edge.u), self.0.get_object(edge.v)) {
+                network.add_edge(u, v, edge_options(edge.label, edge.weight));
+            }
+        }
+        network.lazy_update();
+        StreetNetwork(network)
+    }
+
+    /// Keep only the largest connected component, reporting what was discarded.
+    pub fn largest_component(
+        &self,
+        connectivity: Connectivity,
+    ) -> (StreetNetwork, ComponentReport) {
+        let components = self.connected_components(connectivity);
+        let component_of: HashMap<u32, usize> = components
+            .iter()
+            .enumerate()
+            .flat_map(|(i, c)| c.iter().map(move |id| (*id, i)))
+            .collect();
+
+        // Tally edges lying within each component in a single pass
+        let mut edge_counts = vec![0_usize; components.len()];
+        let mut lengths = vec![0_f32; components.len()];
+        for edge in self.edge_list() {
+            match (component_of.get(&edge.u), component_of.get(&edge.v)) {
+                (Some(cu), Some(cv)) if cu == cv => {
+                    edge_counts[*cu] += 1;
+                    lengths[*cu] += edge.label.map(|l| l.len).unwrap_or(0.0);
+                }
+                _ => {}
+            }
+        }
+
+        let discarded = components
+            .iter()
+            .enumerate()
+            .skip(1)
+            .map(|(i, component)| {
+                let locations: Vec<Real2D> = component
+                    .iter()
+                    .filter_map(|id| self.0.get_object(*id).map(|n| n.loc))
+                    .collect();
+                let count = locations.len().max(1) as f32;
+                FragmentInfo {
+                    node_count: component.len(),
+                    edge_count: edge_counts[i],
+                    total_length: lengths[i],
+                    centroid: Real2D {
+                        x: locations.iter().map(|l| l.x).sum::<f32>() / count,
+                        y: locations.iter().map(|l| l.y).sum::<f32>() / count,
+                    },
+                }
+            })
+            .collect();
+
+        let keep: HashSet<u32> = components
+            .first()
+            .map(|c| c.iter().copied().collect())
+            .unwrap_or_default();
+        let network = self.subnetwork(&keep);
+        let report = ComponentReport {
+            connectivity,
+            kept_nodes: keep.len(),
+            kept_edges: edge_counts.first().copied().unwrap_or(0),
+            discarded,
+        };
+        (network, report)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use krabmaga::engine::fields::network::EdgeOptions;
+
+    use super::*;
+    use crate::model::urban_network::{StreetEdgeLabel, StreetNode};
+
+    /// Nodes 0-1-2 joined both ways, 3 reachable from 2 but not back, and an island 4-5 away
+    /// from both.
+    fn fragmented_network() -> StreetNetwork {
+        let mut network = Network::new(true);
+        let nodes: Vec<StreetNode> = [
+            (0.0, 0.0),
+            (10.0, 0.0),
+            (20.0, 0.0),
+            (30.0, 0.0),
+            (100.0, 0.0),
+            (100.0, 10.0),
+        ]
+        .iter()
+        .enumerate()
+        .map(|(i, &(x, y))| StreetNode::new(i as i64, Real2D { x, y }))
+        .collect();
+        for node in &nodes {
+            network.add_node(*node);
+        }
+        for (u, v) in [(0, 1), (1, 0), (1, 2), (2, 1), (2, 3), (4, 5), (5, 4)] {
+            let label = StreetEdgeLabel::new(10.0, 0, 0);
+            network.add_edge(
+                nodes[u],
+                nodes[v],
+                EdgeOptions::WeightedLabeled(label, 10.0),
+            );
+        }
+        network.lazy_update();
+        StreetNetwork(network)
+    }
+
+    fn sizes(components: &[Vec<u32>]) -> Vec<usize> {
+        components.iter().map(Vec::len).collect()
+    }
+
+    #[test]
+    fn weak_components_ignore_direction() {
+        let network = fragmented_network();
+        assert_eq!(
+            sizes(&network.connected_components(Connectivity::Weak)),
+            vec![4, 2]
+        );
+        assert_eq!(
+            sizes(&network.connected_components(Connectivity::Strong)),
+            vec![3, 2, 1]
+        );
+    }
+
+    #[test]
+    fn largest_component_reports_discarded_fragments() {
+        let network = fragmented_network();
+        let (largest, report) = network.largest_component(Connectivity::Strong);
+
+        assert_eq!(largest.node_ids().len(), 3);
+        assert_eq!(largest.edge_list().len(), 4);
+        assert_eq!((report.kept_nodes, report.kept_edges), (3, 4));
+
+        let fragments: Vec<(usize, usize)> = report
+            .discarded
+            .iter()
+            .map(|f| (f.node_count, f.edge_count))
+            .collect();
+        assert_eq!(fragments, vec![(2, 2), (1, 0)]);
+        let island = &report.discarded[0];
+        assert_eq!(island.total_length, 20.0);
+        assert_eq!((island.centroid.x, island.centroid.y), (100.0, 5.0));
+    }
+}
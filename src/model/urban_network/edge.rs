@@ -1,29 +1,283 @@
 use std::fmt;
 use std::hash::{Hash, Hasher};
 
+use krabmaga::engine::location::Real2D;
 use serde::{Deserialize, Serialize};
 
-#[derive(Copy, Clone, Serialize, Deserialize, Debug)]
+use crate::model::error::ImportError;
+
+use super::node::real2d_vec;
+
+/// Low bits of a segment ID holding the segment's index along its way; the way ID takes the rest.
+const SEGMENT_INDEX_BITS: u32 = 24;
+
+/// OSM `highway` classification of a street segment.
+#[derive(Copy, Clone, Serialize, Deserialize, Debug, Default, PartialEq, Eq, Hash)]
+pub enum HighwayClass {
+    Footway,
+    Pedestrian,
+    Path,
+    Steps,
+    LivingStreet,
+    Residential,
+    Service,
+    Unclassified,
+    Track,
+    Tertiary,
+    Secondary,
+    Primary,
+    Trunk,
+    #[default]
+    Other,
+}
+
+impl HighwayClass {
+    pub fn from_osm(value: &str) -> Self {
+        match value {
+            "footway" | "corridor" | "crossing" => HighwayClass::Footway,
+            "pedestrian" => HighwayClass::Pedestrian,
+            "path" | "bridleway" | "cycleway" => HighwayClass::Path,
+            "steps" => HighwayClass::Steps,
+            "living_street" => HighwayClass::LivingStreet,
+            "residential" => HighwayClass::Residential,
+            "service" => HighwayClass::Service,
+            "unclassified" | "road" => HighwayClass::Unclassified,
+            "track" => HighwayClass::Track,
+            "tertiary" | "tertiary_link" => HighwayClass::Tertiary,
+            "secondary" | "secondary_link" => HighwayClass::Secondary,
+            "primary" | "primary_link" => HighwayClass::Primary,
+            "trunk" | "trunk_link" => HighwayClass::Trunk,
+            _ => HighwayClass::Other,
+        }
+    }
+
+    /// Canonical OSM `highway` value for the class, or `None` for `Other`.
+    pub fn as_osm(&self) -> Option<&'static str> {
+        match self {
+            HighwayClass::Footway => Some("footway"),
+            HighwayClass::Pedestrian => Some("pedestrian"),
+            HighwayClass::Path => Some("path"),
+            HighwayClass::Steps => Some("steps"),
+            HighwayClass::LivingStreet => Some("living_street"),
+            HighwayClass::Residential => Some("residential"),
+            HighwayClass::Service => Some("service"),
+            HighwayClass::Unclassified => Some("unclassified"),
+            HighwayClass::Track => Some("track"),
+            HighwayClass::Tertiary => Some("tertiary"),
+            HighwayClass::Secondary => Some("secondary"),
+            HighwayClass::Primary => Some("primary"),
+            HighwayClass::Trunk => Some("trunk"),
+            HighwayClass::Other => None,
+        }
+    }
+}
+
+/// Which side(s) of a road carry a sidewalk, per the OSM `sidewalk` tag.
+#[derive(Copy, Clone, Serialize, Deserialize, Debug, Default, PartialEq, Eq, Hash)]
+pub enum Sidewalk {
+    Both,
+    Left,
+    Right,
+    No,
+    /// Sidewalk mapped as its own way
+    Separate,
+    #[default]
+    Unknown,
+}
+
+impl Sidewalk {
+    pub fn from_osm(value: &str) -> Self {
+        match value {
+            "both" | "yes" => Sidewalk::Both,
+            "left" => Sidewalk::Left,
+            "right" => Sidewalk::Right,
+            "no" | "none" => Sidewalk::No,
+            "separate" => Sidewalk::Separate,
+            _ => Sidewalk::Unknown,
+        }
+    }
+
+    /// OSM `sidewalk` value for the variant, or `None` for `Unknown`.
+    pub fn as_osm(&self) -> Option<&'static str> {
+        match self {
+            Sidewalk::Both => Some("both"),
+            Sidewalk::Left => Some("left"),
+            Sidewalk::Right => Some("right"),
+            Sidewalk::No => Some("no"),
+            Sidewalk::Separate => Some("separate"),
+            Sidewalk::Unknown => None,
+        }
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize, Debug, Default)]
 pub struct StreetEdgeLabel {
     pub len: f32,
-    pub id: u32,
+    /// Segment ID, unique within the network and stable across imports of the same OSM data; see
+    /// `segment_id`. Both directions of a two-way segment share it.
+    pub id: u64,
+    /// OSM ID of the way the segment belongs to
+    pub way_id: i64,
+    /// Index of the segment along its way, counting from the way's first node
+    pub segment: u32,
+    pub highway: HighwayClass,
+    pub name: Option<String>,
+    pub sidewalk: Sidewalk,
+    pub surface: Option<String>,
+    pub lit: Option<bool>,
+    /// Width in metres
+    pub width: Option<f32>,
+    /// Incline in percent; positive values climb in the direction of the edge
+    pub incline: Option<f32>,
+    pub oneway_foot: bool,
+    /// Speed limit in km/h of the road this segment runs along
+    pub maxspeed: Option<f32>,
+    /// Polyline from the edge's source node to its target node, inclusive. Empty for a straight
+    /// segment between the two nodes.
+    #[serde(with = "real2d_vec")]
+    pub geometry: Vec<Real2D>,
 }
 
 impl StreetEdgeLabel {
-    pub fn new(len: f32, id: u32) -> Self {
-        StreetEdgeLabel { len, id }
+    /// # Panics
+    ///
+    /// If the way ID and segment index do not fit a segment ID; see `segment_id`.
+    pub fn new(len: f32, way_id: i64, segment: u32) -> Self {
+        StreetEdgeLabel {
+            len,
+            id: Self::segment_id(way_id, segment).expect("segment ID out of range"),
+            way_id,
+            segment,
+            ..Default::default()
+        }
+    }
+
+    /// ID of the `segment`th segment of a way: the way ID in the upper 40 bits and the segment
+    /// index in the lower 24. Way IDs of magnitude 2^39 or more (OSM's are below 2^31, and the
+    /// negative IDs editors give new ways are small) and indices of 2^24 or more (OSM caps ways at
+    /// 2000 nodes) would collide with other segments' IDs, so are refused.
+    pub fn segment_id(way_id: i64, segment: u32) -> Result<u64, ImportError> {
+        let way_limit = 1_i64 << (63 - SEGMENT_INDEX_BITS);
+        if !(-way_limit..way_limit).contains(&way_id) || segment >= 1 << SEGMENT_INDEX_BITS {
+            return Err(ImportError::SegmentIdOverflow { way_id, segment });
+        }
+        Ok(((way_id << SEGMENT_INDEX_BITS) as u64) | segment as u64)
+    }
+
+    /// Label for the same segment traversed in the opposite direction.
+    pub fn reversed(&self) -> Self {
+        StreetEdgeLabel {
+            incline: self.incline.map(|i| -i),
+            geometry: self.geometry.iter().rev().copied().collect(),
+            ..self.clone()
+        }
+    }
+
+    /// True if the two labels describe the same kind of street, so that consecutive edges carrying
+    /// them can be merged without losing attributes.
+    pub fn attributes_match(&self, other: &StreetEdgeLabel) -> bool {
+        self.highway == other.highway
+            && self.name == other.name
+            && self.sidewalk == other.sidewalk
+            && self.surface == other.surface
+            && self.lit == other.lit
+            && self.oneway_foot == other.oneway_foot
+    }
+
+    /// Point at fraction `t` (0 at the source, 1 at the target) along the edge, following its
+    /// geometry if it has one and the straight line from `u` to `v` otherwise.
+    pub fn point_at(&self, u: Real2D, v: Real2D, t: f32) -> Real2D {
+        let t = t.clamp(0.0, 1.0);
+        if self.geometry.len() < 2 {
+            return lerp(u, v, t);
+        }
+
+        let segment_lengths: Vec<f32> = self
+            .geometry
+            .windows(2)
+            .map(|pair| distance(pair[0], pair[1]))
+            .collect();
+        let mut remaining = t * segment_lengths.iter().sum::<f32>();
+        for (pair, length) in self.geometry.windows(2).zip(segment_lengths) {
+            if remaining <= length && length > 0.0 {
+                return lerp(pair[0], pair[1], remaining / length);
+            }
+            remaining -= length;
+        }
+        *self.geometry.last().unwrap_or(&v)
     }
 }
 
+fn lerp(a: Real2D, b: Real2D, t: f32) -> Real2D {
+    Real2D {
+        x: a.x + (b.x - a.x) * t,
+        y: a.y + (b.y - a.y) * t,
+    }
+}
+
+fn distance(a: Real2D, b: Real2D) -> f32 {
+    ((b.x - a.x).powi(2) + (b.y - a.y).powi(2)).sqrt()
+}
+
 impl fmt::Display for StreetEdgeLabel {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "{{length: {}, id: {}}}", self.len, self.id)
+        write!(
+            f,
+            "{{length: {}, id: {} (way {} #{}), highway: {:?}, name: {}}}",
+            self.len,
+            self.id,
+            self.way_id,
+            self.segment,
+            self.highway,
+            self.name.as_deref().unwrap_or("-")
+        )
     }
 }
 
 impl Hash for StreetEdgeLabel {
     fn hash<H: Hasher>(&self, state: &mut H) {
         self.id.hash(state);
-        (self.len.round() as i32).hash(state);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn segment_ids_are_unique_across_ways_and_signs() {
+        let ids = [
+            StreetEdgeLabel::segment_id(1, 0),
+            StreetEdgeLabel::segment_id(1, 1),
+            // Beyond 16 bits of segment index, which once spilled into the next way's IDs
+            StreetEdgeLabel::segment_id(1, 1 << 16),
+            StreetEdgeLabel::segment_id(2, 0),
+            StreetEdgeLabel::segment_id(-1, 0),
+            StreetEdgeLabel::segment_id(-2, 5),
+            StreetEdgeLabel::segment_id(1_400_000_000, 1999),
+        ]
+        .map(Result::unwrap);
+        for (i, a) in ids.iter().enumerate() {
+            assert!(ids[i + 1..].iter().all(|b| a != b), "{} repeats", a);
+        }
+    }
+
+    #[test]
+    fn oversized_ways_and_segments_are_refused() {
+        for (way_id, segment) in [(1, 1 << 24), (1 << 39, 0), (-(1 << 39) - 1, 0)] {
+            assert_eq!(
+                StreetEdgeLabel::segment_id(way_id, segment),
+                Err(ImportError::SegmentIdOverflow { way_id, segment })
+            );
+        }
+        assert!(StreetEdgeLabel::segment_id((1 << 39) - 1, (1 << 24) - 1).is_ok());
+        assert!(StreetEdgeLabel::segment_id(-(1 << 39), 0).is_ok());
+    }
+
+    #[test]
+    fn both_directions_share_a_segment_id() {
+        let label = StreetEdgeLabel::new(10.0, 42, 3);
+        assert_eq!((label.way_id, label.segment), (42, 3));
+        assert_eq!(label.reversed().id, label.id);
     }
 }
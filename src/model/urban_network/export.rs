@@ -0,0 +1,470 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs::{self, File},
+    io::{self, BufWriter},
+    path::Path,
+};
+
+use flatgeobuf::{ColumnType, FgbCrs, FgbWriter, FgbWriterOptions, GeometryType};
+use geo::{Geometry, LineString, Point};
+use geozero::{ColumnValue, PropertyProcessor};
+use serde_json::{json, Map, Value};
+
+use super::{LocalProjection, StreetNetwork};
+
+/// Which elements of a network to export; each format stores one geometry type per file.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum NetworkLayer {
+    /// Nodes as Points
+    Nodes,
+    /// Edges as LineStrings following their geometry
+    Edges,
+}
+
+/// Values computed during a run (e.g. footfall per edge), exported as extra attribute columns
+/// alongside each element's own attributes.
+#[derive(Clone, Debug, Default)]
+pub struct NetworkMetrics {
+    /// Values by metric name, then network node ID
+    pub nodes: BTreeMap<String, HashMap<u32, f64>>,
+    /// Values by metric name, then (source, target) network node IDs
+    pub edges: BTreeMap<String, HashMap<(u32, u32), f64>>,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum ColumnKind {
+    Long,
+    Double,
+    Bool,
+    String,
+}
+
+#[derive(Clone, Debug)]
+enum PropertyValue {
+    Long(i64),
+    Double(f64),
+    Bool(bool),
+    String(String),
+}
+
+struct ExportFeature {
+    /// Lon/lat vertices; a single vertex for a Point
+    vertices: Vec<(f64, f64)>,
+    /// One value per column, `None` where the element has no value
+    properties: Vec<Option<PropertyValue>>,
+}
+
+/// A network layer in WGS84, ready to be written out in any format.
+struct ExportLayer {
+    layer: NetworkLayer,
+    columns: Vec<(String, ColumnKind)>,
+    features: Vec<ExportFeature>,
+}
+
+impl ExportLayer {
+    fn new(
+        network: &StreetNetwork,
+        projection: &LocalProjection,
+        metrics: &NetworkMetrics,
+        layer: NetworkLayer,
+    ) -> Self {
+        match layer {
+            NetworkLayer::Nodes => Self::nodes(network, projection, metrics),
+            NetworkLayer::Edges => Self::edges(network, projection, metrics),
+        }
+    }
+
+    fn nodes(
+        network: &StreetNetwork,
+        projection: &LocalProjection,
+        metrics: &NetworkMetrics,
+    ) -> Self {
+        let mut columns = vec![
+            ("id".to_string(), ColumnKind::Long),
+            ("osm_id".to_string(), ColumnKind::Long),
+            ("highway".to_string(), ColumnKind::String),
+            ("crossing".to_string(), ColumnKind::String),
+        ];
+        columns.extend(
+            metrics
+                .nodes
+                .keys()
+                .map(|name| (name.clone(), ColumnKind::Double)),
+        );
+
+        let features = network
+            .node_ids()
+            .into_iter()
+            .filter_map(|id| {
+                let node = network.0.get_object(id)?;
+                let tag = |key: &str| {
+                    node.control
+                        .as_osm()
+                        .iter()
+                        .find(|(k, _)| *k == key)
+                        .map(|(_, v)| PropertyValue::String(v.to_string()))
+                };
+                let mut properties = vec![
+                    Some(PropertyValue::Long(id as i64)),
+                    Some(PropertyValue::Long(node.osm_id)),
+                    tag("highway"),
+                    tag("crossing"),
+                ];
+                properties.extend(
+                    metrics
+                        .nodes
+                        .values()
+                        .map(|values| values.get(&id).map(|v| PropertyValue::Double(*v))),
+                );
+                Some(ExportFeature {
+                    vertices: vec![projection.unproject(node.loc)],
+                    properties,
+                })
+            })
+            .collect();
+
+        ExportLayer {
+            layer: NetworkLayer::Nodes,
+            columns,
+            features,
+        }
+    }
+
+    fn edges(
+        network: &StreetNetwork,
+        projection: &LocalProjection,
+        metrics: &NetworkMetrics,
+    ) -> Self {
+        let mut columns: Vec<(String, ColumnKind)> = [
+            ("u", ColumnKind::Long),
+            ("v", ColumnKind::Long),
+            ("id", ColumnKind::Long),
+            ("way_id", ColumnKind::Long),
+            ("segment", ColumnKind::Long),
+            ("length", ColumnKind::Double),
+            ("highway", ColumnKind::String),
+            ("name", ColumnKind::String),
+            ("sidewalk", ColumnKind::String),
+            ("surface", ColumnKind::String),
+            ("lit", ColumnKind::Bool),
+            ("width", ColumnKind::Double),
+            ("incline", ColumnKind::Double),
+            ("oneway_foot", ColumnKind::Bool),
+            ("maxspeed", ColumnKind::Double),
+        ]
+        .into_iter()
+        .map(|(name, kind)| (name.to_string(), kind))
+        .collect();
+        columns.extend(
+            metrics
+                .edges
+                .keys()
+                .map(|name| (name.clone(), ColumnKind::Double)),
+        );
+
+        let mut edges = network.edge_list();
+        edges.sort_by_key(|edge| (edge.u, edge.v));
+        let features = edges
+            .into_iter()
+            .filter_map(|edge| {
+                let u = network.0.get_object(edge.u)?;
+                let v = network.0.get_object(edge.v)?;
+                let label = edge.label.unwrap_or_default();
+                let points = if label.geometry.len() >= 2 {
+                    label.geometry.clone()
+                } else {
+                    vec![u.loc, v.loc]
+                };
+
+                let float = |value: Option<f32>| value.map(|v| PropertyValue::Double(v as f64));
+                let text =
+                    |value: Option<&str>| value.map(|v| PropertyValue::String(v.to_string()));
+                let mut properties = vec![
+                    Some(PropertyValue::Long(u.osm_id)),
+                    Some(PropertyValue::Long(v.osm_id)),
+                    Some(PropertyValue::Long(label.id as i64)),
+                    Some(PropertyValue::Long(label.way_id)),
+                    Some(PropertyValue::Long(label.segment as i64)),
+                    float(Some(label.len)),
+                    text(label.highway.as_osm()),
+                    text(label.name.as_deref()),
+                    text(Some(&format!("{:?}", label.sidewalk).to_lowercase())),
+                    text(label.surface.as_deref()),
+                    label.lit.map(PropertyValue::Bool),
+                    float(label.width),
+                    float(label.incline),
+                    Some(PropertyValue::Bool(label.oneway_foot)),
+                    float(label.maxspeed),
+                ];
+                properties.extend(metrics.edges.values().map(|values| {
+                    values
+                        .get(&(edge.u, edge.v))
+                        .map(|v| PropertyValue::Double(*v))
+                }));
+
+                Some(ExportFeature {
+                    vertices: points.iter().map(|p| projection.unproject(*p)).collect(),
+                    properties,
+                })
+            })
+            .collect();
+
+        ExportLayer {
+            layer: NetworkLayer::Edges,
+            columns,
+            features,
+        }
+    }
+
+    fn to_geojson(&self) -> Value {
+        let features: Vec<Value> = self
+            .features
+            .iter()
+            .map(|feature| {
+                let coordinates: Vec<Value> = feature
+                    .vertices
+                    .iter()
+                    .map(|(x, y)| json!([x, y]))
+                    .collect();
+                let geometry = match self.layer {
+                    NetworkLayer::Nodes => json!({"type": "Point", "coordinates": coordinates[0]}),
+                    NetworkLayer::Edges => {
+                        json!({"type": "LineString", "coordinates": coordinates})
+                    }
+                };
+                let properties: Map<String, Value> = self
+                    .columns
+                    .iter()
+                    .zip(feature.properties.iter())
+                    .map(|((name, _), value)| {
+                        let value = match value {
+                            Some(PropertyValue::Long(v)) => json!(v),
+                            Some(PropertyValue::Double(v)) => json!(v),
+                            Some(PropertyValue::Bool(v)) => json!(v),
+                            Some(PropertyValue::String(v)) => json!(v),
+                            None => Value::Null,
+                        };
+                        (name.clone(), value)
+                    })
+                    .collect();
+                json!({"type": "Feature", "geometry": geometry, "properties": properties})
+            })
+            .collect();
+        json!({"type": "FeatureCollection", "features": features})
+    }
+
+    /// Errors from the FlatGeobuf writer are passed on as `io::Error`s, like those of the file.
+    fn write_flatgeobuf(&self, out: impl io::Write) -> io::Result<()> {
+        let (name, geometry_type) = match self.layer {
+            NetworkLayer::Nodes => ("nodes", GeometryType::Point),
+            NetworkLayer::Edges => ("edges", GeometryType::LineString),
+        };
+        let options = FgbWriterOptions {
+            detect_type: false,
+            promote_to_multi: false,
+            crs: FgbCrs {
+                code: 4326,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let mut fgb = FgbWriter::create_with_options(name, geometry_type, options)
+            .map_err(io::Error::other)?;
+        for (name, kind) in self.columns.iter() {
+            let column_type = match kind {
+                ColumnKind::Long => ColumnType::Long,
+                ColumnKind::Double => ColumnType::Double,
+                ColumnKind::Bool => ColumnType::Bool,
+                ColumnKind::String => ColumnType::String,
+            };
+            fgb.add_column(name, column_type, |_, column| column.nullable = true);
+        }
+
+        for feature in self.features.iter() {
+            let geometry: Geometry<f64> = match self.layer {
+                NetworkLayer::Nodes => Point::from(feature.vertices[0]).into(),
+                NetworkLayer::Edges => LineString::from(feature.vertices.clone()).into(),
+            };
+            // Properties are written inside the writer's callback, which cannot return an error
+            let mut written = Ok(());
+            fgb.add_feature_geom(geometry, |writer| {
+                written = self
+                    .columns
+                    .iter()
+                    .zip(feature.properties.iter())
+                    .enumerate()
+                    .try_for_each(|(i, ((name, _), value))| {
+                        let value = match value {
+                            Some(PropertyValue::Long(v)) => ColumnValue::Long(*v),
+                            Some(PropertyValue::Double(v)) => ColumnValue::Double(*v),
+                            Some(PropertyValue::Bool(v)) => ColumnValue::Bool(*v),
+                            Some(PropertyValue::String(v)) => ColumnValue::String(v),
+                            // Missing values are left out of the feature, which reads as null
+                            None => return Ok(()),
+                        };
+                        writer.property(i, name, &value).map(|_| ())
+                    });
+            })
+            .map_err(io::Error::other)?;
+            written.map_err(io::Error::other)?;
+        }
+        fgb.write(out).map_err(io::Error::other)
+    }
+}
+
+/// Write one layer of a network to a GeoJSON FeatureCollection in WGS84.
+pub fn write_geojson(
+    network: &StreetNetwork,
+    projection: &LocalProjection,
+    metrics: &NetworkMetrics,
+    layer: NetworkLayer,
+    filepath: &Path,
+) -> io::Result<()> {
+    let layer = ExportLayer::new(network, projection, metrics, layer);
+    let writer = BufWriter::new(File::create(filepath)?);
+    serde_json::to_writer(writer, &layer.to_geojson())?;
+    Ok(())
+}
+
+/// Write one layer of a network to a FlatGeobuf file in WGS84, with a spatial index so readers
+/// such as QGIS can load just the features in view.
+pub fn write_flatgeobuf(
+    network: &StreetNetwork,
+    projection: &LocalProjection,
+    metrics: &NetworkMetrics,
+    layer: NetworkLayer,
+    filepath: &Path,
+) -> io::Result<()> {
+    let layer = ExportLayer::new(network, projection, metrics, layer);
+    let writer = BufWriter::new(File::create(filepath)?);
+    layer.write_flatgeobuf(writer)
+}
+
+/// Write both layers of a network to `dir`, as `nodes` and `edges` files in GeoJSON and FlatGeobuf,
+/// creating the directory if need be.
+pub fn export_network(
+    network: &StreetNetwork,
+    projection: &LocalProjection,
+    metrics: &NetworkMetrics,
+    dir: &Path,
+) -> io::Result<()> {
+    fs::create_dir_all(dir)?;
+    for (layer, name) in [
+        (NetworkLayer::Nodes, "nodes"),
+        (NetworkLayer::Edges, "edges"),
+    ] {
+        let geojson_path = dir.join(name).with_extension("geojson");
+        write_geojson(network, projection, metrics, layer, &geojson_path)?;
+        let flatgeobuf_path = dir.join(name).with_extension("fgb");
+        write_flatgeobuf(network, projection, metrics, layer, &flatgeobuf_path)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use flatgeobuf::{FallibleStreamingIterator, FgbReader};
+    use geozero::{FeatureProperties, ToGeo};
+    use krabmaga::engine::fields::{
+        field::Field,
+        network::{EdgeOptions, Network},
+    };
+
+    use super::*;
+    use crate::model::urban_network::{HighwayClass, StreetEdgeLabel, StreetNode};
+
+    /// Two nodes joined by a named footway, with a footfall count on the edge.
+    fn street(projection: &LocalProjection) -> (StreetNetwork, NetworkMetrics) {
+        let mut network = Network::new(true);
+        let nodes = [(101, -73.18, 44.01), (102, -73.17, 44.02)]
+            .map(|(osm_id, lon, lat)| StreetNode::new(osm_id, projection.project(lon, lat)));
+        for node in nodes {
+            network.add_node(node);
+        }
+        let label = StreetEdgeLabel {
+            highway: HighwayClass::Footway,
+            name: Some("College Path".to_string()),
+            width: Some(2.5),
+            ..StreetEdgeLabel::new(1360.0, 7, 0)
+        };
+        network.add_edge(
+            nodes[0],
+            nodes[1],
+            EdgeOptions::WeightedLabeled(label, 1360.0),
+        );
+        network.lazy_update();
+        let network = StreetNetwork(network);
+
+        let edge = &network.edge_list()[0];
+        let mut metrics = NetworkMetrics::default();
+        metrics.edges.insert(
+            "footfall".to_string(),
+            HashMap::from([((edge.u, edge.v), 42.0)]),
+        );
+        (network, metrics)
+    }
+
+    fn scratch_path(name: &str) -> std::path::PathBuf {
+        std::env::temp_dir().join(format!("flaneur-{}-{}", std::process::id(), name))
+    }
+
+    #[test]
+    fn edges_are_written_to_flatgeobuf_in_wgs84() {
+        let (projection, _) = LocalProjection::fitted_to(-73.18, -73.17, 44.02, 44.01);
+        let (network, metrics) = street(&projection);
+        let path = scratch_path("edges.fgb");
+        write_flatgeobuf(&network, &projection, &metrics, NetworkLayer::Edges, &path).unwrap();
+
+        let mut file = File::open(&path).unwrap();
+        let mut features = FgbReader::open(&mut file).unwrap().select_all().unwrap();
+        let feature = features.next().unwrap().unwrap();
+        assert_eq!(feature.property::<i64>("u").unwrap(), 101);
+        assert_eq!(feature.property::<i64>("v").unwrap(), 102);
+        assert_eq!(feature.property::<String>("highway").unwrap(), "footway");
+        assert_eq!(feature.property::<String>("name").unwrap(), "College Path");
+        assert_eq!(feature.property::<f64>("width").unwrap(), 2.5);
+        assert_eq!(feature.property::<f64>("footfall").unwrap(), 42.0);
+        // Missing values are null rather than a placeholder
+        assert!(feature.property::<String>("surface").is_err());
+
+        let Geometry::LineString(line) = feature.to_geo().unwrap() else {
+            panic!("edge is not a LineString");
+        };
+        let ends = [line.0[0], line.0[line.0.len() - 1]];
+        for (end, (lon, lat)) in ends.iter().zip([(-73.18, 44.01), (-73.17, 44.02)]) {
+            assert!((end.x - lon).abs() < 1e-6 && (end.y - lat).abs() < 1e-6);
+        }
+        assert!(features.next().unwrap().is_none());
+        drop(features);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn nodes_are_written_to_geojson_as_points() {
+        let (projection, _) = LocalProjection::fitted_to(-73.18, -73.17, 44.02, 44.01);
+        let (network, metrics) = street(&projection);
+        let path = scratch_path("nodes.geojson");
+        write_geojson(&network, &projection, &metrics, NetworkLayer::Nodes, &path).unwrap();
+        let contents = fs::read_to_string(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        let geojson::GeoJson::FeatureCollection(collection) = contents.parse().unwrap() else {
+            panic!("nodes are not a FeatureCollection");
+        };
+        let mut nodes: Vec<(i64, Vec<f64>)> = collection
+            .features
+            .iter()
+            .map(|feature| {
+                let osm_id = feature.property("osm_id").unwrap().as_i64().unwrap();
+                match &feature.geometry.as_ref().unwrap().value {
+                    geojson::Value::Point(position) => (osm_id, position.clone()),
+                    other => panic!("node geometry is {:?}", other),
+                }
+            })
+            .collect();
+        nodes.sort_by_key(|(osm_id, _)| *osm_id);
+        assert_eq!(nodes.len(), 2);
+        assert_eq!(nodes[0].0, 101);
+        assert!((nodes[0].1[0] + 73.18).abs() < 1e-6 && (nodes[0].1[1] - 44.01).abs() < 1e-6);
+    }
+}
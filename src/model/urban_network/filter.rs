@@ -0,0 +1,229 @@
+use std::collections::BTreeSet;
+
+/// Highway classes a pedestrian can normally walk along, absent any explicit tagging to the contrary.
+pub const DEFAULT_WALKABLE_HIGHWAYS: [&str; 22] = [
+    "footway",
+    "pedestrian",
+    "path",
+    "steps",
+    "corridor",
+    "living_street",
+    "residential",
+    "service",
+    "unclassified",
+    "track",
+    "bridleway",
+    "road",
+    "tertiary",
+    "tertiary_link",
+    "secondary",
+    "secondary_link",
+    "primary",
+    "primary_link",
+    "trunk",
+    "trunk_link",
+    "crossing",
+    "elevator",
+];
+
+/// Values of `foot` (or `access`) that explicitly grant pedestrian access.
+const FOOT_ALLOWED: [&str; 4] = ["yes", "designated", "permissive", "official"];
+
+/// Configurable predicate deciding which OSM ways become street edges.
+///
+/// A way is walkable if it carries a `highway` tag from `highway_classes` (or any `highway` tag
+/// together with an explicit `foot=yes|designated|permissive`), and is not excluded by its `foot`,
+/// `access` or `area` tags. Pedestrian plazas are kept by default although other areas are not.
+#[derive(Clone, Debug)]
+pub struct WalkabilityFilter {
+    pub highway_classes: BTreeSet<String>,
+    /// Drop ways tagged `foot=no`
+    pub exclude_foot_no: bool,
+    /// Drop ways tagged `access=private|no` (or `service=private`) unless foot access is granted
+    pub exclude_private: bool,
+    /// Keep closed `area=yes` highways; when false, these are dropped
+    pub include_areas: bool,
+    /// Keep pedestrian plazas (`highway=pedestrian` + `area=yes`) even when `include_areas` is
+    /// false, to be linked across on import
+    pub include_plazas: bool,
+}
+
+impl WalkabilityFilter {
+    /// Filter that accepts every way, reproducing the unfiltered import behaviour.
+    pub fn permissive() -> Self {
+        WalkabilityFilter {
+            highway_classes: BTreeSet::new(),
+            exclude_foot_no: false,
+            exclude_private: false,
+            include_areas: true,
+            include_plazas: true,
+        }
+    }
+
+    fn is_permissive(&self) -> bool {
+        self.highway_classes.is_empty()
+            && !self.exclude_foot_no
+            && !self.exclude_private
+            && self.include_areas
+    }
+
+    /// Decide whether a way with the given tags is walkable.
+    pub fn accepts<'a, I>(&self, tags: I) -> bool
+    where
+        I: IntoIterator<Item = (&'a str, &'a str)>,
+    {
+        if self.is_permissive() {
+            return true;
+        }
+
+        let mut highway = None;
+        let mut foot = None;
+        let mut access = None;
+        let mut service = None;
+        let mut area = None;
+
+        for (key, value) in tags {
+            match key {
+                "highway" => highway = Some(value),
+                "foot" => foot = Some(value),
+                "access" => access = Some(value),
+                "service" => service = Some(value),
+                "area" => area = Some(value),
+                _ => {}
+            }
+        }
+
+        let Some(highway) = highway else {
+            return false;
+        };
+
+        let foot_allowed = foot.is_some_and(|f| FOOT_ALLOWED.contains(&f));
+
+        if self.exclude_foot_no && foot == Some("no") {
+            return false;
+        }
+
+        if self.exclude_private
+            && !foot_allowed
+            && (matches!(access, Some("private") | Some("no")) || service == Some("private"))
+        {
+            return false;
+        }
+
+        let is_plaza = highway == "pedestrian" && area == Some("yes");
+        if !self.include_areas && area == Some("yes") && !(self.include_plazas && is_plaza) {
+            return false;
+        }
+
+        foot_allowed || self.highway_classes.contains(highway)
+    }
+}
+
+impl Default for WalkabilityFilter {
+    fn default() -> Self {
+        WalkabilityFilter {
+            highway_classes: DEFAULT_WALKABLE_HIGHWAYS
+                .iter()
+                .map(|c| c.to_string())
+                .collect(),
+            exclude_foot_no: true,
+            exclude_private: true,
+            include_areas: false,
+            include_plazas: true,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn accepts(filter: &WalkabilityFilter, tags: &[(&str, &str)]) -> bool {
+        filter.accepts(tags.iter().copied())
+    }
+
+    #[test]
+    fn walkable_highways_are_kept() {
+        let filter = WalkabilityFilter::default();
+        assert!(accepts(&filter, &[("highway", "footway")]));
+        assert!(accepts(
+            &filter,
+            &[("highway", "residential"), ("name", "Main Street")]
+        ));
+        assert!(!accepts(&filter, &[("highway", "motorway")]));
+        assert!(!accepts(&filter, &[("building", "yes")]));
+        assert!(!accepts(&filter, &[("waterway", "river"), ("foot", "yes")]));
+    }
+
+    #[test]
+    fn foot_tags_override_highway_class() {
+        let filter = WalkabilityFilter::default();
+        assert!(!accepts(&filter, &[("highway", "footway"), ("foot", "no")]));
+        assert!(accepts(
+            &filter,
+            &[("highway", "cycleway"), ("foot", "designated")]
+        ));
+        assert!(!accepts(&filter, &[("highway", "cycleway")]));
+    }
+
+    #[test]
+    fn private_ways_are_dropped_unless_foot_access_is_granted() {
+        let filter = WalkabilityFilter::default();
+        assert!(!accepts(
+            &filter,
+            &[("highway", "service"), ("access", "private")]
+        ));
+        assert!(!accepts(
+            &filter,
+            &[("highway", "service"), ("service", "private")]
+        ));
+        assert!(!accepts(&filter, &[("highway", "track"), ("access", "no")]));
+        assert!(accepts(
+            &filter,
+            &[
+                ("highway", "service"),
+                ("access", "private"),
+                ("foot", "yes")
+            ]
+        ));
+
+        let filter = WalkabilityFilter {
+            exclude_private: false,
+            ..WalkabilityFilter::default()
+        };
+        assert!(accepts(
+            &filter,
+            &[("highway", "service"), ("access", "private")]
+        ));
+    }
+
+    #[test]
+    fn areas_follow_include_areas() {
+        let area = [("highway", "footway"), ("area", "yes")];
+        assert!(!accepts(&WalkabilityFilter::default(), &area));
+        let filter = WalkabilityFilter {
+            include_areas: true,
+            ..WalkabilityFilter::default()
+        };
+        assert!(accepts(&filter, &area));
+    }
+
+    #[test]
+    fn plazas_follow_include_plazas() {
+        let plaza = [("highway", "pedestrian"), ("area", "yes")];
+        assert!(accepts(&WalkabilityFilter::default(), &plaza));
+        let filter = WalkabilityFilter {
+            include_plazas: false,
+            ..WalkabilityFilter::default()
+        };
+        assert!(!accepts(&filter, &plaza));
+    }
+
+    #[test]
+    fn permissive_filter_accepts_everything() {
+        let filter = WalkabilityFilter::permissive();
+        assert!(accepts(&filter, &[("building", "yes")]));
+        assert!(accepts(&filter, &[("highway", "motorway"), ("foot", "no")]));
+        assert!(accepts(&filter, &[]));
+    }
+}
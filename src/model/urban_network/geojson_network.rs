@@ -0,0 +1,299 @@
+use std::{collections::HashMap, fs, path::Path};
+
+use geo::{Coord, Geometry};
+use geojson::{GeoJson, JsonObject, JsonValue};
+
+use crate::model::error::StreetNetworkError;
+
+use super::{
+    import::{ImportOptions, OsmNetworkComponents, OsmNodeInfo, OsmWayInfo, OsmWayTags},
+    network::street_network_from_components,
+    projection::LocalProjection,
+    StreetNetworkSpec,
+};
+
+/// Options for reading a street network from GeoJSON line features, such as a municipal sidewalk
+/// dataset.
+#[derive(Clone, Debug, Default)]
+pub struct GeoJsonNetworkOptions {
+    /// Vertices closer than this many metres are merged into a single node; at zero, only
+    /// vertices with identical coordinates are
+    pub snap_tolerance: f64,
+    /// Renames feature properties to the OSM tag keys they correspond to (e.g. `"STREET_NAME"` to
+    /// `"name"`). Properties not listed are read under their own names.
+    pub property_keys: HashMap<String, String>,
+    /// `highway` value for features that have none, e.g. `"footway"` for a sidewalk dataset
+    pub default_highway: Option<String>,
+}
+
+/// A line feature as a lon/lat polyline with its OSM-style tags.
+type TaggedLine = (Vec<Coord<f64>>, Vec<(String, String)>);
+
+/// Nodes already placed, as (ID, projected position), bucketed by grid cell.
+type SnapCells = HashMap<(i64, i64), Vec<(i64, (f64, f64))>>;
+
+/// Line features, as lon/lat polylines with their OSM-style tags.
+fn read_lines(
+    filepath: &Path,
+    options: &GeoJsonNetworkOptions,
+) -> Result<Vec<TaggedLine>, StreetNetworkError> {
+    let contents = fs::read_to_string(filepath)?;
+    let features = match contents.parse::<GeoJson>()? {
+        GeoJson::FeatureCollection(fc) => fc.features,
+        GeoJson::Feature(feature) => vec![feature],
+        GeoJson::Geometry(geometry) => vec![geojson::Feature::from(geometry)],
+    };
+
+    let mut lines = Vec::new();
+    for feature in features {
+        let tags = feature
+            .properties
+            .as_ref()
+            .map(|properties| tags_of(properties, options))
+            .unwrap_or_default();
+        let Some(geometry) = feature.geometry else {
+            continue;
+        };
+        // Features other than lines (e.g. points marking crossings) carry no street segments
+        match Geometry::<f64>::try_from(geometry)? {
+            Geometry::LineString(line) => lines.push((line.0, tags)),
+            Geometry::MultiLineString(multi) => {
+                for line in multi {
+                    lines.push((line.0, tags.clone()));
+                }
+            }
+            _ => {}
+        }
+    }
+    Ok(lines)
+}
+
+/// Feature properties as OSM-style tags, renamed per `options.property_keys`.
+fn tags_of(properties: &JsonObject, options: &GeoJsonNetworkOptions) -> Vec<(String, String)> {
+    let mut tags: Vec<(String, String)> = properties
+        .iter()
+        .filter_map(|(key, value)| {
+            let value = match value {
+                JsonValue::String(s) => s.clone(),
+                JsonValue::Number(n) => n.to_string(),
+                JsonValue::Bool(b) => if *b { "yes" } else { "no" }.to_string(),
+                _ => return None,
+            };
+            let key = options.property_keys.get(key).unwrap_or(key).clone();
+            Some((key, value))
+        })
+        .collect();
+    if let Some(highway) = &options.default_highway {
+        if !tags.iter().any(|(key, _)| key == "highway") {
+            tags.push(("highway".to_string(), highway.clone()));
+        }
+    }
+    tags
+}
+
+/// Assigns node IDs to vertices, merging those within the snapping tolerance of an existing node.
+/// Nodes are bucketed on a grid of tolerance-sized cells in projected coordinates, so each lookup
+/// only compares against nodes in the neighbouring cells.
+struct VertexSnapper {
+    projection: LocalProjection,
+    tolerance: f64,
+    cells: SnapCells,
+    nodes: HashMap<i64, OsmNodeInfo>,
+}
+
+impl VertexSnapper {
+    fn new(projection: LocalProjection, tolerance: f64) -> Self {
+        VertexSnapper {
+            projection,
+            tolerance,
+            cells: HashMap::new(),
+            nodes: HashMap::new(),
+        }
+    }
+
+    fn node_at(&mut self, vertex: Coord<f64>) -> i64 {
+        let point = self.projection.project_f64(vertex.x, vertex.y);
+        // With no tolerance, cells shrink to exact (nanodegree) coordinates
+        let cell = if self.tolerance > 0.0 {
+            (
+                (point.0 / self.tolerance).floor() as i64,
+                (point.1 / self.tolerance).floor() as i64,
+            )
+        } else {
+            (
+                (vertex.x * 1e9).round() as i64,
+                (vertex.y * 1e9).round() as i64,
+            )
+        };
+
+        let reach = if self.tolerance > 0.0 { 1 } else { 0 };
+        let mut nearest: Option<(f64, i64)> = None;
+        for dx in -reach..=reach {
+            for dy in -reach..=reach {
+                let Some(candidates) = self.cells.get(&(cell.0 + dx, cell.1 + dy)) else {
+                    continue;
+                };
+                for (id, other) in candidates {
+                    let distance = (point.0 - other.0).hypot(point.1 - other.1);
+                    if distance <= self.tolerance && nearest.is_none_or(|(d, _)| distance < d) {
+                        nearest = Some((distance, *id));
+                    }
+                }
+            }
+        }
+        if let Some((_, id)) = nearest {
+            return id;
+        }
+
+        let id = self.nodes.len() as i64 + 1;
+        self.nodes
+            .insert(id, OsmNodeInfo::from_lon_lat(id, vertex.x, vertex.y));
+        self.cells.entry(cell).or_default().push((id, point));
+        id
+    }
+}
+
+/// Read the line features of a GeoJSON file as network components. Lines are noded where they
+/// share a vertex (after snapping), not where they merely cross. Every line is taken as walkable;
+/// the walkability filter is not applied.
+pub fn read_geojson_lines(
+    filepath: &Path,
+    options: &GeoJsonNetworkOptions,
+) -> Result<OsmNetworkComponents, StreetNetworkError> {
+    let lines = read_lines(filepath, options)?;
+
+    let mut components = OsmNetworkComponents::new();
+    let vertices = lines.iter().flat_map(|(line, _)| line.iter());
+    let (left, right, top, bottom) = vertices.fold(
+        (f64::MAX, f64::MIN, f64::MIN, f64::MAX),
+        |(left, right, top, bottom), c| {
+            (left.min(c.x), right.max(c.x), top.max(c.y), bottom.min(c.y))
+        },
+    );
+    let projection = LocalProjection::centred_on(left, right, top, bottom);
+    let mut snapper = VertexSnapper::new(projection, options.snap_tolerance.max(0.0));
+
+    for (way_id, (line, tags)) in (1..).zip(lines) {
+        let mut node_ids: Vec<i64> = Vec::with_capacity(line.len());
+        for vertex in line {
+            let id = snapper.node_at(vertex);
+            // Vertices snapped onto their predecessor would form zero-length segments
+            if node_ids.last() != Some(&id) {
+                node_ids.push(id);
+            }
+        }
+        let tags = OsmWayTags::from_tags(tags.iter().map(|(k, v)| (k.as_str(), v.as_str())));
+        match OsmWayInfo::new(way_id, node_ids, tags) {
+            Ok(way) => components.ways.push(way),
+            Err(e) => components.issues.push(e),
+        }
+    }
+
+    components.nodes = snapper.nodes;
+    components.compute_segment_lengths();
+    components.bounding_box = components.node_extent();
+    Ok(components)
+}
+
+/// Build a street network from the LineString and MultiLineString features of a GeoJSON file, with
+/// feature properties mapped onto edge labels as OSM tags would be.
+pub fn street_network_from_geojson(
+    filepath: &Path,
+    geojson_options: &GeoJsonNetworkOptions,
+    options: &ImportOptions,
+) -> Result<StreetNetworkSpec, StreetNetworkError> {
+    let components = read_geojson_lines(filepath, geojson_options)?;
+    street_network_from_components(components, options)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    fn write_geojson(name: &str, features: &str) -> PathBuf {
+        let path =
+            std::env::temp_dir().join(format!("flaneur-{}-{}.geojson", name, std::process::id()));
+        fs::write(
+            &path,
+            format!(
+                r#"{{"type": "FeatureCollection", "features": [{}]}}"#,
+                features
+            ),
+        )
+        .unwrap();
+        path
+    }
+
+    // Two sidewalks whose shared end is digitised about 0.2m apart, plus a crossing point
+    const NEAR_MISS: &str = r#"
+        {"type": "Feature", "properties": {"STREET": "Main St"},
+         "geometry": {"type": "LineString", "coordinates": [[-73.170, 44.010], [-73.169, 44.010]]}},
+        {"type": "Feature", "properties": {"STREET": "Main St", "highway": "path"},
+         "geometry": {"type": "LineString",
+                      "coordinates": [[-73.169002, 44.010001], [-73.168, 44.010], [-73.168, 44.010]]}},
+        {"type": "Feature", "properties": {"kind": "crossing"},
+         "geometry": {"type": "Point", "coordinates": [-73.169, 44.010]}}"#;
+
+    fn read(name: &str, options: &GeoJsonNetworkOptions) -> OsmNetworkComponents {
+        let path = write_geojson(name, NEAR_MISS);
+        let components = read_geojson_lines(&path, options);
+        fs::remove_file(&path).unwrap();
+        components.unwrap()
+    }
+
+    #[test]
+    fn nearby_vertices_are_snapped_together() {
+        let components = read(
+            "geojson-snapped",
+            &GeoJsonNetworkOptions {
+                snap_tolerance: 1.0,
+                ..Default::default()
+            },
+        );
+        assert_eq!(components.nodes.len(), 3);
+        let ways: Vec<&[i64]> = components.ways.iter().map(|way| way.node_ids()).collect();
+        // The repeated final vertex is dropped rather than forming a zero-length segment
+        assert_eq!(ways, vec![&[1, 2][..], &[2, 3][..]]);
+    }
+
+    #[test]
+    fn without_tolerance_only_identical_vertices_are_merged() {
+        let components = read("geojson-exact", &GeoJsonNetworkOptions::default());
+        assert_eq!(components.nodes.len(), 4);
+        let ways: Vec<&[i64]> = components.ways.iter().map(|way| way.node_ids()).collect();
+        assert_eq!(ways, vec![&[1, 2][..], &[3, 4][..]]);
+    }
+
+    #[test]
+    fn properties_are_renamed_and_highway_defaulted() {
+        let options = GeoJsonNetworkOptions {
+            property_keys: HashMap::from([("STREET".to_string(), "name".to_string())]),
+            default_highway: Some("footway".to_string()),
+            ..Default::default()
+        };
+        let properties = |json: &str| match json.parse::<JsonValue>().unwrap() {
+            JsonValue::Object(properties) => properties,
+            _ => unreachable!(),
+        };
+
+        let mut tags = tags_of(
+            &properties(r#"{"STREET": "Main St", "lanes": 2}"#),
+            &options,
+        );
+        tags.sort();
+        assert_eq!(
+            tags,
+            vec![
+                ("highway".to_string(), "footway".to_string()),
+                ("lanes".to_string(), "2".to_string()),
+                ("name".to_string(), "Main St".to_string()),
+            ]
+        );
+
+        let tags = tags_of(&properties(r#"{"highway": "path", "lit": true}"#), &options);
+        assert!(tags.contains(&("highway".to_string(), "path".to_string())));
+        assert!(tags.contains(&("lit".to_string(), "yes".to_string())));
+        assert!(!tags.contains(&("highway".to_string(), "footway".to_string())));
+    }
+}
@@ -0,0 +1,657 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fs::File,
+    io::{BufReader, BufWriter},
+    path::Path,
+};
+
+use geo::{HaversineDistance, Point};
+use krabmaga::engine::fields::{field::Field, network::Network};
+use osmpbf::HeaderBBox;
+use xml::{
+    attribute::OwnedAttribute,
+    common::Position,
+    reader::{self, EventReader},
+    writer::{self, EmitterConfig, EventWriter},
+};
+
+use crate::model::error::{ErrorPolicy, GraphMlError, ImportError, StreetNetworkError};
+
+use super::{
+    import::{ImportOptions, OsmWayTags},
+    network::{edge_options, finish_network},
+    projection::LocalProjection,
+    ImportReport, NodeControl, StreetEdgeLabel, StreetNetwork, StreetNetworkSpec, StreetNode,
+};
+
+/// A `<node>` or `<edge>` with its `<data>` children, keyed by attribute name.
+#[derive(Default)]
+struct GraphMlElement {
+    id: String,
+    source: String,
+    target: String,
+    data: HashMap<String, String>,
+}
+
+fn attribute<'a>(attributes: &'a [OwnedAttribute], name: &str) -> Option<&'a str> {
+    attributes
+        .iter()
+        .find(|attr| attr.name.local_name == name)
+        .map(|attr| attr.value.as_str())
+}
+
+/// First entry of a value OSMnx may have merged into a list during simplification, e.g.
+/// `"['footway', 'path']"` -> `"footway"`.
+fn first_of_list(value: &str) -> &str {
+    let value = value.trim();
+    let value = match value.strip_prefix('[').and_then(|v| v.strip_suffix(']')) {
+        Some(list) => list.split(',').next().unwrap_or("").trim(),
+        None => value,
+    };
+    value.trim_matches(|c| c == '\'' || c == '"')
+}
+
+/// Vertices of a WKT `LINESTRING`, as written by OSMnx for edge geometries.
+fn parse_wkt_linestring(value: &str) -> Option<Vec<(f64, f64)>> {
+    let body = value.trim().strip_prefix("LINESTRING")?.trim();
+    let body = body.strip_prefix('(')?.strip_suffix(')')?;
+    body.split(',')
+        .map(|vertex| {
+            let mut coords = vertex.split_whitespace().map(str::parse::<f64>);
+            match (coords.next(), coords.next()) {
+                (Some(Ok(x)), Some(Ok(y))) => Some((x, y)),
+                _ => None,
+            }
+        })
+        .collect()
+}
+
+/// Nodes and edges of a GraphML file, with the graph-level `<data>`.
+struct GraphMlDocument {
+    nodes: Vec<GraphMlElement>,
+    edges: Vec<GraphMlElement>,
+    graph_data: HashMap<String, String>,
+}
+
+fn read_document(filepath: &Path) -> Result<GraphMlDocument, GraphMlError> {
+    let mut reader = EventReader::new(BufReader::new(File::open(filepath)?));
+    // Key IDs (e.g. "d4") to attribute names (e.g. "x")
+    let mut keys: HashMap<String, String> = HashMap::new();
+    let mut nodes = Vec::new();
+    let mut edges = Vec::new();
+    let mut graph_data = HashMap::new();
+    let mut current: Option<GraphMlElement> = None;
+    let mut data_key: Option<String> = None;
+    let mut text = String::new();
+
+    loop {
+        let line = reader.position().row + 1;
+        let missing = |element: &str, name: &str| GraphMlError::InvalidElement {
+            line,
+            message: format!("<{}> has no {} attribute", element, name),
+        };
+
+        match reader.next()? {
+            reader::XmlEvent::StartElement {
+                name, attributes, ..
+            } => match name.local_name.as_str() {
+                "key" => {
+                    if let (Some(id), Some(attr_name)) = (
+                        attribute(&attributes, "id"),
+                        attribute(&attributes, "attr.name"),
+                    ) {
+                        keys.insert(id.to_string(), attr_name.to_string());
+                    }
+                }
+                "node" => {
+                    current = Some(GraphMlElement {
+                        id: attribute(&attributes, "id")
+                            .ok_or_else(|| missing("node", "id"))?
+                            .to_string(),
+                        ..Default::default()
+                    });
+                }
+                "edge" => {
+                    current = Some(GraphMlElement {
+                        source: attribute(&attributes, "source")
+                            .ok_or_else(|| missing("edge", "source"))?
+                            .to_string(),
+                        target: attribute(&attributes, "target")
+                            .ok_or_else(|| missing("edge", "target"))?
+                            .to_string(),
+                        ..Default::default()
+                    });
+                }
+                "data" => {
+                    data_key = Some(
+                        attribute(&attributes, "key")
+                            .ok_or_else(|| missing("data", "key"))?
+                            .to_string(),
+                    );
+                    text.clear();
+                }
+                _ => {}
+            },
+            reader::XmlEvent::Characters(chars) | reader::XmlEvent::CData(chars)
+                if data_key.is_some() =>
+            {
+                text.push_str(&chars);
+            }
+            reader::XmlEvent::EndElement { name } => match name.local_name.as_str() {
+                "data" => {
+                    if let Some(key) = data_key.take() {
+                        let attr_name = keys.get(&key).cloned().unwrap_or(key);
+                        let value = std::mem::take(&mut text);
+                        match current.as_mut() {
+                            Some(element) => element.data.insert(attr_name, value),
+                            None => graph_data.insert(attr_name, value),
+                        };
+                    }
+                }
+                "node" => nodes.extend(current.take()),
+                "edge" => edges.extend(current.take()),
+                _ => {}
+            },
+            reader::XmlEvent::EndDocument => break,
+            _ => {}
+        }
+    }
+    Ok(GraphMlDocument {
+        nodes,
+        edges,
+        graph_data,
+    })
+}
+
+/// Load a street graph saved by OSMnx's `save_graphml`. Nodes take their OSM ID from `osmid` (or
+/// the node ID) and their location from `x`/`y`; edges take `length`, `osmid`, `highway`,
+/// `geometry` and any other OSM tags present onto their labels. The graph must be unprojected, as
+/// OSMnx saves it by default.
+///
+/// Edges are kept as given, in their stated direction, so two-way streets are expected to appear
+/// once in each direction. Simplification and component pruning follow `options`; the other import
+/// options apply only to OSM extracts.
+pub fn street_network_from_graphml(
+    filepath: &Path,
+    options: &ImportOptions,
+) -> Result<StreetNetworkSpec, StreetNetworkError> {
+    let GraphMlDocument {
+        nodes: graph_nodes,
+        edges: graph_edges,
+        graph_data,
+    } = read_document(filepath)?;
+    if let Some(crs) = graph_data.get("crs") {
+        let crs = crs.to_lowercase();
+        if crs != "epsg:4326" && crs != "wgs84" {
+            return Err(GraphMlError::Projected(crs).into());
+        }
+    }
+
+    let invalid = GraphMlError::InvalidGraph;
+    let mut lon_lat: HashMap<String, (i64, f64, f64)> = HashMap::with_capacity(graph_nodes.len());
+    for node in graph_nodes.iter() {
+        let coordinate = |name: &str| {
+            node.data
+                .get(name)
+                .and_then(|v| v.trim().parse::<f64>().ok())
+                .ok_or_else(|| invalid(format!("Node {} has no valid {}", node.id, name)))
+        };
+        let osm_id = node
+            .data
+            .get("osmid")
+            .unwrap_or(&node.id)
+            .trim()
+            .parse::<i64>()
+            .map_err(|_| invalid(format!("Node {} has no integer OSM ID", node.id)))?;
+        lon_lat.insert(
+            node.id.clone(),
+            (osm_id, coordinate("x")?, coordinate("y")?),
+        );
+    }
+    if lon_lat.is_empty() || graph_edges.is_empty() {
+        return Err(ImportError::EmptyExtract.into());
+    }
+
+    let bbox = lon_lat.values().fold(
+        HeaderBBox {
+            left: f64::MAX,
+            right: f64::MIN,
+            top: f64::MIN,
+            bottom: f64::MAX,
+        },
+        |bbox, (_, lon, lat)| HeaderBBox {
+            left: bbox.left.min(*lon),
+            right: bbox.right.max(*lon),
+            top: bbox.top.max(*lat),
+            bottom: bbox.bottom.min(*lat),
+        },
+    );
+    let (projection, dim) =
+        LocalProjection::fitted_to(bbox.left, bbox.right, bbox.top, bbox.bottom);
+
+    let mut network = Network::<StreetNode, StreetEdgeLabel>::new(true);
+    let mut street_nodes: HashMap<&str, StreetNode> = HashMap::with_capacity(lon_lat.len());
+    for node in graph_nodes.iter() {
+        let (osm_id, lon, lat) = lon_lat[&node.id];
+        let street_node = StreetNode {
+            control: NodeControl::from_osm(
+                ["highway", "crossing"]
+                    .into_iter()
+                    .filter_map(|key| Some((key, first_of_list(node.data.get(key)?)))),
+            ),
+            ..StreetNode::new(osm_id, projection.project(lon, lat))
+        };
+        network.add_node(street_node);
+        street_nodes.insert(node.id.as_str(), street_node);
+    }
+
+    let mut report = ImportReport {
+        osm_nodes: graph_nodes.len(),
+        osm_ways: graph_edges.len(),
+        filtered_ways: 0,
+        degenerate_ways: 0,
+        segments: graph_edges.len(),
+        total_length: 0.0,
+        dangling_references: 0,
+        zero_length_segments: 0,
+        duplicate_segments: 0,
+        plazas: 0,
+        plaza_links: 0,
+        nodes: 0,
+        edges: 0,
+        self_loops: 0,
+        crossings: 0,
+        signalised_nodes: 0,
+        bounding_box: bbox,
+    };
+    let mut skipped: Vec<ImportError> = Vec::new();
+    let mut seen_pairs: HashSet<(&str, &str)> = HashSet::new();
+    // OSMnx edges span whole runs of a way, so number them per way in file order, giving both
+    // directions of a street the same index
+    let mut segment_indices: HashMap<(i64, &str, &str), u32> = HashMap::new();
+    let mut segments_per_way: HashMap<i64, u32> = HashMap::new();
+    for edge in graph_edges.iter() {
+        let way_id = edge
+            .data
+            .get("osmid")
+            .and_then(|id| first_of_list(id).parse::<i64>().ok())
+            .unwrap_or(0);
+        let endpoints = [&edge.source, &edge.target].map(|id| {
+            street_nodes
+                .get(id.as_str())
+                .copied()
+                .ok_or_else(|| ImportError::MissingNodeRef {
+                    way_id,
+                    node_id: id.parse().unwrap_or(0),
+                })
+        });
+        let (u, v) = match endpoints {
+            [Ok(u), Ok(v)] => (u, v),
+            [Err(e), _] | [_, Err(e)] => {
+                report.dangling_references += 1;
+                match options.on_error {
+                    ErrorPolicy::Fail => return Err(e.into()),
+                    ErrorPolicy::SkipAndReport => {
+                        skipped.push(e);
+                        continue;
+                    }
+                }
+            }
+        };
+
+        let geometry = edge
+            .data
+            .get("geometry")
+            .and_then(|g| parse_wkt_linestring(g));
+        let length = match edge
+            .data
+            .get("length")
+            .and_then(|l| l.trim().parse::<f64>().ok())
+        {
+            Some(length) => length,
+            None => {
+                let (_, u_lon, u_lat) = lon_lat[&edge.source];
+                let (_, v_lon, v_lat) = lon_lat[&edge.target];
+                Point::new(u_lon, u_lat).haversine_distance(&Point::new(v_lon, v_lat))
+            }
+        };
+
+        // A two-way street appears once in each direction, so count its length only once
+        let reverse_seen = seen_pairs.contains(&(edge.target.as_str(), edge.source.as_str()));
+        if !seen_pairs.insert((edge.source.as_str(), edge.target.as_str())) {
+            report.duplicate_segments += 1;
+        } else if !reverse_seen {
+            report.total_length += length;
+        }
+        if length == 0.0 {
+            report.zero_length_segments += 1;
+        }
+
+        let tags = OsmWayTags::from_tags(
+            edge.data
+                .iter()
+                .map(|(key, value)| (key.as_str(), first_of_list(value))),
+        );
+        let segment = match edge.data.get("segment").and_then(|s| s.parse::<u32>().ok()) {
+            // Written by `write_graphml`
+            Some(segment) => segment,
+            None => {
+                let (a, b) = (edge.source.as_str(), edge.target.as_str());
+                *segment_indices
+                    .entry((way_id, a.min(b), a.max(b)))
+                    .or_insert_with(|| {
+                        let next = segments_per_way.entry(way_id).or_default();
+                        *next += 1;
+                        *next - 1
+                    })
+            }
+        };
+        let mut label = match tags.to_edge_label(length as f32, way_id, segment) {
+            Ok(label) => label,
+            Err(e) => match options.on_error {
+                ErrorPolicy::Fail => return Err(e.into()),
+                ErrorPolicy::SkipAndReport => {
+                    skipped.push(e);
+                    continue;
+                }
+            },
+        };
+        if let Some(vertices) = geometry {
+            label.geometry = vertices
+                .into_iter()
+                .map(|(lon, lat)| projection.project(lon, lat))
+                .collect();
+        }
+        network.add_edge(u, v, edge_options(Some(label), Some(length as f32)));
+    }
+    if !skipped.is_empty() {
+        println!("Skipped {} problems in graph", skipped.len());
+    }
+
+    network.lazy_update();
+    let (network, component_report, hierarchy) = finish_network(StreetNetwork(network), options);
+    report.record_network(&network);
+
+    Ok(StreetNetworkSpec {
+        network,
+        dim,
+        projection,
+        component_report,
+        skipped,
+        report,
+        hierarchy,
+    })
+}
+
+/// Save a street network as GraphML that OSMnx's `load_graphml` reads back, with node locations
+/// converted to lon/lat through `projection`. Edge attributes are written under their OSM tag keys,
+/// so [`street_network_from_graphml`] reads them back onto the labels.
+pub fn write_graphml(
+    network: &StreetNetwork,
+    projection: &LocalProjection,
+    filepath: &Path,
+) -> Result<(), GraphMlError> {
+    let file = BufWriter::new(File::create(filepath)?);
+    let mut writer = EmitterConfig::new()
+        .perform_indent(true)
+        .create_writer(file);
+
+    let node_keys = ["y", "x", "osmid", "highway", "crossing"];
+    let edge_keys = [
+        "osmid",
+        "segment",
+        "highway",
+        "name",
+        "sidewalk",
+        "surface",
+        "lit",
+        "width",
+        "incline",
+        "oneway:foot",
+        "maxspeed",
+        "length",
+        "geometry",
+    ];
+    writer.write(
+        writer::XmlEvent::start_element("graphml")
+            .default_ns("http://graphml.graphdrawing.org/xmlns"),
+    )?;
+    let keys: Vec<(&str, &str)> = [("graph", "crs")]
+        .into_iter()
+        .chain(node_keys.iter().map(|k| ("node", *k)))
+        .chain(edge_keys.iter().map(|k| ("edge", *k)))
+        .collect();
+    for (i, (domain, name)) in keys.iter().enumerate() {
+        let id = format!("d{}", i);
+        writer.write(
+            writer::XmlEvent::start_element("key")
+                .attr("id", &id)
+                .attr("for", domain)
+                .attr("attr.name", name)
+                .attr("attr.type", "string"),
+        )?;
+        writer.write(writer::XmlEvent::end_element())?;
+    }
+    let key_id = |domain: &str, name: &str| {
+        let index = keys.iter().position(|k| *k == (domain, name)).unwrap_or(0);
+        format!("d{}", index)
+    };
+
+    writer.write(writer::XmlEvent::start_element("graph").attr("edgedefault", "directed"))?;
+    write_data(&mut writer, &key_id("graph", "crs"), "epsg:4326")?;
+
+    for id in network.node_ids() {
+        let Some(node) = network.0.get_object(id) else {
+            continue;
+        };
+        let (lon, lat) = projection.unproject(node.loc);
+        let osm_id = node.osm_id.to_string();
+        writer.write(writer::XmlEvent::start_element("node").attr("id", &osm_id))?;
+        write_data(&mut writer, &key_id("node", "y"), &lat.to_string())?;
+        write_data(&mut writer, &key_id("node", "x"), &lon.to_string())?;
+        write_data(&mut writer, &key_id("node", "osmid"), &osm_id)?;
+        for (key, value) in node.control.as_osm() {
+            write_data(&mut writer, &key_id("node", key), value)?;
+        }
+        writer.write(writer::XmlEvent::end_element())?;
+    }
+
+    // OSMnx graphs are multigraphs, keyed by the edge's index among those joining the same nodes
+    let mut parallel_counts: HashMap<(u32, u32), u32> = HashMap::new();
+    let mut edges = network.edge_list();
+    edges.sort_by_key(|edge| (edge.u, edge.v));
+    for edge in edges {
+        let (Some(u), Some(v)) = (network.0.get_object(edge.u), network.0.get_object(edge.v))
+        else {
+            continue;
+        };
+        let label = edge.label.clone().unwrap_or_default();
+        let key = parallel_counts.entry((edge.u, edge.v)).or_default();
+        let (source, target, key_str) =
+            (u.osm_id.to_string(), v.osm_id.to_string(), key.to_string());
+        *key += 1;
+
+        writer.write(
+            writer::XmlEvent::start_element("edge")
+                .attr("source", &source)
+                .attr("target", &target)
+                .attr("id", &key_str),
+        )?;
+        write_data(
+            &mut writer,
+            &key_id("edge", "osmid"),
+            &label.way_id.to_string(),
+        )?;
+        write_data(
+            &mut writer,
+            &key_id("edge", "segment"),
+            &label.segment.to_string(),
+        )?;
+        if let Some(highway) = label.highway.as_osm() {
+            write_data(&mut writer, &key_id("edge", "highway"), highway)?;
+        }
+        if let Some(name) = &label.name {
+            write_data(&mut writer, &key_id("edge", "name"), name)?;
+        }
+        if let Some(sidewalk) = label.sidewalk.as_osm() {
+            write_data(&mut writer, &key_id("edge", "sidewalk"), sidewalk)?;
+        }
+        if let Some(surface) = &label.surface {
+            write_data(&mut writer, &key_id("edge", "surface"), surface)?;
+        }
+        if let Some(lit) = label.lit {
+            let lit = if lit { "yes" } else { "no" };
+            write_data(&mut writer, &key_id("edge", "lit"), lit)?;
+        }
+        // Widths are in metres, inclines in percent and speeds in km/h, the units read by default
+        if let Some(width) = label.width {
+            write_data(&mut writer, &key_id("edge", "width"), &width.to_string())?;
+        }
+        if let Some(incline) = label.incline {
+            write_data(
+                &mut writer,
+                &key_id("edge", "incline"),
+                &format!("{}%", incline),
+            )?;
+        }
+        if label.oneway_foot {
+            write_data(&mut writer, &key_id("edge", "oneway:foot"), "yes")?;
+        }
+        if let Some(maxspeed) = label.maxspeed {
+            write_data(
+                &mut writer,
+                &key_id("edge", "maxspeed"),
+                &maxspeed.to_string(),
+            )?;
+        }
+        write_data(
+            &mut writer,
+            &key_id("edge", "length"),
+            &label.len.to_string(),
+        )?;
+        if label.geometry.len() >= 2 {
+            let vertices: Vec<String> = label
+                .geometry
+                .iter()
+                .map(|point| {
+                    let (lon, lat) = projection.unproject(*point);
+                    format!("{} {}", lon, lat)
+                })
+                .collect();
+            let wkt = format!("LINESTRING ({})", vertices.join(", "));
+            write_data(&mut writer, &key_id("edge", "geometry"), &wkt)?;
+        }
+        writer.write(writer::XmlEvent::end_element())?;
+    }
+
+    writer.write(writer::XmlEvent::end_element())?;
+    writer.write(writer::XmlEvent::end_element())?;
+    Ok(())
+}
+
+fn write_data<W: std::io::Write>(
+    writer: &mut EventWriter<W>,
+    key: &str,
+    value: &str,
+) -> Result<(), GraphMlError> {
+    writer.write(writer::XmlEvent::start_element("data").attr("key", key))?;
+    writer.write(writer::XmlEvent::characters(value))?;
+    writer.write(writer::XmlEvent::end_element())?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use krabmaga::engine::fields::network::EdgeOptions;
+
+    use super::*;
+    use crate::model::urban_network::{HighwayClass, Sidewalk};
+
+    #[test]
+    fn labels_survive_a_graphml_round_trip() {
+        let (projection, _) = LocalProjection::fitted_to(-73.18, -73.17, 44.02, 44.01);
+        let mut network = Network::new(true);
+        let nodes = [
+            (101, -73.18, 44.01),
+            (102, -73.17, 44.01),
+            (103, -73.17, 44.02),
+        ]
+        .map(|(osm_id, lon, lat)| StreetNode::new(osm_id, projection.project(lon, lat)));
+        for node in nodes {
+            network.add_node(node);
+        }
+        let label = StreetEdgeLabel {
+            highway: HighwayClass::Residential,
+            name: Some("Main Street".to_string()),
+            sidewalk: Sidewalk::Left,
+            surface: Some("asphalt".to_string()),
+            lit: Some(false),
+            width: Some(3.5),
+            incline: Some(-4.0),
+            oneway_foot: true,
+            maxspeed: Some(40.0),
+            ..StreetEdgeLabel::new(802.5, 2001, 3)
+        };
+        network.add_edge(
+            nodes[0],
+            nodes[1],
+            EdgeOptions::WeightedLabeled(label.clone(), 802.5),
+        );
+        network.add_edge(
+            nodes[1],
+            nodes[2],
+            EdgeOptions::WeightedLabeled(StreetEdgeLabel::new(1112.0, 2002, 0), 1112.0),
+        );
+        network.lazy_update();
+
+        let path = std::env::temp_dir().join(format!("flaneur-{}.graphml", std::process::id()));
+        write_graphml(&StreetNetwork(network), &projection, &path).unwrap();
+        let spec = street_network_from_graphml(&path, &ImportOptions::default());
+        std::fs::remove_file(&path).unwrap();
+        let spec = spec.unwrap();
+
+        let osm_id = |id: u32| spec.network.0.get_object(id).unwrap().osm_id;
+        let mut edges: Vec<_> = spec
+            .network
+            .edge_list()
+            .into_iter()
+            .map(|edge| ((osm_id(edge.u), osm_id(edge.v)), edge.label.unwrap()))
+            .collect();
+        edges.sort_by_key(|(ends, _)| *ends);
+        assert_eq!(edges.len(), 2);
+
+        let ((ends, read), (_, plain)) = (&edges[0], &edges[1]);
+        assert_eq!(*ends, (101, 102));
+        assert_eq!((read.way_id, read.segment), (2001, 3));
+        assert_eq!(read.id, StreetEdgeLabel::segment_id(2001, 3).unwrap());
+        assert_eq!(read.len, 802.5);
+        assert_eq!(read.highway, HighwayClass::Residential);
+        assert_eq!(read.name.as_deref(), Some("Main Street"));
+        assert_eq!(read.sidewalk, Sidewalk::Left);
+        assert_eq!(read.surface.as_deref(), Some("asphalt"));
+        assert_eq!(read.lit, Some(false));
+        assert_eq!(read.width, Some(3.5));
+        assert_eq!(read.incline, Some(-4.0));
+        assert!(read.oneway_foot);
+        assert_eq!(read.maxspeed, Some(40.0));
+
+        assert_eq!(plain.sidewalk, Sidewalk::Unknown);
+        assert_eq!((plain.surface.as_ref(), plain.lit), (None, None));
+        assert_eq!(
+            (plain.width, plain.incline, plain.maxspeed),
+            (None, None, None)
+        );
+        assert!(!plain.oneway_foot);
+    }
+
+    #[test]
+    fn osmnx_list_values_and_geometries_are_read() {
+        assert_eq!(first_of_list("['footway', 'path']"), "footway");
+        assert_eq!(first_of_list("residential"), "residential");
+        assert_eq!(
+            parse_wkt_linestring("LINESTRING (-73.1 44.0, -73.2 44.1)"),
+            Some(vec![(-73.1, 44.0), (-73.2, 44.1)])
+        );
+        assert_eq!(parse_wkt_linestring("POINT (-73.1 44.0)"), None);
+    }
+}
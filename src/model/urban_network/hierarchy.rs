@@ -0,0 +1,545 @@
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap};
+
+use indicatif::ProgressBar;
+use serde::{Deserialize, Serialize};
+
+use super::routing::{PartialLeg, QueueEntry};
+use super::{IntersectionDelays, Route, StreetEdgeLabel, StreetNetwork, StreetNetworkPosition};
+
+/// Witness searches give up after settling this many nodes, adding a possibly redundant shortcut
+/// rather than spending longer proving it unnecessary.
+const WITNESS_SETTLE_LIMIT: usize = 500;
+
+/// An edge of the hierarchy, leading to a node contracted later. Shortcuts stand for the two edges
+/// through the `middle` node they bypass.
+#[derive(Copy, Clone, Debug, Serialize, Deserialize)]
+struct HierarchyEdge {
+    node: u32,
+    /// Length of the edges stood for, plus the waits at the nodes they leave
+    cost: f32,
+}
+
+/// Contraction hierarchy over a street network's edge lengths, for shortest-path queries that
+/// settle only a few hundred nodes however large the network.
+///
+/// Nodes are contracted one at a time, least important first, adding shortcut edges wherever
+/// removing a node would lengthen a shortest path between its neighbours. A query then searches
+/// upwards in contraction order from both ends, meeting at the most important node of the route.
+/// The hierarchy refers to nodes by network ID, so it only answers for the network it was built
+/// from; it is rebuilt along with the network when the import is cached. Built with intersection
+/// delays, it charges each node's expected wait as `StreetNetwork::route_by_cost` does, and routes
+/// minimise length plus waits.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ContractionHierarchy {
+    /// Network node ID of each hierarchy node, indexed by hierarchy node
+    node_ids: Vec<u32>,
+    /// Hierarchy node of each network node ID
+    index: HashMap<u32, u32>,
+    /// Edges to later-contracted nodes, by hierarchy node
+    up: Vec<Vec<HierarchyEdge>>,
+    /// Edges from later-contracted nodes, by hierarchy node, for searching backwards
+    down: Vec<Vec<HierarchyEdge>>,
+    /// The node each shortcut (source, target) bypasses, in hierarchy node indices
+    middles: HashMap<(u32, u32), u32>,
+    /// Waits charged at nodes, if any
+    delays: Option<IntersectionDelays>,
+}
+
+/// Remaining graph while contracting: edges between uncontracted nodes, as the shortest length
+/// between each pair.
+struct Contraction {
+    out_edges: Vec<HashMap<u32, f32>>,
+    in_edges: Vec<HashMap<u32, f32>>,
+    contracted_neighbours: Vec<i64>,
+    /// Depth of each node in the hierarchy so far: one more than that of its deepest contracted
+    /// neighbour
+    levels: Vec<i64>,
+    /// Witness search lengths by node, kept between searches and reset where they were touched
+    witness: Vec<f32>,
+}
+
+impl Contraction {
+    /// Length of the shortest path from `source` to each of `targets` avoiding `excluded`, or
+    /// infinity where none is found within `max_length` and the settle limit.
+    fn witness_lengths(
+        &mut self,
+        source: u32,
+        excluded: u32,
+        targets: &[(u32, f32)],
+        max_length: f32,
+    ) -> Vec<f32> {
+        let lengths = &mut self.witness;
+        let mut touched = vec![source];
+        lengths[source as usize] = 0.0;
+        let mut queue = BinaryHeap::from([QueueEntry {
+            priority: 0.0,
+            node: source,
+        }]);
+        let mut settled = 0;
+        while let Some(QueueEntry { priority, node }) = queue.pop() {
+            if priority > lengths[node as usize] {
+                continue;
+            }
+            settled += 1;
+            if priority > max_length || settled > WITNESS_SETTLE_LIMIT {
+                break;
+            }
+            for (&next, &length) in self.out_edges[node as usize].iter() {
+                let next_length = priority + length;
+                if next != excluded && next_length < lengths[next as usize] {
+                    if lengths[next as usize] == f32::INFINITY {
+                        touched.push(next);
+                    }
+                    lengths[next as usize] = next_length;
+                    queue.push(QueueEntry {
+                        priority: next_length,
+                        node: next,
+                    });
+                }
+            }
+        }
+        let found = targets
+            .iter()
+            .map(|(target, _)| lengths[*target as usize])
+            .collect();
+        for node in touched {
+            lengths[node as usize] = f32::INFINITY;
+        }
+        found
+    }
+
+    /// Shortcuts (source, target, length) needed to keep shortest paths through `node` intact
+    /// once it is removed.
+    fn shortcuts(&mut self, node: u32) -> Vec<(u32, u32, f32)> {
+        let targets: Vec<(u32, f32)> = self.out_edges[node as usize]
+            .iter()
+            .map(|(t, l)| (*t, *l))
+            .collect();
+        let sources: Vec<(u32, f32)> = self.in_edges[node as usize]
+            .iter()
+            .map(|(s, l)| (*s, *l))
+            .collect();
+        let max_out = targets.iter().map(|(_, l)| *l).fold(0.0, f32::max);
+        let mut shortcuts = Vec::new();
+        for (source, in_length) in sources {
+            let witnesses = self.witness_lengths(source, node, &targets, in_length + max_out);
+            for ((target, out_length), witness) in targets.iter().zip(witnesses) {
+                let through = in_length + out_length;
+                if *target != source && through < witness {
+                    shortcuts.push((source, *target, through));
+                }
+            }
+        }
+        shortcuts
+    }
+
+    /// Contraction order priority: the edge difference, plus terms spreading contraction evenly
+    /// over the network and keeping the hierarchy shallow.
+    fn priority(&self, node: u32, shortcuts: usize) -> i64 {
+        let degree = self.out_edges[node as usize].len() + self.in_edges[node as usize].len();
+        2 * (shortcuts as i64 - degree as i64)
+            + self.contracted_neighbours[node as usize]
+            + self.levels[node as usize]
+    }
+}
+
+/// Forward or backward search through the hierarchy, recording how each node was reached.
+#[derive(Default)]
+struct UpwardSearch {
+    costs: HashMap<u32, f32>,
+    /// How each node was reached: from another hierarchy node, or from the route's end position
+    previous: HashMap<u32, Result<u32, PartialLeg>>,
+    queue: BinaryHeap<QueueEntry>,
+}
+
+impl UpwardSearch {
+    fn new(legs: Vec<PartialLeg>, index: &HashMap<u32, u32>) -> Self {
+        let mut search = UpwardSearch::default();
+        for leg in legs {
+            let Some(&node) = index.get(&leg.node) else {
+                continue;
+            };
+            if search.costs.get(&node).is_none_or(|c| leg.cost < *c) {
+                search.costs.insert(node, leg.cost);
+                search.previous.insert(node, Err(leg));
+                search.queue.push(QueueEntry {
+                    priority: leg.cost,
+                    node,
+                });
+            }
+        }
+        search
+    }
+
+    fn min_priority(&self) -> f32 {
+        self.queue
+            .peek()
+            .map_or(f32::INFINITY, |entry| entry.priority)
+    }
+
+    /// Settle the next node, returning it with its cost.
+    fn step(&mut self, edges: &[Vec<HierarchyEdge>]) -> Option<(u32, f32)> {
+        while let Some(QueueEntry { priority, node }) = self.queue.pop() {
+            if priority > self.costs[&node] {
+                continue;
+            }
+            for edge in edges[node as usize].iter() {
+                let next_cost = priority + edge.cost;
+                if self.costs.get(&edge.node).is_none_or(|c| next_cost < *c) {
+                    self.costs.insert(edge.node, next_cost);
+                    self.previous.insert(edge.node, Ok(node));
+                    self.queue.push(QueueEntry {
+                        priority: next_cost,
+                        node: edge.node,
+                    });
+                }
+            }
+            return Some((node, priority));
+        }
+        None
+    }
+
+    /// Hierarchy nodes from the search's origin to `node`, and the leg leaving the end position.
+    fn chain_to(&self, node: u32) -> (Vec<u32>, PartialLeg) {
+        let mut chain = vec![node];
+        loop {
+            match self.previous[chain.last().unwrap()] {
+                Ok(prev) => chain.push(prev),
+                Err(leg) => {
+                    chain.reverse();
+                    return (chain, leg);
+                }
+            }
+        }
+    }
+}
+
+impl ContractionHierarchy {
+    /// Contract the network's nodes, least important first, updating priorities lazily. With
+    /// `delays`, each edge also costs the expected wait at the node it leaves.
+    pub fn new(network: &StreetNetwork, delays: Option<&IntersectionDelays>) -> Self {
+        let node_ids = network.node_ids();
+        let index: HashMap<u32, u32> = (0..).zip(node_ids.iter()).map(|(i, id)| (*id, i)).collect();
+        let mut contraction = Contraction {
+            out_edges: vec![HashMap::new(); node_ids.len()],
+            in_edges: vec![HashMap::new(); node_ids.len()],
+            contracted_neighbours: vec![0; node_ids.len()],
+            levels: vec![0; node_ids.len()],
+            witness: vec![f32::INFINITY; node_ids.len()],
+        };
+        for edge in network.edge_list() {
+            let (Some(&u), Some(&v), Some(label)) =
+                (index.get(&edge.u), index.get(&edge.v), edge.label)
+            else {
+                continue;
+            };
+            if u == v {
+                continue;
+            }
+            let weight = label.len + network.delay_cost(edge.u, delays);
+            let length = contraction.out_edges[u as usize]
+                .get(&v)
+                .map_or(weight, |l| l.min(weight));
+            contraction.out_edges[u as usize].insert(v, length);
+            contraction.in_edges[v as usize].insert(u, length);
+        }
+
+        println!("Building contraction hierarchy...");
+        let pb = ProgressBar::new(node_ids.len() as u64);
+        let mut queue: BinaryHeap<(Reverse<i64>, u32)> = (0..node_ids.len() as u32)
+            .map(|node| {
+                let shortcuts = contraction.shortcuts(node).len();
+                (Reverse(contraction.priority(node, shortcuts)), node)
+            })
+            .collect();
+
+        let mut up = vec![Vec::new(); node_ids.len()];
+        let mut down = vec![Vec::new(); node_ids.len()];
+        let mut middles = HashMap::new();
+        while let Some((_, node)) = queue.pop() {
+            let shortcuts = contraction.shortcuts(node);
+            // Priorities go stale as neighbours are contracted; defer the node if it has fallen
+            // behind the next one
+            let priority = contraction.priority(node, shortcuts.len());
+            if let Some((Reverse(next), _)) = queue.peek() {
+                if priority > *next {
+                    queue.push((Reverse(priority), node));
+                    continue;
+                }
+            }
+            pb.inc(1);
+
+            let out_edges = std::mem::take(&mut contraction.out_edges[node as usize]);
+            let in_edges = std::mem::take(&mut contraction.in_edges[node as usize]);
+            for (&target, &length) in out_edges.iter() {
+                up[node as usize].push(HierarchyEdge {
+                    node: target,
+                    cost: length,
+                });
+                contraction.in_edges[target as usize].remove(&node);
+                contraction.contracted_neighbours[target as usize] += 1;
+                contraction.levels[target as usize] =
+                    contraction.levels[target as usize].max(contraction.levels[node as usize] + 1);
+            }
+            for (&source, &length) in in_edges.iter() {
+                down[node as usize].push(HierarchyEdge {
+                    node: source,
+                    cost: length,
+                });
+                contraction.out_edges[source as usize].remove(&node);
+                contraction.contracted_neighbours[source as usize] += 1;
+                contraction.levels[source as usize] =
+                    contraction.levels[source as usize].max(contraction.levels[node as usize] + 1);
+            }
+            for (source, target, length) in shortcuts {
+                if contraction.out_edges[source as usize]
+                    .get(&target)
+                    .is_some_and(|l| *l <= length)
+                {
+                    continue;
+                }
+                contraction.out_edges[source as usize].insert(target, length);
+                contraction.in_edges[target as usize].insert(source, length);
+                middles.insert((source, target), node);
+            }
+        }
+        pb.finish();
+
+        ContractionHierarchy {
+            node_ids,
+            index,
+            up,
+            down,
+            middles,
+            delays: delays.cloned(),
+        }
+    }
+
+    /// Expand an edge of the hierarchy into the network edges it stands for, as network node IDs.
+    fn unpack(&self, source: u32, target: u32, edges: &mut Vec<(u32, u32)>) {
+        match self.middles.get(&(source, target)) {
+            Some(&middle) => {
+                self.unpack(source, middle, edges);
+                self.unpack(middle, target, edges);
+            }
+            None => edges.push((
+                self.node_ids[source as usize],
+                self.node_ids[target as usize],
+            )),
+        }
+    }
+
+    /// Shortest route between two positions on the network the hierarchy was built from, as
+    /// `StreetNetwork::route_by_cost` would find it by length and the hierarchy's delays.
+    pub fn route(
+        &self,
+        network: &StreetNetwork,
+        from: &StreetNetworkPosition,
+        to: &StreetNetworkPosition,
+    ) -> Option<Route> {
+        let length = |label: &StreetEdgeLabel| label.len;
+        let mut forward = UpwardSearch::new(network.departures(from, &length), &self.index);
+        self.route_from(network, &mut forward, from, to)
+    }
+
+    /// Shortest routes from one position to each of several others. The upward search from the
+    /// start is run to completion once and shared by all of them, so each further destination only
+    /// costs a backward search.
+    pub fn one_to_many(
+        &self,
+        network: &StreetNetwork,
+        from: &StreetNetworkPosition,
+        targets: &[StreetNetworkPosition],
+    ) -> Vec<Option<Route>> {
+        let length = |label: &StreetEdgeLabel| label.len;
+        let mut forward = UpwardSearch::new(network.departures(from, &length), &self.index);
+        while forward.step(&self.up).is_some() {}
+        targets
+            .iter()
+            .map(|to| self.route_from(network, &mut forward, from, to))
+            .collect()
+    }
+
+    /// Search upwards from both ends, advancing whichever search is nearer its origin, until
+    /// neither can improve on the best meeting point found.
+    fn route_from(
+        &self,
+        network: &StreetNetwork,
+        forward: &mut UpwardSearch,
+        from: &StreetNetworkPosition,
+        to: &StreetNetworkPosition,
+    ) -> Option<Route> {
+        let length = |label: &StreetEdgeLabel| label.len;
+        let direct = network.direct_route(from, to, &length);
+        // The wait at the node a walk to the destination leaves is charged here, as no hierarchy
+        // edge leaves it
+        let arrivals = network
+            .arrivals(to, &length)
+            .into_iter()
+            .map(|leg| PartialLeg {
+                cost: leg.cost + network.delay_cost(leg.node, self.delays.as_ref()),
+                ..leg
+            })
+            .collect();
+        let mut backward = UpwardSearch::new(arrivals, &self.index);
+
+        let mut best: Option<(u32, f32)> = None;
+        let mut bound = direct.as_ref().map_or(f32::INFINITY, |route| route.cost);
+        loop {
+            let (forward_min, backward_min) = (forward.min_priority(), backward.min_priority());
+            if forward_min.min(backward_min) >= bound {
+                break;
+            }
+            let (settled, other) = if forward_min <= backward_min {
+                (forward.step(&self.up), &backward)
+            } else {
+                (backward.step(&self.down), &*forward)
+            };
+            let Some((node, settled_cost)) = settled else {
+                continue;
+            };
+            if let Some(other_cost) = other.costs.get(&node) {
+                let total = settled_cost + other_cost;
+                if total < bound {
+                    best = Some((node, total));
+                    bound = total;
+                }
+            }
+        }
+
+        let Some((meeting, total)) = best else {
+            return direct;
+        };
+        let (up_chain, departure) = forward.chain_to(meeting);
+        let (down_chain, arrival) = backward.chain_to(meeting);
+
+        let mut walked = Vec::new();
+        for pair in up_chain.windows(2) {
+            self.unpack(pair[0], pair[1], &mut walked);
+        }
+        for pair in down_chain.windows(2).rev() {
+            self.unpack(pair[1], pair[0], &mut walked);
+        }
+        let mut nodes: Vec<u32> = walked.iter().map(|(u, _)| *u).collect();
+        nodes.push(self.node_ids[*down_chain.first().unwrap() as usize]);
+        // The cost may include waits, so the length is summed separately
+        let length = departure.length
+            + walked
+                .iter()
+                .filter_map(|(u, v)| network.shortest_edge_length(*u, *v))
+                .sum::<f32>()
+            + arrival.length;
+
+        let mut edges = Vec::with_capacity(walked.len() + 2);
+        if departure.length > 0.0 {
+            edges.push(departure.edge);
+        }
+        edges.extend(walked);
+        if arrival.length > 0.0 {
+            edges.push(arrival.edge);
+        }
+
+        Some(Route {
+            edges,
+            nodes,
+            length,
+            cost: total,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use krabmaga::engine::fields::field::Field;
+    use krabmaga::engine::fields::network::{EdgeOptions, Network};
+    use krabmaga::rand::{rngs::StdRng, SeedableRng};
+
+    use super::*;
+    use crate::model::urban_network::test_support::{
+        assert_close, assert_connected, organic_network, random_pairs,
+    };
+    use crate::model::urban_network::{NodeControl, RoutingAlgorithm, StreetNode};
+
+    /// The network with signals at every third node and marked crossings at the next.
+    fn with_controls(network: &StreetNetwork) -> StreetNetwork {
+        let controlled = |id: u32| {
+            let node = network.0.get_object(id).unwrap();
+            let control = match node.osm_id % 3 {
+                0 => NodeControl::TrafficSignals,
+                1 => NodeControl::MarkedCrossing,
+                _ => NodeControl::None,
+            };
+            StreetNode { control, ..node }
+        };
+        let mut copy = Network::new(true);
+        for id in network.node_ids() {
+            copy.add_node(controlled(id));
+        }
+        for edge in network.edge_list() {
+            let label = edge.label.unwrap();
+            let len = label.len;
+            copy.add_edge(
+                controlled(edge.u),
+                controlled(edge.v),
+                EdgeOptions::WeightedLabeled(label, len),
+            );
+        }
+        copy.lazy_update();
+        StreetNetwork(copy)
+    }
+
+    #[test]
+    fn routes_match_dijkstra() {
+        let mut rng = StdRng::seed_from_u64(23);
+        let network = organic_network(&mut rng);
+        let hierarchy = ContractionHierarchy::new(&network, None);
+        for (from, to) in random_pairs(&network, 200, &mut rng) {
+            let expected = network
+                .route(&from, &to, RoutingAlgorithm::Dijkstra, None)
+                .unwrap();
+            let route = hierarchy.route(&network, &from, &to).unwrap();
+            assert_close(route.length, expected.length);
+            assert_close(route.cost, expected.cost);
+            // Shortcuts unpack into the network edges they stand for
+            assert_connected(&route);
+        }
+    }
+
+    #[test]
+    fn routes_charge_waits_as_dijkstra_does() {
+        let mut rng = StdRng::seed_from_u64(23);
+        let network = with_controls(&organic_network(&mut rng));
+        let delays = IntersectionDelays::default();
+        let hierarchy = ContractionHierarchy::new(&network, Some(&delays));
+        for (from, to) in random_pairs(&network, 200, &mut rng) {
+            let expected = network
+                .route(&from, &to, RoutingAlgorithm::Dijkstra, Some(&delays))
+                .unwrap();
+            let route = hierarchy.route(&network, &from, &to).unwrap();
+            assert_close(route.cost, expected.cost);
+            assert_close(route.length, expected.length);
+            assert_eq!(route.nodes, expected.nodes);
+        }
+    }
+
+    #[test]
+    fn one_to_many_matches_dijkstra() {
+        let mut rng = StdRng::seed_from_u64(23);
+        let network = organic_network(&mut rng);
+        let hierarchy = ContractionHierarchy::new(&network, None);
+        for _ in 0..5 {
+            let pairs = random_pairs(&network, 40, &mut rng);
+            let from = pairs[0].0;
+            let targets: Vec<StreetNetworkPosition> = pairs.iter().map(|(_, to)| *to).collect();
+            let routes = hierarchy.one_to_many(&network, &from, &targets);
+            assert_eq!(routes.len(), targets.len());
+            for (to, route) in targets.iter().zip(routes) {
+                let expected = network
+                    .route(&from, to, RoutingAlgorithm::Dijkstra, None)
+                    .unwrap();
+                assert_close(route.unwrap().length, expected.length);
+            }
+        }
+    }
+}
@@ -2,39 +2,73 @@ use std::{
     collections::{HashMap, HashSet},
     fmt::Display,
     hash::Hash,
-    path::Path,
+    path::{Path, PathBuf},
 };
 
-use geo::{HaversineDistance, Point};
+use geo::{BoundingRect, Coord, HaversineDistance, MultiPolygon, Point};
 use indicatif::ProgressBar;
-use krabmaga::engine::{
-    fields::network::{Edge, EdgeOptions},
-    location::Real2D,
-};
-use osmpbf::{BlobReader, Element, HeaderBBox, IndexedReader};
+use krabmaga::engine::fields::network::{Edge, EdgeOptions};
+use osmpbf::{Blob, BlobDecode, BlobReader, HeaderBBox};
+use rayon::prelude::*;
 use serde::{Deserialize, Serialize};
 
-use crate::model::urban_network::{edge::StreetEdgeLabel, node::StreetNode};
+use crate::model::error::{ErrorPolicy, ImportError, StreetNetworkError};
+use crate::model::urban_network::{
+    boundary::clip_segment,
+    components::Connectivity,
+    edge::{HighwayClass, Sidewalk, StreetEdgeLabel},
+    filter::WalkabilityFilter,
+    intersection::IntersectionDelays,
+    node::{NodeControl, StreetNode},
+    osm_xml::read_osm_xml,
+    projection::LocalProjection,
+    report::ImportReport,
+};
 
 #[derive(Clone, Copy, PartialEq, Eq, Hash, Debug, Serialize, Deserialize)]
 pub struct OsmNodeInfo {
     id: i64,
     nano_lat: i64,
     nano_lon: i64,
+    control: NodeControl,
 }
 impl OsmNodeInfo {
     const NANO_DIVISOR: f64 = 1.0e9;
-}
 
-impl From<OsmNodeInfo> for StreetNode {
-    fn from(val: OsmNodeInfo) -> Self {
-        StreetNode::new(
-            val.id,
-            Real2D {
-                x: (val.nano_lon as f64 / OsmNodeInfo::NANO_DIVISOR) as f32,
-                y: (val.nano_lat as f64 / OsmNodeInfo::NANO_DIVISOR) as f32,
-            },
-        )
+    pub(crate) fn from_lon_lat(id: i64, lon: f64, lat: f64) -> Self {
+        OsmNodeInfo {
+            id,
+            nano_lat: (lat * OsmNodeInfo::NANO_DIVISOR).round() as i64,
+            nano_lon: (lon * OsmNodeInfo::NANO_DIVISOR).round() as i64,
+            control: NodeControl::None,
+        }
+    }
+
+    pub(crate) fn with_control(self, control: NodeControl) -> Self {
+        OsmNodeInfo { control, ..self }
+    }
+
+    pub(crate) fn coord(&self) -> Coord<f64> {
+        Coord {
+            x: self.lon(),
+            y: self.lat(),
+        }
+    }
+
+    pub fn lon(&self) -> f64 {
+        self.nano_lon as f64 / OsmNodeInfo::NANO_DIVISOR
+    }
+
+    pub fn lat(&self) -> f64 {
+        self.nano_lat as f64 / OsmNodeInfo::NANO_DIVISOR
+    }
+
+    /// Convert to a street node located in the projected (metric) coordinate system.
+    pub fn to_street_node(self, projection: &LocalProjection) -> StreetNode {
+        StreetNode {
+            control: self.control,
+            ..StreetNode::new(self.id, projection.project(self.lon(), self.lat()))
+        }
     }
 }
 
@@ -43,6 +77,127 @@ pub struct OsmSegmentInfo {
     u_id: i64,
     v_id: i64,
     length: f64,
+    /// Position of the segment along its way as mapped, kept when clipping drops earlier segments
+    index: u32,
+}
+
+/// The subset of OSM way tags retained through import.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct OsmWayTags {
+    pub highway: Option<String>,
+    pub name: Option<String>,
+    pub sidewalk: Option<String>,
+    pub surface: Option<String>,
+    pub lit: Option<String>,
+    pub width: Option<String>,
+    pub incline: Option<String>,
+    pub oneway_foot: Option<String>,
+    pub maxspeed: Option<String>,
+    pub area: Option<String>,
+}
+
+impl OsmWayTags {
+    pub fn from_tags<'a, I>(tags: I) -> Self
+    where
+        I: IntoIterator<Item = (&'a str, &'a str)>,
+    {
+        let mut way_tags = OsmWayTags::default();
+        for (key, value) in tags {
+            let slot = match key {
+                "highway" => &mut way_tags.highway,
+                "name" => &mut way_tags.name,
+                "sidewalk" => &mut way_tags.sidewalk,
+                "surface" => &mut way_tags.surface,
+                "lit" => &mut way_tags.lit,
+                "width" => &mut way_tags.width,
+                "incline" => &mut way_tags.incline,
+                "oneway:foot" => &mut way_tags.oneway_foot,
+                "maxspeed" => &mut way_tags.maxspeed,
+                "area" => &mut way_tags.area,
+                _ => continue,
+            };
+            *slot = Some(value.to_string());
+        }
+        way_tags
+    }
+
+    /// Build an edge label of the given length for the `segment`th segment of a way, carrying
+    /// these tags as typed fields. Fails if the way and segment do not fit a segment ID.
+    pub fn to_edge_label(
+        &self,
+        len: f32,
+        way_id: i64,
+        segment: u32,
+    ) -> Result<StreetEdgeLabel, ImportError> {
+        Ok(StreetEdgeLabel {
+            len,
+            id: StreetEdgeLabel::segment_id(way_id, segment)?,
+            way_id,
+            segment,
+            highway: self
+                .highway
+                .as_deref()
+                .map(HighwayClass::from_osm)
+                .unwrap_or_default(),
+            name: self.name.clone(),
+            sidewalk: self
+                .sidewalk
+                .as_deref()
+                .map(Sidewalk::from_osm)
+                .unwrap_or_default(),
+            surface: self.surface.clone(),
+            lit: self.lit.as_deref().and_then(parse_osm_bool),
+            width: self.width.as_deref().and_then(parse_width),
+            incline: self.incline.as_deref().and_then(parse_incline),
+            oneway_foot: self.oneway_foot.as_deref().and_then(parse_osm_bool) == Some(true),
+            maxspeed: self.maxspeed.as_deref().and_then(parse_maxspeed),
+            geometry: Vec::new(),
+        })
+    }
+}
+
+fn parse_osm_bool(value: &str) -> Option<bool> {
+    match value {
+        "yes" | "true" | "1" => Some(true),
+        "no" | "false" | "0" => Some(false),
+        _ => None,
+    }
+}
+
+/// Leading numeric part of a tag value, e.g. `"3.5 m"` -> `(3.5, " m")`.
+fn split_number(value: &str) -> Option<(f32, &str)> {
+    let value = value.trim();
+    let end = value
+        .find(|c: char| !(c.is_ascii_digit() || c == '.' || c == '-' || c == '+'))
+        .unwrap_or(value.len());
+    value[..end]
+        .parse::<f32>()
+        .ok()
+        .map(|n| (n, value[end..].trim()))
+}
+
+fn parse_width(value: &str) -> Option<f32> {
+    match split_number(value)? {
+        (n, "" | "m") => Some(n),
+        (n, "ft" | "'") => Some(n * 0.3048),
+        _ => None,
+    }
+}
+
+fn parse_incline(value: &str) -> Option<f32> {
+    match split_number(value)? {
+        (n, "%" | "") => Some(n),
+        (n, "°") => Some(n.to_radians().tan() * 100.0),
+        _ => None,
+    }
+}
+
+fn parse_maxspeed(value: &str) -> Option<f32> {
+    match split_number(value)? {
+        (n, "" | "km/h" | "kmh") => Some(n),
+        (n, "mph") => Some(n * 1.609_344),
+        _ => None,
+    }
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
@@ -50,6 +205,7 @@ pub struct OsmWayInfo {
     id: i64,
     node_ids: Vec<i64>,
     segments: Vec<OsmSegmentInfo>,
+    tags: OsmWayTags,
 }
 
 pub struct EdgeSpec<L: Clone + Hash + Display> {
@@ -59,37 +215,148 @@ pub struct EdgeSpec<L: Clone + Hash + Display> {
 }
 
 impl OsmWayInfo {
+    /// A way through the given nodes, split into node-to-node segments whose lengths are filled in
+    /// once node locations are known. Ways with fewer than two nodes are degenerate.
+    pub fn new(id: i64, node_ids: Vec<i64>, tags: OsmWayTags) -> Result<Self, ImportError> {
+        if node_ids.len() < 2 {
+            return Err(ImportError::DegenerateWay {
+                way_id: id,
+                reason: format!("{} node reference(s)", node_ids.len()),
+            });
+        }
+        let segments = (0..)
+            .zip(node_ids.windows(2))
+            .map(|(index, pair)| OsmSegmentInfo {
+                u_id: pair[0],
+                v_id: pair[1],
+                length: -1.0,
+                index,
+            })
+            .collect();
+        Ok(OsmWayInfo {
+            id,
+            node_ids,
+            segments,
+            tags,
+        })
+    }
+
+    pub fn id(&self) -> i64 {
+        self.id
+    }
+
+    pub fn node_ids(&self) -> &[i64] {
+        &self.node_ids
+    }
+
+    /// Copies of the way made up of the given segments, one for each run of segments that join end
+    /// to start, with node lists following each run.
+    fn split_into_runs(&self, segments: Vec<OsmSegmentInfo>) -> Vec<OsmWayInfo> {
+        let mut runs: Vec<OsmWayInfo> = Vec::new();
+        for seg in segments {
+            match runs.last_mut() {
+                Some(run) if run.node_ids.last() == Some(&seg.u_id) => {
+                    run.node_ids.push(seg.v_id);
+                    run.segments.push(seg);
+                }
+                _ => runs.push(OsmWayInfo {
+                    id: self.id,
+                    node_ids: vec![seg.u_id, seg.v_id],
+                    segments: vec![seg],
+                    tags: self.tags.clone(),
+                }),
+            }
+        }
+        // A closed way cut open continues from its last run into its first
+        if runs.len() > 1 && runs.last().unwrap().node_ids.last() == runs[0].node_ids.first() {
+            let mut last = runs.pop().unwrap();
+            last.node_ids.extend_from_slice(&runs[0].node_ids[1..]);
+            last.segments.append(&mut runs[0].segments);
+            runs[0] = last;
+        }
+        runs
+    }
+
+    /// True for a pedestrian square mapped as a closed `highway=pedestrian` + `area=yes` way.
+    pub fn is_plaza(&self) -> bool {
+        self.tags.highway.as_deref() == Some("pedestrian")
+            && self.tags.area.as_deref() == Some("yes")
+            && self.node_ids.len() >= 4
+            && self.node_ids.first() == self.node_ids.last()
+    }
+
+    /// Add a segment of known length between two of the way's nodes, numbered after the existing
+    /// ones; used to link nodes across a plaza.
+    pub(crate) fn add_segment(&mut self, u_id: i64, v_id: i64, length: f64) {
+        let index = self
+            .segments
+            .iter()
+            .map(|seg| seg.index + 1)
+            .max()
+            .unwrap_or(0);
+        self.segments.push(OsmSegmentInfo {
+            u_id,
+            v_id,
+            length,
+            index,
+        });
+    }
+
+    /// Build edge specs for every segment of the way. Pedestrians may walk a street in either
+    /// direction, so each segment yields a reverse edge as well unless tagged `oneway:foot=yes`.
+    ///
+    /// Segments referring to nodes missing from `osm_id_node_map` fail the conversion under
+    /// `ErrorPolicy::Fail`, and are otherwise skipped and recorded in `skipped`.
     pub fn as_edge_specs(
         &self,
         osm_id_node_map: &HashMap<i64, StreetNode>,
-    ) -> Vec<EdgeSpec<StreetEdgeLabel>> {
-        let edges: Vec<EdgeSpec<StreetEdgeLabel>> = self
-            .segments
-            .iter()
-            .map(|seg| {
-                let edge_options = EdgeOptions::WeightedLabeled(
-                    StreetEdgeLabel {
-                        len: seg.length as f32,
-                        id: self.id as u32,
-                    },
-                    seg.length as f32,
-                );
-                let u_node = *osm_id_node_map.get(&seg.u_id).expect(&format!(
-                    "No corresponding network node ID found for OSM ID {}",
-                    &seg.u_id
-                ));
-                let v_node = *osm_id_node_map.get(&seg.v_id).expect(&format!(
-                    "No corresponding network node ID found for OSM ID {}",
-                    &seg.u_id
-                ));
-                EdgeSpec {
-                    u: u_node,
-                    v: v_node,
-                    options: edge_options,
-                }
-            })
-            .collect();
-        edges
+        policy: ErrorPolicy,
+        skipped: &mut Vec<ImportError>,
+    ) -> Result<Vec<EdgeSpec<StreetEdgeLabel>>, ImportError> {
+        let mut edges: Vec<EdgeSpec<StreetEdgeLabel>> = Vec::with_capacity(self.segments.len() * 2);
+        for seg in self.segments.iter() {
+            let lookup = |node_id: i64| {
+                osm_id_node_map
+                    .get(&node_id)
+                    .copied()
+                    .ok_or(ImportError::MissingNodeRef {
+                        way_id: self.id,
+                        node_id,
+                    })
+            };
+            let edge = lookup(seg.u_id).and_then(|u| {
+                let v = lookup(seg.v_id)?;
+                let label = self
+                    .tags
+                    .to_edge_label(seg.length as f32, self.id, seg.index)?;
+                Ok((u, v, label))
+            });
+            let (u_node, v_node, label) = match edge {
+                Ok(edge) => edge,
+                Err(e) => match policy {
+                    ErrorPolicy::Fail => return Err(e),
+                    ErrorPolicy::SkipAndReport => {
+                        skipped.push(e);
+                        continue;
+                    }
+                },
+            };
+
+            let reverse_label = (!label.oneway_foot).then(|| label.reversed());
+            edges.push(EdgeSpec {
+                u: u_node,
+                v: v_node,
+                options: EdgeOptions::WeightedLabeled(label, seg.length as f32),
+            });
+            if let Some(reverse_label) = reverse_label {
+                edges.push(EdgeSpec {
+                    u: v_node,
+                    v: u_node,
+                    options: EdgeOptions::WeightedLabeled(reverse_label, seg.length as f32),
+                });
+            }
+        }
+        Ok(edges)
     }
 }
 
@@ -103,124 +370,725 @@ pub struct HeaderBBoxDef {
     bottom: f64,
 }
 
+/// Serde adapter for an optional `HeaderBBox`, for use with `#[serde(with = "header_bbox_option")]`.
+pub mod header_bbox_option {
+    use osmpbf::HeaderBBox;
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    use super::HeaderBBoxDef;
+
+    #[derive(Serialize, Deserialize)]
+    struct Wrapper(#[serde(with = "HeaderBBoxDef")] HeaderBBox);
+
+    pub fn serialize<S: Serializer>(
+        bbox: &Option<HeaderBBox>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        bbox.clone().map(Wrapper).serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Option<HeaderBBox>, D::Error> {
+        Ok(Option::<Wrapper>::deserialize(deserializer)?.map(|Wrapper(bbox)| bbox))
+    }
+}
+
 #[derive(Serialize, Deserialize)]
 pub struct OsmNetworkComponents {
-    pub nodes: HashSet<OsmNodeInfo>,
+    /// Nodes referenced by the loaded ways, keyed by OSM ID
+    pub nodes: HashMap<i64, OsmNodeInfo>,
     pub ways: Vec<OsmWayInfo>,
+    /// Problems found while reading, e.g. ways with too few nodes to form a segment
+    pub issues: Vec<ImportError>,
+    /// IDs of highway-tagged ways rejected by the walkability filter
+    pub filtered_ways: HashSet<i64>,
+    /// IDs of ways deleted in this input or rejected by the walkability filter, which `merge`
+    /// removes from the inputs layered beneath it
+    pub removed_ways: HashSet<i64>,
 
-    #[serde(with = "HeaderBBoxDef")]
-    pub bounding_box: HeaderBBox,
+    /// Extent declared by the input or fitted to its nodes, or `None` if it has neither
+    #[serde(with = "header_bbox_option")]
+    pub bounding_box: Option<HeaderBBox>,
 }
 
 impl OsmNetworkComponents {
     pub fn new() -> Self {
         OsmNetworkComponents {
-            nodes: HashSet::new(),
+            nodes: HashMap::new(),
             ways: Vec::new(),
-            bounding_box: HeaderBBox {
+            issues: Vec::new(),
+            filtered_ways: HashSet::new(),
+            removed_ways: HashSet::new(),
+            bounding_box: None,
+        }
+    }
+
+    /// Bounding box of the loaded nodes, or `None` if no nodes were loaded.
+    pub fn node_extent(&self) -> Option<HeaderBBox> {
+        if self.nodes.is_empty() {
+            return None;
+        }
+        Some(self.nodes.values().fold(
+            HeaderBBox {
+                left: f64::MAX,
+                right: f64::MIN,
+                top: f64::MIN,
+                bottom: f64::MAX,
+            },
+            |bbox, n| HeaderBBox {
+                left: bbox.left.min(n.lon()),
+                right: bbox.right.max(n.lon()),
+                top: bbox.top.max(n.lat()),
+                bottom: bbox.bottom.min(n.lat()),
+            },
+        ))
+    }
+}
+
+impl OsmNetworkComponents {
+    /// Report on the extract as loaded. Network counts are left at zero, to be filled in with
+    /// `ImportReport::record_network` once the network is built.
+    pub fn report(&self) -> ImportReport {
+        let mut seen_pairs: HashSet<(i64, i64)> = HashSet::new();
+        let mut report = ImportReport {
+            osm_nodes: self.nodes.len(),
+            osm_ways: self.ways.len(),
+            filtered_ways: self.filtered_ways.len(),
+            degenerate_ways: self
+                .issues
+                .iter()
+                .filter(|issue| matches!(issue, ImportError::DegenerateWay { .. }))
+                .count(),
+            segments: 0,
+            total_length: 0.0,
+            dangling_references: 0,
+            zero_length_segments: 0,
+            duplicate_segments: 0,
+            plazas: 0,
+            plaza_links: 0,
+            nodes: 0,
+            edges: 0,
+            self_loops: 0,
+            bounding_box: self.bounding_box.clone().unwrap_or(HeaderBBox {
                 left: 0.0,
                 right: 0.0,
                 top: 0.0,
                 bottom: 0.0,
-            },
+            }),
+            crossings: 0,
+            signalised_nodes: 0,
+        };
+
+        for seg in self.ways.iter().flat_map(|way| way.segments.iter()) {
+            report.segments += 1;
+            report.dangling_references += [seg.u_id, seg.v_id]
+                .iter()
+                .filter(|id| !self.nodes.contains_key(id))
+                .count();
+            // Lengths of segments with dangling references are never computed
+            if seg.length == 0.0 {
+                report.zero_length_segments += 1;
+            } else if seg.length > 0.0 {
+                report.total_length += seg.length;
+            }
+            if !seen_pairs.insert((seg.u_id.min(seg.v_id), seg.u_id.max(seg.v_id))) {
+                report.duplicate_segments += 1;
+            }
         }
+        report
     }
 }
 
-pub fn read_osm(filepath: &Path) -> Result<OsmNetworkComponents, osmpbf::Error> {
-    let mut bbox: HeaderBBox;
+impl OsmNetworkComponents {
+    /// Merge another extract's components into these, with `other` taking precedence for nodes and
+    /// ways present in both. Ways that `other` deletes or filters out are dropped.
+    pub fn merge(&mut self, other: OsmNetworkComponents) {
+        let replaced: HashSet<i64> = other.ways.iter().map(|way| way.id).collect();
+        self.ways
+            .retain(|way| !replaced.contains(&way.id) && !other.removed_ways.contains(&way.id));
+        self.ways.extend(other.ways);
+        self.ways.sort_unstable_by_key(|way| way.id);
+        self.nodes.extend(other.nodes);
+        self.issues.extend(other.issues);
+        // A way removed here but restored by `other` is live again, and one filtered out of several
+        // inputs is only counted once
+        self.filtered_ways.retain(|id| !replaced.contains(id));
+        self.filtered_ways.extend(other.filtered_ways);
+        self.removed_ways.retain(|id| !replaced.contains(id));
+        self.removed_ways.extend(other.removed_ways);
+        self.bounding_box = match (self.bounding_box.take(), other.bounding_box) {
+            (Some(a), Some(b)) => Some(HeaderBBox {
+                left: a.left.min(b.left),
+                right: a.right.max(b.right),
+                top: a.top.max(b.top),
+                bottom: a.bottom.min(b.bottom),
+            }),
+            (a, b) => a.or(b),
+        };
+    }
 
-    if let Ok(reader) = BlobReader::from_path(filepath) {
-        for blob_res in reader {
-            if let Ok(hblock) = blob_res.and_then(|blob| blob.to_headerblock()) {
-                bbox = hblock.bbox().expect("File should contain bounding box")
+    /// Compute the haversine length of every segment whose nodes are both loaded.
+    pub fn compute_segment_lengths(&mut self) {
+        let nodes = &self.nodes;
+        self.ways.par_iter_mut().for_each(|way| {
+            for segment in way.segments.iter_mut() {
+                // Segments with missing nodes are reported when the way is converted to edges
+                if let (Some(u), Some(v)) = (nodes.get(&segment.u_id), nodes.get(&segment.v_id)) {
+                    segment.length = haversine_length(u, v);
+                }
             }
-        }
-    };
+        });
+    }
+}
 
-    match IndexedReader::from_path(filepath) {
-        Ok(mut reader) => {
-            let mut components = OsmNetworkComponents::new();
-            let mut local_node_index: HashMap<i64, OsmNodeInfo> = HashMap::new();
-
-            let res = reader.read_ways_and_deps(
-                |_| true,
-                |element| match element {
-                    Element::Node(n) => {
-                        let new_node = OsmNodeInfo {
-                            id: n.id(),
-                            nano_lat: n.nano_lat(),
-                            nano_lon: n.nano_lon(),
-                        };
-                        local_node_index.insert(n.id(), new_node.clone());
-                        components.nodes.insert(new_node);
-                    }
-                    Element::DenseNode(n) => {
-                        let new_node = OsmNodeInfo {
-                            id: n.id(),
-                            nano_lat: n.nano_lat(),
-                            nano_lon: n.nano_lon(),
-                        };
-                        local_node_index.insert(n.id(), new_node.clone());
-                        components.nodes.insert(new_node);
-                    }
-                    Element::Way(w) => {
-                        // Get segment info
-                        let mut segments = Vec::<OsmSegmentInfo>::new();
-                        let node_ids = w.refs();
-                        match node_ids.clone().next() {
-                            None => {
-                                print!("Unable to load edge {}: no node references found", w.id());
-                            }
-                            Some(_) => {
-                                let node_pairs = w.refs().zip(node_ids.clone().skip(1));
-                                node_pairs.for_each(|(u, v)| {
-                                    segments.push(OsmSegmentInfo {
-                                        u_id: u,
-                                        v_id: v,
-                                        length: -1.0,
-                                    });
-                                });
-                                components.ways.push(OsmWayInfo {
-                                    id: w.id(),
-                                    node_ids: node_ids.collect(),
-                                    segments,
-                                })
-                            }
-                        }
-                    }
-                    Element::Relation(_) => {}
-                },
-            );
-
-            // Wrap processing step in progress bar
-            println!("{}", "Processing way segment lengths...");
-            let pb = ProgressBar::new(components.ways.len() as u64);
-
-            pb.wrap_iter(components.ways.iter_mut()).for_each(|edge| {
-                edge.segments.iter_mut().for_each(|segment| {
-                    if let Some(u) = local_node_index.get(&segment.u_id) {
-                        if let Some(v) = local_node_index.get(&segment.v_id) {
-                            let segment_dist = Point::new(
-                                (u.nano_lon as f64 / 1e9_f64),
-                                (u.nano_lat as f64 / 1e9_f64),
-                            )
-                            .haversine_distance(&Point::new(
-                                (v.nano_lon as f64 / 1e9_f64),
-                                (v.nano_lat as f64 / 1e9_f64),
-                            ));
+impl OsmNetworkComponents {
+    /// First ID handed to nodes created during import (e.g. where a boundary cuts a segment).
+    /// Counts downward, well clear of both OSM IDs and the negative IDs used by unsaved JOSM edits.
+    pub const SYNTHETIC_NODE_ID_BASE: i64 = -(1 << 60);
 
-                            segment.length = segment_dist;
+    /// Clip the extract to a lon/lat boundary: nodes outside it are dropped, and segments crossing it
+    /// are cut at the boundary, ending at new nodes placed on the boundary line. A way that leaves
+    /// and re-enters the boundary is split into one way per piece left inside. Cut segments keep
+    /// their index along the way; where a single segment leaves and re-enters the boundary, its
+    /// later pieces are numbered after the way's last segment so that segment IDs stay unique.
+    pub fn clip_to(&mut self, boundary: &MultiPolygon<f64>) {
+        let mut next_synthetic_id = Self::SYNTHETIC_NODE_ID_BASE;
+        let mut kept_nodes: HashMap<i64, OsmNodeInfo> = HashMap::new();
+
+        println!("Clipping ways to boundary...");
+        let pb = ProgressBar::new(self.ways.len() as u64);
+        let mut clipped_ways = Vec::with_capacity(self.ways.len());
+        for way in pb.wrap_iter(std::mem::take(&mut self.ways).into_iter()) {
+            let mut clipped_segments = Vec::with_capacity(way.segments.len());
+            let mut next_index = way
+                .segments
+                .iter()
+                .map(|seg| seg.index + 1)
+                .max()
+                .unwrap_or(0);
+            for seg in way.segments.iter() {
+                let (Some(u), Some(v)) = (self.nodes.get(&seg.u_id), self.nodes.get(&seg.v_id))
+                else {
+                    // Leave dangling references for edge construction to report
+                    clipped_segments.push(seg.clone());
+                    continue;
+                };
+                let (a, b) = (u.coord(), v.coord());
+
+                for (piece, (t_start, t_end)) in
+                    clip_segment(boundary, a, b).into_iter().enumerate()
+                {
+                    let mut endpoint = |t: f64, original: &OsmNodeInfo| {
+                        if t == 0.0 || t == 1.0 {
+                            *original
                         } else {
-                            print!("Failed to calculate distance on segment {} - {}: node {} not found.", segment.u_id, segment.v_id, segment.v_id)
+                            next_synthetic_id -= 1;
+                            OsmNodeInfo::from_lon_lat(
+                                next_synthetic_id,
+                                a.x + (b.x - a.x) * t,
+                                a.y + (b.y - a.y) * t,
+                            )
                         }
+                    };
+                    let start = endpoint(t_start, u);
+                    let end = endpoint(t_end, v);
+
+                    kept_nodes.insert(start.id, start);
+                    kept_nodes.insert(end.id, end);
+                    clipped_segments.push(OsmSegmentInfo {
+                        u_id: start.id,
+                        v_id: end.id,
+                        length: haversine_length(&start, &end),
+                        index: if piece == 0 {
+                            seg.index
+                        } else {
+                            next_index += 1;
+                            next_index - 1
+                        },
+                    });
+                }
+            }
+            clipped_ways.extend(way.split_into_runs(clipped_segments));
+        }
+        self.ways = clipped_ways;
+        self.nodes = kept_nodes;
+
+        if let Some(rect) = boundary.bounding_rect() {
+            self.bounding_box = Some(HeaderBBox {
+                left: rect.min().x,
+                right: rect.max().x,
+                top: rect.max().y,
+                bottom: rect.min().y,
+            });
+        }
+    }
+}
+
+fn haversine_length(u: &OsmNodeInfo, v: &OsmNodeInfo) -> f64 {
+    Point::new(u.lon(), u.lat()).haversine_distance(&Point::new(v.lon(), v.lat()))
+}
+
+/// Options controlling how an OSM extract is turned into a street network.
+#[derive(Clone, Debug, Default)]
+pub struct ImportOptions {
+    /// Predicate selecting which ways become street edges
+    pub walkability: WalkabilityFilter,
+    /// Lon/lat study-area boundary; when set, the network is clipped to it
+    pub boundary: Option<MultiPolygon<f64>>,
+    /// Whether to fail on, or skip and report, missing node references and degenerate ways
+    pub on_error: ErrorPolicy,
+    /// Collapse interstitial degree-2 nodes so each block becomes a single edge
+    pub simplify: bool,
+    /// Directory for caching imported networks; caching is disabled when unset
+    pub cache_dir: Option<PathBuf>,
+    /// Keep only the largest component under this notion of connectivity
+    pub largest_component: Option<Connectivity>,
+    /// Waits agents face at the network's crossings and signals, which the contraction hierarchy
+    /// charges too
+    pub intersection_delays: IntersectionDelays,
+    /// Build a contraction hierarchy over the finished network for fast routing, caching it with
+    /// the network
+    pub contraction_hierarchy: bool,
+}
+
+/// Walkable ways found in one blob of a PBF file, with the header bounding box if the blob is the
+/// file's header.
+#[derive(Default)]
+struct BlobWays {
+    ways: Vec<OsmWayInfo>,
+    issues: Vec<ImportError>,
+    filtered_ways: Vec<i64>,
+    removed_ways: Vec<i64>,
+    header_bbox: Option<HeaderBBox>,
+}
+
+impl BlobWays {
+    fn merge(mut self, other: BlobWays) -> BlobWays {
+        self.ways.extend(other.ways);
+        self.issues.extend(other.issues);
+        self.filtered_ways.extend(other.filtered_ways);
+        self.removed_ways.extend(other.removed_ways);
+        self.header_bbox = self.header_bbox.or(other.header_bbox);
+        self
+    }
+}
+
+fn ways_in_blob(blob: &Blob, filter: &WalkabilityFilter) -> Result<BlobWays, osmpbf::Error> {
+    let mut found = BlobWays::default();
+    match blob.decode()? {
+        BlobDecode::OsmHeader(header) => found.header_bbox = header.bbox(),
+        BlobDecode::OsmData(block) => {
+            for way in block.groups().flat_map(|group| group.ways()) {
+                if !filter.accepts(way.tags()) {
+                    if way.tags().any(|(key, _)| key == "highway") {
+                        found.filtered_ways.push(way.id());
+                        found.removed_ways.push(way.id());
                     }
+                    continue;
+                }
+                match OsmWayInfo::new(
+                    way.id(),
+                    way.refs().collect(),
+                    OsmWayTags::from_tags(way.tags()),
+                ) {
+                    Ok(info) => found.ways.push(info),
+                    Err(e) => found.issues.push(e),
+                }
+            }
+        }
+        BlobDecode::Unknown(_) => {}
+    }
+    Ok(found)
+}
+
+/// Nodes in one blob whose IDs appear in `wanted`, which must be sorted.
+fn nodes_in_blob(blob: &Blob, wanted: &[i64]) -> Result<Vec<OsmNodeInfo>, osmpbf::Error> {
+    let BlobDecode::OsmData(block) = blob.decode()? else {
+        return Ok(Vec::new());
+    };
+    let mut found = Vec::new();
+    for group in block.groups() {
+        for n in group.nodes() {
+            if wanted.binary_search(&n.id()).is_ok() {
+                found.push(OsmNodeInfo {
+                    id: n.id(),
+                    nano_lat: n.nano_lat(),
+                    nano_lon: n.nano_lon(),
+                    control: NodeControl::from_osm(n.tags()),
                 });
-            });
+            }
+        }
+        for n in group.dense_nodes() {
+            if wanted.binary_search(&n.id()).is_ok() {
+                found.push(OsmNodeInfo {
+                    id: n.id(),
+                    nano_lat: n.nano_lat(),
+                    nano_lon: n.nano_lon(),
+                    control: NodeControl::from_osm(n.tags()),
+                });
+            }
+        }
+    }
+    Ok(found)
+}
+
+/// Read the walkable ways of a PBF extract and the nodes they reference. Segment lengths are left
+/// unset.
+///
+/// The file is read in two passes, each decoding blobs in parallel: the first collects the ways
+/// accepted by `filter`, and the second only the nodes those ways refer to, so memory grows with the
+/// size of the walkable network rather than with the size of the extract.
+pub fn read_osm_pbf(
+    filepath: &Path,
+    filter: &WalkabilityFilter,
+) -> Result<OsmNetworkComponents, osmpbf::Error> {
+    println!("Reading OSM ways...");
+    let BlobWays {
+        mut ways,
+        issues,
+        filtered_ways,
+        removed_ways,
+        header_bbox,
+    } = BlobReader::from_path(filepath)?
+        .par_bridge()
+        .map(|blob| ways_in_blob(&blob?, filter))
+        .try_reduce(BlobWays::default, |a, b| Ok(a.merge(b)))?;
+    // Blobs finish in arbitrary order; sort so that imports are reproducible
+    ways.sort_unstable_by_key(|way| way.id);
+
+    println!("Reading OSM nodes...");
+    let mut wanted: Vec<i64> = ways
+        .iter()
+        .flat_map(|way| way.node_ids.iter().copied())
+        .collect();
+    wanted.sort_unstable();
+    wanted.dedup();
+    let nodes: HashMap<i64, OsmNodeInfo> = BlobReader::from_path(filepath)?
+        .par_bridge()
+        .map(|blob| nodes_in_blob(&blob?, &wanted))
+        .collect::<Result<Vec<_>, _>>()?
+        .into_iter()
+        .flatten()
+        .map(|node| (node.id, node))
+        .collect();
 
-            Ok(components)
+    let mut components = OsmNetworkComponents {
+        nodes,
+        ways,
+        issues,
+        filtered_ways: filtered_ways.into_iter().collect(),
+        removed_ways: removed_ways.into_iter().collect(),
+        ..OsmNetworkComponents::new()
+    };
+    // Fall back to the extent of the loaded nodes if the header declares no bounding box
+    components.bounding_box = header_bbox.or_else(|| components.node_extent());
+    Ok(components)
+}
+
+/// Read a single extract, choosing the format by file extension: `.osm` and `.xml` are read as OSM
+/// XML, anything else as PBF. Segment lengths are left unset.
+pub fn read_osm_file(
+    filepath: &Path,
+    filter: &WalkabilityFilter,
+) -> Result<OsmNetworkComponents, StreetNetworkError> {
+    match filepath.extension().and_then(|ext| ext.to_str()) {
+        Some("osm" | "xml") => Ok(read_osm_xml(filepath, filter)?),
+        _ => Ok(read_osm_pbf(filepath, filter)?),
+    }
+}
+
+/// Read one or more extracts into a single set of components, deduplicating nodes and ways by OSM
+/// ID. Where an ID appears in several extracts the last one wins, so hand edits can be layered over
+/// the extract they were made against by listing them after it. Ways deleted in, or filtered out
+/// of, a later file are dropped from the earlier ones.
+pub fn read_osm(
+    filepaths: &[PathBuf],
+    filter: &WalkabilityFilter,
+) -> Result<OsmNetworkComponents, StreetNetworkError> {
+    let mut merged: Option<OsmNetworkComponents> = None;
+    for filepath in filepaths {
+        let components = read_osm_file(filepath, filter)?;
+        merged = Some(match merged {
+            Some(mut merged) => {
+                merged.merge(components);
+                merged
+            }
+            None => components,
+        });
+    }
+    let mut components = merged.ok_or(ImportError::EmptyExtract)?;
+
+    // Ways may cross from one extract into the next, so lengths are only computed once all nodes
+    // are known
+    println!("Processing way segment lengths...");
+    components.compute_segment_lengths();
+    Ok(components)
+}
+
+#[cfg(test)]
+mod tests {
+    use krabmaga::engine::location::Real2D;
+
+    use super::*;
+    use crate::model::urban_network::boundary::parse_poly;
+
+    fn assert_close(actual: Option<f32>, expected: f32) {
+        let actual = actual.expect("value should parse");
+        assert!(
+            (actual - expected).abs() < 1e-3,
+            "expected {}, got {}",
+            expected,
+            actual
+        );
+    }
+
+    #[test]
+    fn widths_parse_in_metres() {
+        assert_close(parse_width("3"), 3.0);
+        assert_close(parse_width("3.5 m"), 3.5);
+        assert_close(parse_width("10 ft"), 3.048);
+        assert_close(parse_width("10'"), 3.048);
+        assert_eq!(parse_width("narrow"), None);
+        assert_eq!(parse_width("3 yards"), None);
+    }
+
+    #[test]
+    fn inclines_parse_in_percent() {
+        assert_close(parse_incline("10%"), 10.0);
+        assert_close(parse_incline("-5%"), -5.0);
+        assert_close(parse_incline("45°"), 100.0);
+        assert_eq!(parse_incline("up"), None);
+    }
+
+    #[test]
+    fn speed_limits_parse_in_kilometres_per_hour() {
+        assert_close(parse_maxspeed("50"), 50.0);
+        assert_close(parse_maxspeed("30 km/h"), 30.0);
+        assert_close(parse_maxspeed("25 mph"), 40.234);
+        assert_eq!(parse_maxspeed("walk"), None);
+        assert_eq!(parse_maxspeed("signals"), None);
+    }
+
+    #[test]
+    fn curated_tags_become_typed_label_fields() {
+        let tags = OsmWayTags::from_tags([
+            ("highway", "residential"),
+            ("name", "Main Street"),
+            ("sidewalk", "both"),
+            ("lit", "yes"),
+            ("width", "6 m"),
+            ("oneway", "yes"),
+            ("oneway:foot", "yes"),
+            ("building", "yes"),
+        ]);
+        let label = tags.to_edge_label(12.5, 7, 0).unwrap();
+        assert_eq!(label.len, 12.5);
+        assert_eq!(label.highway, HighwayClass::Residential);
+        assert_eq!(label.name.as_deref(), Some("Main Street"));
+        assert_eq!(label.sidewalk, Sidewalk::Both);
+        assert_eq!(label.lit, Some(true));
+        assert_eq!(label.width, Some(6.0));
+        assert!(label.oneway_foot);
+        assert_eq!(label.surface, None);
+
+        let label = OsmWayTags::from_tags([("highway", "footway"), ("oneway:foot", "no")])
+            .to_edge_label(1.0, 8, 0)
+            .unwrap();
+        assert_eq!(label.highway, HighwayClass::Footway);
+        assert!(!label.oneway_foot);
+        assert_eq!(label.lit, None);
+    }
+
+    fn two_node_way(tags: &[(&str, &str)]) -> (OsmWayInfo, HashMap<i64, StreetNode>) {
+        let way = OsmWayInfo {
+            id: 100,
+            node_ids: vec![1, 2],
+            segments: vec![OsmSegmentInfo {
+                u_id: 1,
+                v_id: 2,
+                length: 25.0,
+                index: 0,
+            }],
+            tags: OsmWayTags::from_tags(tags.iter().copied()),
+        };
+        let nodes = [1, 2]
+            .map(|id| {
+                (
+                    id,
+                    StreetNode::new(
+                        id,
+                        Real2D {
+                            x: id as f32,
+                            y: 0.0,
+                        },
+                    ),
+                )
+            })
+            .into_iter()
+            .collect();
+        (way, nodes)
+    }
+
+    #[test]
+    fn segments_are_walkable_both_ways() {
+        let (way, nodes) = two_node_way(&[("highway", "footway"), ("incline", "8%")]);
+        let specs = way
+            .as_edge_specs(&nodes, ErrorPolicy::Fail, &mut Vec::new())
+            .unwrap();
+        assert_eq!(specs.len(), 2);
+        let ends: Vec<(i64, i64)> = specs
+            .iter()
+            .map(|spec| (spec.u.osm_id, spec.v.osm_id))
+            .collect();
+        assert_eq!(ends, vec![(1, 2), (2, 1)]);
+        let inclines: Vec<Option<f32>> = specs
+            .iter()
+            .map(|spec| match &spec.options {
+                EdgeOptions::WeightedLabeled(label, _) => label.incline,
+                _ => panic!("edges should carry labels"),
+            })
+            .collect();
+        assert_eq!(inclines, vec![Some(8.0), Some(-8.0)]);
+    }
+
+    #[test]
+    fn oneway_foot_segments_are_walkable_one_way() {
+        let (way, nodes) = two_node_way(&[("highway", "steps"), ("oneway:foot", "yes")]);
+        let specs = way
+            .as_edge_specs(&nodes, ErrorPolicy::Fail, &mut Vec::new())
+            .unwrap();
+        assert_eq!(specs.len(), 1);
+        assert_eq!((specs[0].u.osm_id, specs[0].v.osm_id), (1, 2));
+
+        // A car oneway does not bind pedestrians
+        let (way, nodes) = two_node_way(&[("highway", "residential"), ("oneway", "yes")]);
+        assert_eq!(
+            way.as_edge_specs(&nodes, ErrorPolicy::Fail, &mut Vec::new())
+                .unwrap()
+                .len(),
+            2
+        );
+    }
+
+    #[test]
+    fn missing_nodes_fail_or_are_skipped_by_policy() {
+        let (mut way, nodes) = two_node_way(&[("highway", "footway")]);
+        way.node_ids.push(3);
+        way.segments.push(OsmSegmentInfo {
+            u_id: 2,
+            v_id: 3,
+            length: 10.0,
+            index: 1,
+        });
+        let missing = ImportError::MissingNodeRef {
+            way_id: 100,
+            node_id: 3,
+        };
+
+        let mut skipped = Vec::new();
+        let result = way.as_edge_specs(&nodes, ErrorPolicy::Fail, &mut skipped);
+        assert_eq!(result.err(), Some(missing.clone()));
+        assert!(skipped.is_empty());
+
+        let specs = way
+            .as_edge_specs(&nodes, ErrorPolicy::SkipAndReport, &mut skipped)
+            .unwrap();
+        assert_eq!(specs.len(), 2);
+        assert_eq!(skipped, vec![missing]);
+    }
+
+    #[test]
+    fn reports_count_suspect_segments() {
+        let mut components = OsmNetworkComponents::new();
+        for id in [1, 2, 3] {
+            components
+                .nodes
+                .insert(id, OsmNodeInfo::from_lon_lat(id, id as f64 * 0.001, 0.0));
+        }
+        let segment = |u_id, v_id, length| OsmSegmentInfo {
+            u_id,
+            v_id,
+            length,
+            index: 0,
+        };
+        components.ways.push(OsmWayInfo {
+            id: 100,
+            node_ids: vec![1, 2, 3, 4],
+            // 3-4 dangles, so its length is never computed
+            segments: vec![segment(1, 2, 10.0), segment(2, 3, 0.0), segment(3, 4, -1.0)],
+            tags: OsmWayTags::default(),
+        });
+        components.ways.push(OsmWayInfo {
+            id: 101,
+            node_ids: vec![2, 1],
+            segments: vec![segment(2, 1, 10.0)],
+            tags: OsmWayTags::default(),
+        });
+        components.issues.push(ImportError::DegenerateWay {
+            way_id: 102,
+            reason: "1 node reference(s)".to_string(),
+        });
+
+        let report = components.report();
+        assert_eq!((report.osm_nodes, report.osm_ways), (3, 2));
+        assert_eq!(report.degenerate_ways, 1);
+        assert_eq!(report.segments, 4);
+        assert_eq!(report.total_length, 20.0);
+        assert_eq!(report.dangling_references, 1);
+        assert_eq!(report.zero_length_segments, 1);
+        assert_eq!(report.duplicate_segments, 1);
+    }
+
+    #[test]
+    fn node_extent_spans_the_loaded_nodes() {
+        let mut components = OsmNetworkComponents::new();
+        assert!(components.node_extent().is_none());
+        for (id, lon, lat) in [(1, -73.2, 44.0), (2, -73.1, 44.05), (3, -73.15, 43.98)] {
+            components
+                .nodes
+                .insert(id, OsmNodeInfo::from_lon_lat(id, lon, lat));
+        }
+        let extent = components.node_extent().unwrap();
+        assert!((extent.left + 73.2).abs() < 1e-9);
+        assert!((extent.right + 73.1).abs() < 1e-9);
+        assert!((extent.top - 44.05).abs() < 1e-9);
+        assert!((extent.bottom - 43.98).abs() < 1e-9);
+    }
+
+    #[test]
+    fn ways_leaving_and_reentering_the_boundary_are_split() {
+        // A U-shaped boundary: the way along y = 5 crosses the notch between its arms
+        let boundary =
+            parse_poly("u\n1\n0 0\n10 0\n10 10\n6 10\n6 2\n4 2\n4 10\n0 10\nEND\nEND\n").unwrap();
+        let mut components = OsmNetworkComponents::new();
+        for (id, lon) in [(1, 1.0), (2, 9.0)] {
+            components
+                .nodes
+                .insert(id, OsmNodeInfo::from_lon_lat(id, lon, 5.0));
         }
+        components.ways.push(OsmWayInfo {
+            id: 100,
+            node_ids: vec![1, 2],
+            segments: vec![OsmSegmentInfo {
+                u_id: 1,
+                v_id: 2,
+                length: 0.0,
+                index: 0,
+            }],
+            tags: OsmWayTags::default(),
+        });
 
-        Err(e) => Err(e),
+        components.clip_to(&boundary);
+        assert_eq!(components.ways.len(), 2);
+        for way in &components.ways {
+            assert_eq!(way.id, 100);
+            assert_eq!(way.segments.len(), 1);
+            assert_eq!(way.node_ids.len(), 2);
+        }
+        assert_eq!(components.ways[0].node_ids[0], 1);
+        assert_eq!(components.ways[1].node_ids[1], 2);
+        // Both original nodes and the two cut points remain
+        assert_eq!(components.nodes.len(), 4);
     }
 }
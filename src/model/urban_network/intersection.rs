@@ -0,0 +1,78 @@
+use krabmaga::rand::Rng;
+use serde::{Deserialize, Serialize};
+
+use super::NodeControl;
+
+/// Waits, in seconds, that pedestrians face passing through controlled nodes.
+///
+/// Signals alternate a walk phase with a wait over a fixed cycle, so a pedestrian arriving at a
+/// random point in the cycle either crosses at once or waits out the rest of it. Unsignalised
+/// crossings have pedestrians wait for a gap in traffic (or for it to yield), modelled as an
+/// exponentially distributed wait around the given mean.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct IntersectionDelays {
+    /// Length of a full signal cycle
+    pub signal_cycle: f32,
+    /// Part of each cycle during which pedestrians may start crossing
+    pub walk_phase: f32,
+    /// Mean wait for traffic to yield at a marked crossing
+    pub marked_wait: f32,
+    /// Mean wait for a gap in traffic at an unmarked crossing
+    pub unmarked_wait: f32,
+}
+
+impl Default for IntersectionDelays {
+    fn default() -> Self {
+        IntersectionDelays {
+            signal_cycle: 90.0,
+            walk_phase: 20.0,
+            marked_wait: 2.0,
+            unmarked_wait: 8.0,
+        }
+    }
+}
+
+impl IntersectionDelays {
+    /// Seconds spent in the signal cycle outside the walk phase.
+    fn red_phase(&self) -> f32 {
+        (self.signal_cycle - self.walk_phase).max(0.0)
+    }
+
+    fn mean_gap_wait(&self, control: NodeControl) -> f32 {
+        match control {
+            NodeControl::MarkedCrossing => self.marked_wait,
+            NodeControl::UnmarkedCrossing => self.unmarked_wait,
+            _ => 0.0,
+        }
+    }
+
+    /// Mean wait at a node with the given control, e.g. for routing costs.
+    pub fn expected_delay(&self, control: NodeControl) -> f32 {
+        if control.is_signalised() {
+            if self.signal_cycle <= 0.0 {
+                return 0.0;
+            }
+            self.red_phase().powi(2) / (2.0 * self.signal_cycle)
+        } else {
+            self.mean_gap_wait(control)
+        }
+    }
+
+    /// Draw the wait of one pedestrian arriving at a node with the given control.
+    pub fn sample_delay(&self, control: NodeControl, rng: &mut impl Rng) -> f32 {
+        if control.is_signalised() {
+            let arrival = rng.gen::<f32>() * self.signal_cycle;
+            if arrival < self.walk_phase {
+                0.0
+            } else {
+                self.signal_cycle - arrival
+            }
+        } else {
+            let mean = self.mean_gap_wait(control);
+            if mean <= 0.0 {
+                return 0.0;
+            }
+            -mean * (1.0 - rng.gen::<f32>()).ln()
+        }
+    }
+}
@@ -1,8 +1,46 @@
+pub mod angular;
+pub mod boundary;
+pub mod cache;
+pub mod components;
 pub mod edge;
+pub mod export;
+pub mod filter;
+pub mod geojson_network;
+pub mod graphml;
+pub mod hierarchy;
 pub mod import;
+pub mod intersection;
 pub mod network;
 pub mod node;
+pub mod osm_xml;
+pub mod plaza;
+pub mod projection;
+pub mod report;
+pub mod route_choice;
+pub mod routing;
+pub mod simplify;
+pub mod synthetic;
+#[cfg(test)]
+mod test_support;
+pub mod zones;
 
-pub use edge::StreetEdgeLabel;
+pub use angular::RouteMetric;
+pub use boundary::{read_boundary, BoundaryError};
+pub use cache::cached_street_network_from_osm;
+pub use components::Connectivity;
+pub use edge::{HighwayClass, Sidewalk, StreetEdgeLabel};
+pub use export::{export_network, NetworkMetrics};
+pub use filter::WalkabilityFilter;
+pub use geojson_network::{street_network_from_geojson, GeoJsonNetworkOptions};
+pub use graphml::{street_network_from_graphml, write_graphml};
+pub use hierarchy::ContractionHierarchy;
+pub use import::ImportOptions;
+pub use intersection::IntersectionDelays;
 pub use network::*;
 pub use node::*;
+pub use projection::LocalProjection;
+pub use report::ImportReport;
+pub use route_choice::{RouteChoice, RouteChoiceModel, RouteWeights};
+pub use routing::{Route, RoutingAlgorithm};
+pub use synthetic::SyntheticLayout;
+pub use zones::{Zone, ZoneLayer};
@@ -4,24 +4,28 @@ use krabmaga::HashMap;
 use krabmaga::Rng;
 use krabmaga::Uniform;
 use serde_with::serde_as;
-use std::cell::RefCell;
 use std::fmt::Display;
 use std::fmt::Pointer;
 use std::fmt::Write;
 use std::hash::Hash;
-use std::path::Path;
+use std::path::PathBuf;
 
 use indicatif::ProgressBar;
 use krabmaga::engine::fields::{
     field::Field,
     network::{Edge, EdgeOptions, Network},
 };
-use osmpbf::HeaderBBox;
+use krabmaga::engine::location::Real2D;
 use serde::{Deserialize, Serialize};
 
-use crate::model::urban_network::import::EdgeSpec;
+pub use crate::model::error::StreetNetworkError;
+use crate::model::error::{ErrorPolicy, ImportError};
 
-use super::import::read_osm;
+use super::components::ComponentReport;
+use super::hierarchy::ContractionHierarchy;
+use super::import::{read_osm, ImportOptions, OsmNetworkComponents};
+use super::projection::LocalProjection;
+use super::report::ImportReport;
 
 use super::{StreetEdgeLabel, StreetNode};
 
@@ -34,24 +38,18 @@ struct EdgeDef<L: Clone + Hash + Display> {
     pub weight: Option<f32>,
 }
 
-//#[derive(Serialize)]
-//#[serde_as]
-//#[serde(remote = "krabmaga::engine::fields::network::Network")]
-struct NetworkDef<
-    O: Hash + Eq + Clone + Display + Serialize + for<'a> Deserialize<'a>,
-    L: Clone + Hash + Display + Serialize + for<'a> Deserialize<'a>,
-> {
-    //#[serde_as(as = "hashbrown::HashMap<u32, Vec<Edge<L>>>")]
-    pub edges: Vec<RefCell<HashMap<u32, Vec<Edge<L>>>>>,
-    pub read: usize,
-    pub write: usize,
-    //#[serde(with = "krabmaga::hashbrown::HashMap")]
-    pub nodes2id: Vec<RefCell<HashMap<O, u32>>>,
-    //#[serde(with = "krabmaga::hashbrown::HashMap")]
-    pub id2nodes: Vec<RefCell<HashMap<u32, O>>>,
-    pub direct: bool,
+/// Flat, ID-ordered representation of a `StreetNetwork`, used for (de)serialization. Node IDs are
+/// their index in `nodes`, so they survive the round trip unchanged.
+#[derive(Serialize, Deserialize)]
+struct NetworkDef {
+    direct: bool,
+    nodes: Vec<StreetNode>,
+    edges: Vec<EdgeRecord>,
 }
 
+#[derive(Serialize, Deserialize)]
+struct EdgeRecord(#[serde(with = "EdgeDef")] Edge<StreetEdgeLabel>);
+
 #[derive(Copy, Clone, Debug, PartialEq)]
 pub struct StreetNetworkPosition {
     pub from_node: u32,
@@ -77,7 +75,7 @@ impl StreetNetworkPosition {
             .expect("Network should have non-empty edge list.");
 
         let starting_edge_length = starting_edge
-                .label.map(|label| label.len)
+                .label.as_ref().map(|label| label.len)
                 .unwrap_or_else(|| panic!("Error occurred on edge ({} - {}): Edges must be defined with length value in label",
                     starting_edge.u, starting_edge.v));
 
@@ -89,6 +87,61 @@ impl StreetNetworkPosition {
         );
         starting_loc
     }
+
+    /// Location of the position in network coordinates, interpolated along its edge.
+    pub fn location(&self, network: &StreetNetwork) -> Option<Real2D> {
+        let u = network.0.get_object(self.from_node)?.loc;
+        let v = network.0.get_object(self.to_node)?.loc;
+        let label = network
+            .get_edge_by_ids(self.from_node, self.to_node)
+            .and_then(|edge| edge.label)
+            .unwrap_or_default();
+        let t = if label.len > 0.0 {
+            self.edge_dist / label.len
+        } else {
+            0.0
+        };
+        Some(label.point_at(u, v, t))
+    }
+
+    /// The same point on the street, facing the other way: the position on the reverse edge
+    /// (`to_node` -> `from_node`), measured from its start. Returns `None` if the street may only
+    /// be walked in one direction.
+    pub fn reversed(&self, network: &StreetNetwork) -> Option<StreetNetworkPosition> {
+        let reverse_edge = network.get_edge_by_ids(self.to_node, self.from_node)?;
+        let reverse_length = reverse_edge.label.map(|label| label.len)?;
+        Some(StreetNetworkPosition::new(
+            self.to_node,
+            self.from_node,
+            (reverse_length - self.edge_dist).max(0.0),
+        ))
+    }
+
+    /// The same point, facing `node`, if `node` is an end of its street that may be walked to.
+    pub fn heading_to(&self, node: u32, network: &StreetNetwork) -> Option<Self> {
+        if self.to_node == node {
+            Some(*self)
+        } else if self.from_node == node {
+            self.reversed(network)
+        } else {
+            None
+        }
+    }
+
+    /// Distance of `other` along this position's edge, if both lie on the same street.
+    pub fn distance_along(
+        &self,
+        other: &StreetNetworkPosition,
+        network: &StreetNetwork,
+    ) -> Option<f32> {
+        if (other.from_node, other.to_node) == (self.from_node, self.to_node) {
+            Some(other.edge_dist)
+        } else if (other.from_node, other.to_node) == (self.to_node, self.from_node) {
+            Some(network.edge_length(other.from_node, other.to_node) - other.edge_dist)
+        } else {
+            None
+        }
+    }
 }
 
 impl Default for StreetNetworkPosition {
@@ -114,130 +167,306 @@ impl Display for StreetNetworkPosition {
         f.write_str(rep.as_str())
     }
 }
-//#[derive(Serialize, Deserialize)]
-
-pub struct StreetNetwork(
-    //#[serde(with = "NetworkDef")]
-    pub Network<StreetNode, StreetEdgeLabel>,
-);
+pub struct StreetNetwork(pub Network<StreetNode, StreetEdgeLabel>);
 
 impl StreetNetwork {
+    /// All edges in the network's read state.
+    pub fn edge_list(&self) -> Vec<Edge<StreetEdgeLabel>> {
+        let network = &self.0;
+        network.edges[network.read]
+            .borrow()
+            .values()
+            .flat_map(|edges| edges.iter().cloned())
+            .collect()
+    }
+
+    /// IDs of all nodes in the network's read state, in ascending order.
+    pub fn node_ids(&self) -> Vec<u32> {
+        let network = &self.0;
+        let mut ids: Vec<u32> = network.id2nodes[network.read]
+            .borrow()
+            .keys()
+            .copied()
+            .collect();
+        ids.sort_unstable();
+        ids
+    }
+
+    /// Look up the edge from node `u` to node `v` by network node ID.
+    pub fn get_edge_by_ids(&self, u: u32, v: u32) -> Option<Edge<StreetEdgeLabel>> {
+        let network = &self.0;
+        network.edges[network.read]
+            .borrow()
+            .get(&u)
+            .and_then(|edges| edges.iter().find(|e| e.v == v).cloned())
+    }
+
+    /// Length of the street between nodes `u` and `v`, in whichever direction it is stored.
+    pub(crate) fn edge_length(&self, u: u32, v: u32) -> f32 {
+        self.get_edge_by_ids(u, v)
+            .or_else(|| self.get_edge_by_ids(v, u))
+            .and_then(|edge| edge.label)
+            .map_or(0.0, |label| label.len)
+    }
+
     fn get_random_edge_position(&self) -> Option<StreetNetworkPosition> {
         unimplemented!("Eventually hope to use this in the state initialization routine, if re-running of edge list routine doesn't take too long");
     }
 }
-// impl Serialize for StreetNetwork {
-//     fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
-//     where
-//         S: serde::Serializer,
-//     {
-//         serializer.serialize_newtype_struct("StreetNetwork", {
-//             let mut state =
-//                 serializer.serialize_struct("Network<StreetNode, StreetEdgeLabel>", 6)?;
-//             state.serialize_field("edges",{&self.0.edges});
-//             &"placeholder"
-//         })
-//     }
-// }
+/// Options that recreate an edge with the given label and weight.
+pub(crate) fn edge_options(
+    label: Option<StreetEdgeLabel>,
+    weight: Option<f32>,
+) -> EdgeOptions<StreetEdgeLabel> {
+    match (label, weight) {
+        (Some(label), Some(weight)) => EdgeOptions::WeightedLabeled(label, weight),
+        (Some(label), None) => EdgeOptions::Labeled(label),
+        (None, Some(weight)) => EdgeOptions::Weighted(weight),
+        (None, None) => EdgeOptions::Simple,
+    }
+}
+
+impl Serialize for StreetNetwork {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        // Renumber nodes densely in ID order, so that re-adding them in order reproduces the IDs
+        let node_ids = self.node_ids();
+        let index: HashMap<u32, u32> = node_ids
+            .iter()
+            .enumerate()
+            .map(|(i, id)| (*id, i as u32))
+            .collect();
+
+        let nodes = node_ids
+            .iter()
+            .filter_map(|id| self.0.get_object(*id))
+            .collect();
+        // Undirected networks store each edge in both directions and re-add both on insertion
+        let direct = self.0.direct;
+        let mut edges: Vec<EdgeRecord> = self
+            .edge_list()
+            .into_iter()
+            .filter(|edge| direct || edge.u <= edge.v)
+            .filter_map(|edge| {
+                Some(EdgeRecord(Edge {
+                    u: *index.get(&edge.u)?,
+                    v: *index.get(&edge.v)?,
+                    ..edge
+                }))
+            })
+            .collect();
+        edges.sort_by_key(|EdgeRecord(e)| (e.u, e.v));
+
+        NetworkDef {
+            direct: self.0.direct,
+            nodes,
+            edges,
+        }
+        .serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for StreetNetwork {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        let NetworkDef {
+            direct,
+            nodes,
+            edges,
+        } = NetworkDef::deserialize(deserializer)?;
+
+        let mut network = Network::<StreetNode, StreetEdgeLabel>::new(direct);
+        for node in nodes.iter() {
+            network.add_node(*node);
+        }
+        for EdgeRecord(edge) in edges {
+            let (Some(u), Some(v)) = (nodes.get(edge.u as usize), nodes.get(edge.v as usize))
+            else {
+                return Err(serde::de::Error::custom(format!(
+                    "Edge ({} - {}) references a missing node",
+                    edge.u, edge.v
+                )));
+            };
+            network.add_edge(*u, *v, edge_options(edge.label, edge.weight));
+        }
+        network.lazy_update();
+        Ok(StreetNetwork(network))
+    }
+}
+
+#[derive(Serialize, Deserialize)]
 pub struct StreetNetworkSpec {
     pub network: StreetNetwork,
     pub dim: (f32, f32),
+    /// Projection from WGS84 to the metric coordinates of the network's node locations
+    pub projection: LocalProjection,
+    /// Fragments discarded when keeping only the largest connected component
+    pub component_report: Option<ComponentReport>,
+    /// Problems skipped under `ErrorPolicy::SkipAndReport`
+    pub skipped: Vec<ImportError>,
+    pub report: ImportReport,
+    /// Contraction hierarchy over `network`, when `ImportOptions::contraction_hierarchy` is set
+    pub hierarchy: Option<ContractionHierarchy>,
 }
 
-#[derive(Debug)]
-pub enum StreetNetworkError {
-    Parse(osmpbf::Error),
-}
-
-pub fn street_network_from_osm(filepath: &Path) -> Result<StreetNetworkSpec, StreetNetworkError> {
-    match read_osm(filepath) {
-        Ok(osm_spec) => {
-            // Instantiate network
-            let mut network = Network::<StreetNode, StreetEdgeLabel>::new(true);
-
-            // Generate StreetNodes from osm_spec's nodes
-            println!("{}", "Processing OSM nodes as KBM nodes...");
-            let pb = ProgressBar::new(osm_spec.nodes.len() as u64);
-            let nodes: Vec<StreetNode> = pb
-                .wrap_iter(osm_spec.nodes.iter())
-                .map(|n| (*n).into())
-                .collect();
-
-            // Add nodes to network (for subsequent reference during edge creation)
-            println!("{}", "Adding nodes to network...");
-            let pb = ProgressBar::new(nodes.len() as u64);
-
-            pb.wrap_iter(nodes.into_iter()).for_each(|n| {
-                network.add_node(n);
-            });
-
-            //network.update();
-
-            // Create map of OSM IDs to Node IDs
-            // let osm_id_node_map = network
-            //     .nodes2id
-            //     .iter()
-            //     .map(|item| {
-            //         item.borrow()
-            //             .iter()
-            //             .map(|(node, id)| (node.osm_id, *id))
-            //             .collect::<HashMap<i64, u32>>()
-            //     })
-            //     .reduce(|acc, item| {
-            //         let mut new = acc;
-            //         new.extend(item.into_iter());
-            //         new
-            //     })
-            //     .expect("Network nodes list is empty -- nodes should have already been loaded.");
-
-            let osm_id_node_map = network
-                .nodes2id
-                .iter()
-                .map(|item| {
-                    item.borrow()
-                        .iter()
-                        .map(|(node, _)| (node.osm_id, *node))
-                        .collect::<HashMap<i64, StreetNode>>()
-                })
-                .reduce(|acc, item| {
-                    acc.into_iter()
-                        .chain(item)
-                        .collect::<HashMap<i64, StreetNode>>()
-                })
-                .expect("Network nodes list is empty -- nodes should have already been loaded.");
-
-            // Generate edges from osm_spec's ways
-            println!("{}", "Processing OSM ways as KBM edges...");
-            let pb = ProgressBar::new(osm_spec.ways.len() as u64);
-            let edges: Vec<EdgeSpec<StreetEdgeLabel>> = pb.wrap_iter(osm_spec.ways.iter()).map(|e| {
-                                                            e.as_edge_specs(&osm_id_node_map)}).reduce(|acc, el|{
-                                                            acc.into_iter().chain(el).collect()
-                                                        }).expect("If you've reached this point, your list of OSM segments is improperly formatted");
-
-            // Add edges to network
-            println!("{}", "Adding edges to network...");
-            let pb = ProgressBar::new(edges.len() as u64);
-            pb.wrap_iter(edges.into_iter()).for_each(|e| {
-                network.add_edge(e.u, e.v, e.options);
-            });
-
-            network.lazy_update();
-            // Calculate dimensions from bounding box
-            let HeaderBBox {
-                left,
-                right,
-                top,
-                bottom,
-            } = osm_spec.bounding_box;
-
-            let dim = ((right - left) as f32, (top - bottom) as f32);
-
-            Ok(StreetNetworkSpec {
-                network: StreetNetwork(network),
-                dim,
-            })
+/// Build a street network from one or more OSM extracts (PBF or XML), merged into one network.
+pub fn street_network_from_osm(
+    filepaths: &[PathBuf],
+    options: &ImportOptions,
+) -> Result<StreetNetworkSpec, StreetNetworkError> {
+    let osm_spec = read_osm(filepaths, &options.walkability)?;
+    street_network_from_components(osm_spec, options)
+}
+
+/// Simplify the network, keep only its largest component and build its contraction hierarchy, as
+/// `options` ask.
+pub(crate) fn finish_network(
+    mut network: StreetNetwork,
+    options: &ImportOptions,
+) -> (
+    StreetNetwork,
+    Option<ComponentReport>,
+    Option<ContractionHierarchy>,
+) {
+    if options.simplify {
+        network = network.simplified();
+    }
+    let component_report = options.largest_component.map(|connectivity| {
+        let (largest, component_report) = network.largest_component(connectivity);
+        network = largest;
+        print!("{}", component_report);
+        component_report
+    });
+    let hierarchy = options
+        .contraction_hierarchy
+        .then(|| ContractionHierarchy::new(&network, Some(&options.intersection_delays)));
+    (network, component_report, hierarchy)
+}
+
+/// Build a street network from loaded components: clip, check, project and assemble them, then
+/// simplify and prune the result as `options` ask.
+pub fn street_network_from_components(
+    mut osm_spec: OsmNetworkComponents,
+    options: &ImportOptions,
+) -> Result<StreetNetworkSpec, StreetNetworkError> {
+    if let Some(boundary) = &options.boundary {
+        osm_spec.clip_to(boundary);
+    }
+    // The report describes the ways as mapped, so is taken before plazas are linked across
+    let mut report = osm_spec.report();
+    (report.plazas, report.plaza_links) = osm_spec.connect_plazas();
+
+    // Problems found while reading (degenerate ways) are subject to the same policy as those found
+    // while building edges
+    let mut skipped: Vec<ImportError> = Vec::new();
+    for issue in osm_spec.issues.drain(..) {
+        match options.on_error {
+            ErrorPolicy::Fail => return Err(issue.into()),
+            ErrorPolicy::SkipAndReport => skipped.push(issue),
+        }
+    }
+    if osm_spec.nodes.is_empty() || osm_spec.ways.is_empty() {
+        return Err(ImportError::EmptyExtract.into());
+    }
+
+    // Instantiate network
+    let mut network = Network::<StreetNode, StreetEdgeLabel>::new(true);
+
+    // Fit a local metric projection to the extract, widened to cover any way dependencies
+    // that lie outside the declared bounding box
+    let mut bbox = osm_spec.node_extent().ok_or(ImportError::EmptyExtract)?;
+    if let Some(declared) = &osm_spec.bounding_box {
+        bbox.left = bbox.left.min(declared.left);
+        bbox.right = bbox.right.max(declared.right);
+        bbox.top = bbox.top.max(declared.top);
+        bbox.bottom = bbox.bottom.min(declared.bottom);
+    }
+    let (projection, dim) =
+        LocalProjection::fitted_to(bbox.left, bbox.right, bbox.top, bbox.bottom);
+
+    // Project nodes, consuming the OSM node table so only one copy is held at a time, and add them
+    // in OSM ID order so that network IDs are reproducible
+    let osm_id_node_map: HashMap<i64, StreetNode> = osm_spec
+        .nodes
+        .drain()
+        .map(|(id, n)| (id, n.to_street_node(&projection)))
+        .collect();
+    let mut osm_ids: Vec<i64> = osm_id_node_map.keys().copied().collect();
+    osm_ids.sort_unstable();
+    let pb = ProgressBar::new(osm_ids.len() as u64);
+    for id in pb.wrap_iter(osm_ids.into_iter()) {
+        network.add_node(osm_id_node_map[&id]);
+    }
+
+    // Stream each way's edges straight into the network; reverse edges are added explicitly, so the
+    // network stays directed
+    let pb = ProgressBar::new(osm_spec.ways.len() as u64);
+    let mut edge_count = 0;
+    for way in pb.wrap_iter(osm_spec.ways.iter()) {
+        for e in way.as_edge_specs(&osm_id_node_map, options.on_error, &mut skipped)? {
+            network.add_edge(e.u, e.v, e.options);
+            edge_count += 1;
         }
+    }
+    drop(osm_spec);
+    drop(osm_id_node_map);
+    if edge_count == 0 {
+        return Err(ImportError::EmptyExtract.into());
+    }
+    if !skipped.is_empty() {
+        println!("Skipped {} problems in extract", skipped.len());
+    }
+
+    network.lazy_update();
+    let (network, component_report, hierarchy) = finish_network(StreetNetwork(network), options);
+    report.record_network(&network);
+
+    Ok(StreetNetworkSpec {
+        network,
+        dim,
+        projection,
+        component_report,
+        skipped,
+        report,
+        hierarchy,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use krabmaga::engine::location::Real2D;
+
+    use super::*;
+
+    #[test]
+    fn reversed_positions_measure_from_the_other_end() {
+        let mut network = Network::new(true);
+        let a = StreetNode::new(1, Real2D { x: 0.0, y: 0.0 });
+        let b = StreetNode::new(2, Real2D { x: 10.0, y: 0.0 });
+        let c = StreetNode::new(3, Real2D { x: 0.0, y: 10.0 });
+        for node in [a, b, c] {
+            network.add_node(node);
+        }
+        let label = StreetEdgeLabel::new(10.0, 1, 0);
+        for (u, v) in [(a, b), (b, a), (a, c)] {
+            network.add_edge(u, v, EdgeOptions::WeightedLabeled(label.clone(), label.len));
+        }
+        network.lazy_update();
+        let ids = |node: StreetNode| network.nodes2id[network.read].borrow()[&node];
+        let (a_id, b_id, c_id) = (ids(a), ids(b), ids(c));
+        let network = StreetNetwork(network);
+
+        let position = StreetNetworkPosition::new(a_id, b_id, 3.0);
+        let reversed = position.reversed(&network).unwrap();
+        assert_eq!(reversed, StreetNetworkPosition::new(b_id, a_id, 7.0));
+        assert_eq!(reversed.reversed(&network), Some(position));
 
-        Err(e) => Err(StreetNetworkError::Parse(e)),
+        // No way back along a street that may only be walked one way
+        let position = StreetNetworkPosition::new(a_id, c_id, 3.0);
+        assert_eq!(position.reversed(&network), None);
     }
 }
@@ -12,16 +12,112 @@ pub struct Real2DDef {
     pub x: f32,
     pub y: f32,
 }
+
+/// (De)serialize a `Vec<Real2D>` through `Real2DDef`, for use with `#[serde(with = "real2d_vec")]`.
+pub mod real2d_vec {
+    use krabmaga::engine::location::Real2D;
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    use super::Real2DDef;
+
+    #[derive(Serialize, Deserialize)]
+    struct Wrapper(#[serde(with = "Real2DDef")] Real2D);
+
+    pub fn serialize<S: Serializer>(points: &[Real2D], serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(points.iter().map(|p| Wrapper(*p)))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Vec<Real2D>, D::Error> {
+        let wrapped = Vec::<Wrapper>::deserialize(deserializer)?;
+        Ok(wrapped.into_iter().map(|Wrapper(p)| p).collect())
+    }
+}
+/// Traffic control at a node that pedestrians passing through may have to wait for, from the
+/// node's OSM `highway`, `crossing` and `crossing:*` tags.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum NodeControl {
+    #[default]
+    None,
+    /// Crossing without markings or signals, where pedestrians wait for a gap in traffic
+    UnmarkedCrossing,
+    /// Marked crossing without signals (e.g. a zebra crossing), where traffic should yield
+    MarkedCrossing,
+    /// Crossing with pedestrian signals
+    SignalisedCrossing,
+    /// Traffic signals at a junction with no crossing mapped on the node
+    TrafficSignals,
+}
+
+impl NodeControl {
+    pub fn from_osm<'a>(tags: impl Iterator<Item = (&'a str, &'a str)>) -> Self {
+        let mut highway = None;
+        let mut crossing = None;
+        let mut signals = None;
+        let mut markings = None;
+        for (key, value) in tags {
+            match key {
+                "highway" => highway = Some(value),
+                "crossing" => crossing = Some(value),
+                "crossing:signals" => signals = Some(value),
+                "crossing:markings" => markings = Some(value),
+                _ => {}
+            }
+        }
+
+        if crossing == Some("traffic_signals") || signals == Some("yes") {
+            return NodeControl::SignalisedCrossing;
+        }
+        match (highway, crossing, markings) {
+            (_, Some("no"), _) => NodeControl::None,
+            (_, Some("unmarked"), _) | (_, _, Some("no")) => NodeControl::UnmarkedCrossing,
+            (_, Some("uncontrolled" | "zebra" | "marked"), _) | (_, _, Some(_)) => {
+                NodeControl::MarkedCrossing
+            }
+            (Some("traffic_signals"), _, _) => NodeControl::TrafficSignals,
+            // Most crossings mapped without details are marked
+            (Some("crossing"), _, _) => NodeControl::MarkedCrossing,
+            _ => NodeControl::None,
+        }
+    }
+
+    /// OSM tags describing the control, the inverse of `from_osm`.
+    pub fn as_osm(&self) -> &'static [(&'static str, &'static str)] {
+        match self {
+            NodeControl::None => &[],
+            NodeControl::UnmarkedCrossing => &[("highway", "crossing"), ("crossing", "unmarked")],
+            NodeControl::MarkedCrossing => &[("highway", "crossing"), ("crossing", "uncontrolled")],
+            NodeControl::SignalisedCrossing => {
+                &[("highway", "crossing"), ("crossing", "traffic_signals")]
+            }
+            NodeControl::TrafficSignals => &[("highway", "traffic_signals")],
+        }
+    }
+
+    pub fn is_signalised(&self) -> bool {
+        matches!(
+            self,
+            NodeControl::SignalisedCrossing | NodeControl::TrafficSignals
+        )
+    }
+}
+
 #[derive(Copy, Clone, Eq, Default, Debug, Serialize, Deserialize)]
 pub struct StreetNode {
     pub osm_id: i64,
     #[serde(with = "Real2DDef")]
     pub loc: Real2D,
+    pub control: NodeControl,
 }
 
 impl StreetNode {
     pub fn new(id: i64, loc: Real2D) -> Self {
-        StreetNode { osm_id: id, loc }
+        StreetNode {
+            osm_id: id,
+            loc,
+            control: NodeControl::None,
+        }
     }
 }
 
@@ -65,3 +161,57 @@ impl fmt::Display for StreetNode {
 //         }
 //     }
 // }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn control(tags: &[(&str, &str)]) -> NodeControl {
+        NodeControl::from_osm(tags.iter().copied())
+    }
+
+    #[test]
+    fn crossings_are_classified_from_their_tags() {
+        assert_eq!(control(&[]), NodeControl::None);
+        assert_eq!(
+            control(&[("highway", "crossing")]),
+            NodeControl::MarkedCrossing
+        );
+        assert_eq!(
+            control(&[("highway", "crossing"), ("crossing", "no")]),
+            NodeControl::None
+        );
+        assert_eq!(
+            control(&[("highway", "crossing"), ("crossing:markings", "no")]),
+            NodeControl::UnmarkedCrossing
+        );
+        assert_eq!(
+            control(&[("highway", "crossing"), ("crossing:markings", "zebra")]),
+            NodeControl::MarkedCrossing
+        );
+        assert_eq!(
+            control(&[("crossing", "uncontrolled"), ("crossing:signals", "yes")]),
+            NodeControl::SignalisedCrossing
+        );
+        assert_eq!(
+            control(&[("highway", "traffic_signals")]),
+            NodeControl::TrafficSignals
+        );
+    }
+
+    #[test]
+    fn controls_survive_a_round_trip_through_osm_tags() {
+        for node_control in [
+            NodeControl::None,
+            NodeControl::UnmarkedCrossing,
+            NodeControl::MarkedCrossing,
+            NodeControl::SignalisedCrossing,
+            NodeControl::TrafficSignals,
+        ] {
+            assert_eq!(control(node_control.as_osm()), node_control);
+        }
+        assert!(NodeControl::SignalisedCrossing.is_signalised());
+        assert!(NodeControl::TrafficSignals.is_signalised());
+        assert!(!NodeControl::MarkedCrossing.is_signalised());
+    }
+}
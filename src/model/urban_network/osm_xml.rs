@@ -0,0 +1,264 @@
+use std::{collections::HashMap, fs::File, io::BufReader, path::Path, str::FromStr};
+
+use osmpbf::HeaderBBox;
+use xml::{
+    attribute::OwnedAttribute,
+    common::Position,
+    reader::{EventReader, XmlEvent},
+};
+
+use crate::model::error::OsmXmlError;
+
+use super::{
+    filter::WalkabilityFilter,
+    import::{OsmNetworkComponents, OsmNodeInfo, OsmWayInfo, OsmWayTags},
+    node::NodeControl,
+};
+
+/// A way whose `<nd>` and `<tag>` children are still being read.
+struct PendingWay {
+    id: i64,
+    node_ids: Vec<i64>,
+    tags: Vec<(String, String)>,
+}
+
+fn attribute<'a>(attributes: &'a [OwnedAttribute], name: &str) -> Option<&'a str> {
+    attributes
+        .iter()
+        .find(|attr| attr.name.local_name == name)
+        .map(|attr| attr.value.as_str())
+}
+
+/// Parse a required attribute of an element found on the given line.
+fn parsed<T: FromStr>(
+    attributes: &[OwnedAttribute],
+    element: &str,
+    name: &str,
+    line: u64,
+) -> Result<T, OsmXmlError> {
+    attribute(attributes, name)
+        .and_then(|value| value.parse::<T>().ok())
+        .ok_or_else(|| OsmXmlError::InvalidElement {
+            line,
+            message: format!("<{}> has no valid {} attribute", element, name),
+        })
+}
+
+/// Whether an element survives the edits recorded in the file: JOSM marks deleted objects with
+/// `action="delete"`, and history dumps mark them `visible="false"`.
+fn is_live(attributes: &[OwnedAttribute]) -> bool {
+    attribute(attributes, "action") != Some("delete")
+        && attribute(attributes, "visible") != Some("false")
+}
+
+/// Read the walkable ways of an OSM XML file, such as one saved from JOSM, and the nodes they
+/// reference. Segment lengths are left unset.
+pub fn read_osm_xml(
+    filepath: &Path,
+    filter: &WalkabilityFilter,
+) -> Result<OsmNetworkComponents, OsmXmlError> {
+    let mut reader = EventReader::new(BufReader::new(File::open(filepath)?));
+    let mut components = OsmNetworkComponents::new();
+    let mut all_nodes: HashMap<i64, OsmNodeInfo> = HashMap::new();
+    let mut bounds: Option<HeaderBBox> = None;
+    let mut pending_way: Option<PendingWay> = None;
+    // A node whose `<tag>` children are still being read
+    let mut pending_node: Option<(i64, Vec<(String, String)>)> = None;
+
+    loop {
+        let line = reader.position().row + 1;
+        match reader.next()? {
+            XmlEvent::StartElement {
+                name, attributes, ..
+            } => match name.local_name.as_str() {
+                "bounds" => {
+                    bounds = Some(HeaderBBox {
+                        left: parsed(&attributes, "bounds", "minlon", line)?,
+                        right: parsed(&attributes, "bounds", "maxlon", line)?,
+                        top: parsed(&attributes, "bounds", "maxlat", line)?,
+                        bottom: parsed(&attributes, "bounds", "minlat", line)?,
+                    });
+                }
+                "node" if is_live(&attributes) => {
+                    let id = parsed::<i64>(&attributes, "node", "id", line)?;
+                    let lon = parsed(&attributes, "node", "lon", line)?;
+                    let lat = parsed(&attributes, "node", "lat", line)?;
+                    all_nodes.insert(id, OsmNodeInfo::from_lon_lat(id, lon, lat));
+                    pending_node = Some((id, Vec::new()));
+                }
+                "way" if is_live(&attributes) => {
+                    pending_way = Some(PendingWay {
+                        id: parsed::<i64>(&attributes, "way", "id", line)?,
+                        node_ids: Vec::new(),
+                        tags: Vec::new(),
+                    });
+                }
+                "way" => {
+                    components
+                        .removed_ways
+                        .insert(parsed::<i64>(&attributes, "way", "id", line)?);
+                }
+                "nd" => {
+                    if let Some(way) = pending_way.as_mut() {
+                        way.node_ids
+                            .push(parsed::<i64>(&attributes, "nd", "ref", line)?);
+                    }
+                }
+                "tag" => {
+                    let (Some(key), Some(value)) =
+                        (attribute(&attributes, "k"), attribute(&attributes, "v"))
+                    else {
+                        continue;
+                    };
+                    let tags = match (pending_way.as_mut(), pending_node.as_mut()) {
+                        (Some(way), _) => &mut way.tags,
+                        (None, Some((_, tags))) => tags,
+                        (None, None) => continue,
+                    };
+                    tags.push((key.to_string(), value.to_string()));
+                }
+                _ => {}
+            },
+            XmlEvent::EndElement { name } if name.local_name == "node" => {
+                let Some((id, tags)) = pending_node.take() else {
+                    continue;
+                };
+                let control =
+                    NodeControl::from_osm(tags.iter().map(|(k, v)| (k.as_str(), v.as_str())));
+                if let Some(node) = all_nodes.get_mut(&id) {
+                    *node = node.with_control(control);
+                }
+            }
+            XmlEvent::EndElement { name } if name.local_name == "way" => {
+                let Some(way) = pending_way.take() else {
+                    continue;
+                };
+                let tags = way.tags.iter().map(|(k, v)| (k.as_str(), v.as_str()));
+                if !filter.accepts(tags.clone()) {
+                    if way.tags.iter().any(|(key, _)| key == "highway") {
+                        components.filtered_ways.insert(way.id);
+                    }
+                    // Including ways whose highway tag was removed, so that the edit takes effect
+                    // when this file is layered over an extract
+                    components.removed_ways.insert(way.id);
+                    continue;
+                }
+                match OsmWayInfo::new(way.id, way.node_ids, OsmWayTags::from_tags(tags)) {
+                    Ok(info) => components.ways.push(info),
+                    Err(e) => components.issues.push(e),
+                }
+            }
+            XmlEvent::EndDocument => break,
+            _ => {}
+        }
+    }
+
+    // Keep only the nodes the walkable ways refer to
+    for node_id in components.ways.iter().flat_map(|way| way.node_ids()) {
+        if let Some(node) = all_nodes.get(node_id) {
+            components.nodes.insert(*node_id, *node);
+        }
+    }
+    components.ways.sort_unstable_by_key(|way| way.id());
+
+    // Fall back to the extent of the loaded nodes if the file declares no bounds
+    components.bounding_box = bounds.or_else(|| components.node_extent());
+    Ok(components)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, path::PathBuf};
+
+    use super::*;
+    use crate::model::urban_network::import::read_osm;
+
+    fn write_osm(name: &str, body: &str) -> PathBuf {
+        let path =
+            std::env::temp_dir().join(format!("flaneur-{}-{}.osm", name, std::process::id()));
+        fs::write(
+            &path,
+            format!(
+                "<?xml version='1.0' encoding='UTF-8'?>\n<osm version='0.6'>\n{}\n</osm>\n",
+                body
+            ),
+        )
+        .unwrap();
+        path
+    }
+
+    fn way_ids(components: &OsmNetworkComponents) -> Vec<i64> {
+        components.ways.iter().map(|way| way.id()).collect()
+    }
+
+    const BASE: &str = r#"
+  <bounds minlat='44.0' minlon='-73.2' maxlat='44.1' maxlon='-73.1'/>
+  <node id='1' lat='44.01' lon='-73.19'/>
+  <node id='2' lat='44.02' lon='-73.18'/>
+  <node id='3' lat='44.03' lon='-73.17'/>
+  <way id='10'><nd ref='1'/><nd ref='2'/><tag k='highway' v='footway'/></way>
+  <way id='11'><nd ref='2'/><nd ref='3'/><tag k='highway' v='footway'/></way>
+  <way id='12'><nd ref='1'/><nd ref='3'/><tag k='highway' v='motorway'/></way>"#;
+
+    #[test]
+    fn deleted_and_invisible_elements_are_dropped() {
+        let path = write_osm(
+            "xml-deleted",
+            r#"
+  <node id='1' lat='44.01' lon='-73.19'/>
+  <node id='2' lat='44.02' lon='-73.18'/>
+  <node id='3' lat='44.03' lon='-73.17' action='delete'/>
+  <way id='10'><nd ref='1'/><nd ref='2'/><tag k='highway' v='footway'/></way>
+  <way id='11' visible='false'><nd ref='2'/><nd ref='3'/><tag k='highway' v='footway'/></way>
+  <way id='12' action='delete'><nd ref='1'/><nd ref='3'/><tag k='highway' v='path'/></way>
+  <way id='13'><nd ref='1'/><nd ref='2'/><tag k='building' v='yes'/></way>"#,
+        );
+        let components = read_osm_xml(&path, &WalkabilityFilter::default());
+        fs::remove_file(&path).unwrap();
+        let components = components.unwrap();
+
+        assert_eq!(way_ids(&components), vec![10]);
+        let mut nodes: Vec<i64> = components.nodes.keys().copied().collect();
+        nodes.sort_unstable();
+        assert_eq!(nodes, vec![1, 2]);
+        let mut removed: Vec<i64> = components.removed_ways.iter().copied().collect();
+        removed.sort_unstable();
+        assert_eq!(removed, vec![11, 12, 13]);
+        // Only highway-tagged ways count as filtered out
+        assert!(components.filtered_ways.is_empty());
+        // With no <bounds>, the extent is fitted to the nodes
+        let bbox = components.bounding_box.unwrap();
+        assert!((bbox.left + 73.19).abs() < 1e-9 && (bbox.top - 44.02).abs() < 1e-9);
+    }
+
+    #[test]
+    fn later_extracts_override_earlier_ones() {
+        let base = write_osm("xml-base", BASE);
+        let edits = write_osm(
+            "xml-edits",
+            r#"
+  <node id='1' lat='44.01' lon='-73.19'/>
+  <node id='3' lat='44.03' lon='-73.17'/>
+  <node id='4' lat='44.04' lon='-73.16'/>
+  <way id='10'><nd ref='1'/><nd ref='3'/><tag k='highway' v='footway'/></way>
+  <way id='11' action='delete'><nd ref='2'/><nd ref='3'/><tag k='highway' v='footway'/></way>
+  <way id='12'><nd ref='1'/><nd ref='3'/><tag k='highway' v='motorway'/></way>
+  <way id='14'><nd ref='3'/><nd ref='4'/><tag k='highway' v='steps'/></way>"#,
+        );
+        let merged = read_osm(
+            &[base.clone(), edits.clone()],
+            &WalkabilityFilter::default(),
+        );
+        fs::remove_file(&base).unwrap();
+        fs::remove_file(&edits).unwrap();
+        let merged = merged.unwrap();
+
+        assert_eq!(way_ids(&merged), vec![10, 14]);
+        assert_eq!(merged.ways[0].node_ids(), &[1, 3]);
+        // The motorway is filtered out of both files, but is one way
+        assert_eq!(merged.report().filtered_ways, 1);
+        // The edits' extent, fitted to their nodes, lies within the base file's bounds
+        let bbox = merged.bounding_box.unwrap();
+        assert_eq!((bbox.left, bbox.right, bbox.top), (-73.2, -73.1, 44.1));
+    }
+}
@@ -0,0 +1,189 @@
+use std::collections::{HashMap, HashSet};
+
+use geo::{Coord, HaversineDistance, Line, LineString, Point, Polygon, Relate};
+
+use super::import::OsmNetworkComponents;
+
+impl OsmNetworkComponents {
+    /// Link nodes across pedestrian plazas, which are otherwise walkable only around their rim.
+    ///
+    /// Each plaza is crossed by a visibility graph: a straight segment joins every pair of its
+    /// entry points (rim nodes shared with another way) and reflex rim corners that can see each
+    /// other without leaving the plaza. Including the reflex corners means the shortest way across
+    /// an L- or U-shaped square is still represented. The new segments belong to the plaza's way
+    /// and carry its tags.
+    ///
+    /// Returns the number of plazas found and of segments added across them.
+    pub fn connect_plazas(&mut self) -> (usize, usize) {
+        let mut ways_per_node: HashMap<i64, usize> = HashMap::new();
+        for way in self.ways.iter() {
+            let unique: HashSet<&i64> = way.node_ids().iter().collect();
+            for node_id in unique {
+                *ways_per_node.entry(*node_id).or_default() += 1;
+            }
+        }
+
+        let (mut plazas, mut links) = (0, 0);
+        for way in self.ways.iter_mut().filter(|way| way.is_plaza()) {
+            plazas += 1;
+            let rim = &way.node_ids()[..way.node_ids().len() - 1];
+            let Some(coords) = rim
+                .iter()
+                .map(|id| self.nodes.get(id).map(|node| node.coord()))
+                .collect::<Option<Vec<Coord<f64>>>>()
+            else {
+                continue;
+            };
+            let polygon = Polygon::new(LineString::from(coords.clone()), Vec::new());
+
+            let orientation = signed_area(&coords).signum();
+            let candidates: Vec<usize> = (0..rim.len())
+                .filter(|&i| {
+                    let is_entry = ways_per_node.get(&rim[i]).copied().unwrap_or(0) > 1;
+                    is_entry || is_reflex(&coords, i, orientation)
+                })
+                .collect();
+
+            let mut new_segments = Vec::new();
+            for (k, &i) in candidates.iter().enumerate() {
+                for &j in candidates[k + 1..].iter() {
+                    let adjacent = j - i == 1 || (i == 0 && j == rim.len() - 1);
+                    if adjacent || rim[i] == rim[j] {
+                        continue;
+                    }
+                    let line = Line::new(coords[i], coords[j]);
+                    if polygon.relate(&line).is_covers() {
+                        let length =
+                            Point::from(coords[i]).haversine_distance(&Point::from(coords[j]));
+                        new_segments.push((rim[i], rim[j], length));
+                    }
+                }
+            }
+            links += new_segments.len();
+            for (u_id, v_id, length) in new_segments {
+                way.add_segment(u_id, v_id, length);
+            }
+        }
+        (plazas, links)
+    }
+}
+
+/// Twice the signed area of a ring; positive when its vertices run anticlockwise.
+fn signed_area(ring: &[Coord<f64>]) -> f64 {
+    ring.iter()
+        .zip(ring.iter().cycle().skip(1))
+        .map(|(a, b)| a.x * b.y - b.x * a.y)
+        .sum()
+}
+
+/// True if the ring turns against its orientation at vertex `i`, making the corner concave.
+fn is_reflex(ring: &[Coord<f64>], i: usize, orientation: f64) -> bool {
+    let n = ring.len();
+    let (prev, here, next) = (ring[(i + n - 1) % n], ring[i], ring[(i + 1) % n]);
+    let turn = (here.x - prev.x) * (next.y - here.y) - (here.y - prev.y) * (next.x - here.x);
+    turn * orientation < 0.0
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::model::urban_network::import::{ImportOptions, OsmNodeInfo, OsmWayInfo, OsmWayTags};
+    use crate::model::urban_network::network::street_network_from_components;
+
+    use super::*;
+
+    const PLAZA_ID: i64 = 100;
+
+    /// A plaza with the given rim, in thousandths of a degree, and a street leading away from each
+    /// of the rim nodes listed in `entries`.
+    fn plaza(rim: &[(f64, f64)], entries: &[usize]) -> OsmNetworkComponents {
+        let mut components = OsmNetworkComponents::new();
+        for (i, (x, y)) in rim.iter().enumerate() {
+            let id = i as i64 + 1;
+            components
+                .nodes
+                .insert(id, OsmNodeInfo::from_lon_lat(id, x * 0.001, y * 0.001));
+        }
+        let mut node_ids: Vec<i64> = (1..=rim.len() as i64).collect();
+        node_ids.push(1);
+        let tags = OsmWayTags::from_tags([("highway", "pedestrian"), ("area", "yes")]);
+        components
+            .ways
+            .push(OsmWayInfo::new(PLAZA_ID, node_ids, tags).unwrap());
+
+        for &i in entries {
+            let (id, outside) = (i as i64 + 1, 100 + i as i64);
+            let (x, y) = rim[i];
+            components.nodes.insert(
+                outside,
+                OsmNodeInfo::from_lon_lat(outside, x * 0.001 - 0.005, y * 0.001 - 0.005),
+            );
+            let tags = OsmWayTags::from_tags([("highway", "footway")]);
+            components
+                .ways
+                .push(OsmWayInfo::new(outside, vec![id, outside], tags).unwrap());
+        }
+        components.compute_segment_lengths();
+        components
+    }
+
+    /// Build the network, returning the plazas and links found and the OSM node pairs linked
+    /// across the plaza, in ascending order.
+    fn links(components: OsmNetworkComponents, rim_len: u32) -> ((usize, usize), Vec<(i64, i64)>) {
+        let spec = street_network_from_components(components, &ImportOptions::default()).unwrap();
+        let network = &spec.network;
+        let osm_id = |id: u32| network.0.get_object(id).unwrap().osm_id;
+        let mut links: Vec<(i64, i64)> = network
+            .edge_list()
+            .into_iter()
+            .filter(|edge| {
+                let label = edge.label.as_ref().unwrap();
+                label.way_id == PLAZA_ID && label.segment >= rim_len
+            })
+            .map(|edge| (osm_id(edge.u), osm_id(edge.v)))
+            .filter(|(u, v)| u < v)
+            .collect();
+        links.sort_unstable();
+        ((spec.report.plazas, spec.report.plaza_links), links)
+    }
+
+    #[test]
+    fn entries_of_a_convex_plaza_are_linked_across_it() {
+        let rim = [(0.0, 0.0), (1.0, 0.0), (1.0, 1.0), (0.0, 1.0)];
+        let (found, links) = links(plaza(&rim, &[0, 1, 2, 3]), rim.len() as u32);
+        assert_eq!(found, (1, 2));
+        // Neighbours around the rim are already joined by it
+        assert_eq!(links, vec![(1, 3), (2, 4)]);
+    }
+
+    #[test]
+    fn links_go_round_the_reflex_corner_of_an_l_shaped_plaza() {
+        let rim = [
+            (0.0, 0.0),
+            (3.0, 0.0),
+            (3.0, 1.0),
+            (1.0, 1.0),
+            (1.0, 3.0),
+            (0.0, 3.0),
+        ];
+        let (found, links) = links(plaza(&rim, &[0, 2, 4]), rim.len() as u32);
+        assert_eq!(found, (1, 3));
+        // The ends of the two arms cannot see each other, but both see the reflex corner (node 4)
+        // along the rim, and the corner node 1 sees all three
+        assert_eq!(links, vec![(1, 3), (1, 4), (1, 5)]);
+    }
+
+    #[test]
+    fn plaza_links_are_reported_apart_from_mapped_segments() {
+        let rim = [(0.0, 0.0), (1.0, 0.0), (1.0, 1.0), (0.0, 1.0)];
+        let components = plaza(&rim, &[0, 1, 2, 3]);
+        let mapped = components.report();
+
+        let spec = street_network_from_components(components, &ImportOptions::default()).unwrap();
+        let report = spec.report;
+        assert_eq!((report.plazas, report.plaza_links), (1, 2));
+        assert_eq!(report.segments, mapped.segments);
+        assert_eq!(report.total_length, mapped.total_length);
+        // Every segment, including the links, is walkable both ways
+        assert_eq!(report.edges, 2 * (mapped.segments + 2));
+    }
+}
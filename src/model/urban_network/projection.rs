@@ -0,0 +1,197 @@
+use krabmaga::engine::location::Real2D;
+use serde::{Deserialize, Serialize};
+
+/// Mean earth radius in metres, matching the radius `geo` uses for haversine distances.
+pub const EARTH_RADIUS: f64 = 6_371_008.8;
+
+/// Spherical transverse Mercator projection centred on a local origin.
+///
+/// Projected coordinates are metres east (`x`) and north (`y`) of the origin, offset by a false
+/// easting and northing so that the study area lies in the positive quadrant. Scale error grows
+/// with the square of the distance from the central meridian, staying below 0.01% within 60 km,
+/// which keeps an extract-sized study area effectively distortion-free in `f32` metres.
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct LocalProjection {
+    /// Longitude of the origin in degrees
+    pub origin_lon: f64,
+    /// Latitude of the origin in degrees
+    pub origin_lat: f64,
+    pub earth_radius: f64,
+    /// Metres added to every projected `x`
+    pub false_easting: f64,
+    /// Metres added to every projected `y`
+    pub false_northing: f64,
+}
+
+impl LocalProjection {
+    pub fn new(origin_lon: f64, origin_lat: f64) -> Self {
+        LocalProjection {
+            origin_lon,
+            origin_lat,
+            earth_radius: EARTH_RADIUS,
+            false_easting: 0.0,
+            false_northing: 0.0,
+        }
+    }
+
+    /// Projection centred on the middle of a lon/lat bounding box.
+    pub fn centred_on(left: f64, right: f64, top: f64, bottom: f64) -> Self {
+        LocalProjection::new((left + right) / 2.0, (top + bottom) / 2.0)
+    }
+
+    /// Projection centred on a lon/lat bounding box and offset so that the box's projection starts
+    /// at `(0, 0)`. Returns the projection together with the projected `(width, height)` of the box.
+    pub fn fitted_to(left: f64, right: f64, top: f64, bottom: f64) -> (Self, (f32, f32)) {
+        let mut projection = LocalProjection::centred_on(left, right, top, bottom);
+        // Parallels curve away from the central meridian, so the box's lowest and highest
+        // projected points may lie at the middle of its top and bottom edges
+        let extremes = [
+            projection.project_f64(left, bottom),
+            projection.project_f64(left, top),
+            projection.project_f64(right, bottom),
+            projection.project_f64(right, top),
+            projection.project_f64(projection.origin_lon, bottom),
+            projection.project_f64(projection.origin_lon, top),
+        ];
+        let (min_x, max_x, min_y, max_y) = extremes.iter().fold(
+            (f64::MAX, f64::MIN, f64::MAX, f64::MIN),
+            |(min_x, max_x, min_y, max_y), &(x, y)| {
+                (min_x.min(x), max_x.max(x), min_y.min(y), max_y.max(y))
+            },
+        );
+        projection.false_easting = -min_x;
+        projection.false_northing = -min_y;
+        (projection, ((max_x - min_x) as f32, (max_y - min_y) as f32))
+    }
+
+    /// Project WGS84 degrees to metres relative to the origin, at full precision.
+    pub fn project_f64(&self, lon: f64, lat: f64) -> (f64, f64) {
+        let lat = lat.to_radians();
+        let d_lon = (lon - self.origin_lon).to_radians();
+
+        let b = lat.cos() * d_lon.sin();
+        let x = self.earth_radius * b.atanh();
+        let y = self.earth_radius * (lat.tan().atan2(d_lon.cos()) - self.origin_lat.to_radians());
+        (x + self.false_easting, y + self.false_northing)
+    }
+
+    /// Project WGS84 degrees to a simulation location in metres.
+    pub fn project(&self, lon: f64, lat: f64) -> Real2D {
+        let (x, y) = self.project_f64(lon, lat);
+        Real2D {
+            x: x as f32,
+            y: y as f32,
+        }
+    }
+
+    /// Inverse of `project_f64`: metres relative to the origin back to WGS84 `(lon, lat)` degrees.
+    pub fn unproject_f64(&self, x: f64, y: f64) -> (f64, f64) {
+        let d = (y - self.false_northing) / self.earth_radius + self.origin_lat.to_radians();
+        let x = (x - self.false_easting) / self.earth_radius;
+
+        let lat = (d.sin() / x.cosh()).asin();
+        let lon = self.origin_lon.to_radians() + x.sinh().atan2(d.cos());
+        (lon.to_degrees(), lat.to_degrees())
+    }
+
+    /// Convert a simulation location back to WGS84 `(lon, lat)` degrees.
+    pub fn unproject(&self, loc: Real2D) -> (f64, f64) {
+        self.unproject_f64(loc.x as f64, loc.y as f64)
+    }
+
+    /// The most that distances are stretched anywhere between projected `x` of 0 and `width`.
+    /// The scale factor is 1 on the central meridian and grows as `cosh` of the distance from it
+    /// over the earth's radius.
+    pub fn max_scale_factor(&self, width: f32) -> f32 {
+        [0.0, width as f64]
+            .map(|x| ((x - self.false_easting) / self.earth_radius).cosh())
+            .into_iter()
+            .fold(1.0, f64::max) as f32
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use geo::{HaversineDistance, Point};
+
+    use super::*;
+
+    #[test]
+    fn unproject_inverts_project() {
+        let projection = LocalProjection::new(-73.1673, 44.0153);
+        for (lon, lat) in [
+            (-73.1673, 44.0153),
+            (-73.2, 44.05),
+            (-73.1, 43.98),
+            (-72.5, 44.5),
+        ] {
+            let (x, y) = projection.project_f64(lon, lat);
+            let (lon_back, lat_back) = projection.unproject_f64(x, y);
+            assert!((lon - lon_back).abs() < 1e-9, "{} != {}", lon, lon_back);
+            assert!((lat - lat_back).abs() < 1e-9, "{} != {}", lat, lat_back);
+        }
+    }
+
+    #[test]
+    fn origin_projects_to_zero_and_axes_point_east_and_north() {
+        let projection = LocalProjection::centred_on(-73.2, -73.1, 44.05, 43.98);
+        let origin = projection.project(-73.15, 44.015);
+        assert!(origin.x.abs() < 1e-3 && origin.y.abs() < 1e-3);
+
+        // A thousandth of a degree of latitude is about 111 m everywhere
+        let north = projection.project(-73.15, 44.016);
+        assert!(north.x.abs() < 1e-3);
+        assert!((north.y - 111.2).abs() < 0.1, "{}", north.y);
+
+        // and of longitude, that shrunk by the cosine of the latitude
+        let east = projection.project(-73.149, 44.015);
+        let expected = 111.195 * 44.015_f32.to_radians().cos();
+        assert!(
+            (east.x - expected).abs() < 0.1,
+            "{} != {}",
+            east.x,
+            expected
+        );
+        assert!(east.y.abs() < 0.01);
+    }
+
+    #[test]
+    fn locations_round_trip_within_a_centimetre() {
+        let projection = LocalProjection::new(-73.1673, 44.0153);
+        let loc = projection.project(-73.18, 44.02);
+        let (lon, lat) = projection.unproject(loc);
+        let back = projection.project(lon, lat);
+        assert!((loc.x - back.x).abs() < 0.01 && (loc.y - back.y).abs() < 0.01);
+    }
+
+    #[test]
+    fn fitted_projection_puts_the_box_in_the_positive_quadrant() {
+        let (left, right, top, bottom) = (-73.2, -73.1, 44.05, 43.98);
+        let (projection, (width, height)) = LocalProjection::fitted_to(left, right, top, bottom);
+
+        // About 8 km east to west and 7.8 km north to south
+        assert!((width - 8_000.0).abs() < 50.0, "{}", width);
+        assert!((height - 7_784.0).abs() < 50.0, "{}", height);
+        for lon in [left, projection.origin_lon, right] {
+            for lat in [bottom, top] {
+                let loc = projection.project(lon, lat);
+                assert!(loc.x >= -1e-3 && loc.x <= width + 1e-3, "{:?}", loc);
+                assert!(loc.y >= -1e-3 && loc.y <= height + 1e-3, "{:?}", loc);
+            }
+        }
+
+        // Distances stretch most at the box's edges, by about one part in ten million
+        let max_scale = projection.max_scale_factor(width) as f64;
+        let ground = Point::new(left, bottom).haversine_distance(&Point::new(left, top));
+        let (x0, y0) = projection.project_f64(left, bottom);
+        let (x1, y1) = projection.project_f64(left, top);
+        let projected = (x1 - x0).hypot(y1 - y0);
+        assert!(projected > ground && projected <= ground * max_scale + 1e-6);
+        assert!(max_scale < 1.0 + 1e-6);
+
+        // False easting and northing are undone on the way back
+        let (lon, lat) = projection.unproject_f64(width as f64 / 2.0, height as f64 / 2.0);
+        assert!((lon - projection.origin_lon).abs() < 1e-4);
+        assert!((lat - projection.origin_lat).abs() < 1e-3);
+    }
+}
@@ -0,0 +1,123 @@
+use std::{
+    fmt::Display,
+    fs::{self, File},
+    io::{self, BufWriter},
+    path::Path,
+};
+
+use osmpbf::HeaderBBox;
+use serde::{Deserialize, Serialize};
+
+use super::{import::HeaderBBoxDef, NodeControl, StreetNetwork};
+
+/// Summary of what an OSM import loaded, and of the problems found along the way, for checking a
+/// new study area before simulating on it.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ImportReport {
+    /// OSM nodes referenced by the walkable ways, after clipping
+    pub osm_nodes: usize,
+    /// Walkable ways loaded, after clipping
+    pub osm_ways: usize,
+    /// Highway-tagged ways rejected by the walkability filter
+    pub filtered_ways: usize,
+    /// Ways with too few nodes to form a segment
+    pub degenerate_ways: usize,
+    /// Node-to-node segments across all loaded ways, as mapped; links across plazas are counted
+    /// only in `plaza_links`
+    pub segments: usize,
+    /// Summed length of all mapped segments, each counted once regardless of direction, in metres
+    pub total_length: f64,
+    /// Segment endpoints referring to nodes missing from the extract
+    pub dangling_references: usize,
+    pub zero_length_segments: usize,
+    /// Segments joining a pair of nodes already joined by an earlier segment, in either direction
+    pub duplicate_segments: usize,
+    /// Pedestrian plazas found, and segments added to link nodes across them
+    pub plazas: usize,
+    pub plaza_links: usize,
+    /// Nodes in the final network
+    pub nodes: usize,
+    /// Directed edges in the final network; two-way streets count twice
+    pub edges: usize,
+    /// Edges in the final network that start and end at the same node
+    pub self_loops: usize,
+    /// Nodes in the final network at mapped pedestrian crossings
+    pub crossings: usize,
+    /// Nodes in the final network with traffic or pedestrian signals
+    pub signalised_nodes: usize,
+    /// Lon/lat extent of the extract
+    #[serde(with = "HeaderBBoxDef")]
+    pub bounding_box: HeaderBBox,
+}
+
+impl ImportReport {
+    /// Fill in the counts describing the network finally built from the extract.
+    pub fn record_network(&mut self, network: &StreetNetwork) {
+        let edges = network.edge_list();
+        self.nodes = network.node_ids().len();
+        self.edges = edges.len();
+        self.self_loops = edges.iter().filter(|edge| edge.u == edge.v).count();
+
+        let controls: Vec<NodeControl> = network
+            .node_ids()
+            .into_iter()
+            .filter_map(|id| network.0.get_object(id).map(|node| node.control))
+            .collect();
+        self.crossings = controls
+            .iter()
+            .filter(|control| !matches!(control, NodeControl::None | NodeControl::TrafficSignals))
+            .count();
+        self.signalised_nodes = controls.iter().filter(|c| c.is_signalised()).count();
+    }
+
+    pub fn write_json(&self, path: &Path) -> io::Result<()> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let writer = BufWriter::new(File::create(path)?);
+        serde_json::to_writer_pretty(writer, self)?;
+        Ok(())
+    }
+}
+
+impl Display for ImportReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let bbox = &self.bounding_box;
+        writeln!(f, "Import report")?;
+        writeln!(
+            f,
+            "  Extent: lon {:.5} to {:.5}, lat {:.5} to {:.5}",
+            bbox.left, bbox.right, bbox.bottom, bbox.top
+        )?;
+        writeln!(
+            f,
+            "  OSM: {} nodes, {} ways ({} filtered out, {} degenerate), {} segments",
+            self.osm_nodes, self.osm_ways, self.filtered_ways, self.degenerate_ways, self.segments
+        )?;
+        writeln!(
+            f,
+            "  Plazas: {}, crossed by {} links",
+            self.plazas, self.plaza_links
+        )?;
+        writeln!(
+            f,
+            "  Total street length: {:.2} km",
+            self.total_length / 1000.0
+        )?;
+        writeln!(
+            f,
+            "  Dangling references: {}, zero-length segments: {}, duplicate segments: {}",
+            self.dangling_references, self.zero_length_segments, self.duplicate_segments
+        )?;
+        writeln!(
+            f,
+            "  Network: {} nodes, {} edges, {} self-loops",
+            self.nodes, self.edges, self.self_loops
+        )?;
+        writeln!(
+            f,
+            "  Crossings: {}, signalised nodes: {}",
+            self.crossings, self.signalised_nodes
+        )
+    }
+}
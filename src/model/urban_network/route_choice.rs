@@ -0,0 +1,579 @@
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{HashMap, HashSet};
+use std::f64::consts::TAU;
+use std::hash::{Hash, Hasher};
+
+use krabmaga::engine::location::Real2D;
+use krabmaga::rand::Rng;
+
+use super::routing::PartialLeg;
+use super::{
+    HighwayClass, IntersectionDelays, Route, Sidewalk, StreetEdgeLabel, StreetNetwork,
+    StreetNetworkPosition,
+};
+
+/// Values of `surface` counted as unpaved.
+const UNPAVED_SURFACES: [&str; 12] = [
+    "unpaved",
+    "compacted",
+    "fine_gravel",
+    "gravel",
+    "pebblestone",
+    "ground",
+    "dirt",
+    "earth",
+    "grass",
+    "mud",
+    "sand",
+    "woodchips",
+];
+
+/// How much an agent minds street attributes besides distance. Each weight is a penalty in metres
+/// per metre walked on edges with the attribute: an edge costs its length times one plus the
+/// penalties that apply, so all-zero weights give shortest paths and no edge costs less than its
+/// length.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct RouteWeights {
+    /// Walking along tertiary or larger roads
+    pub major_road: f32,
+    /// Walking along roads known to have no sidewalk
+    pub no_sidewalk: f32,
+    /// Walking along edges known to be unlit
+    pub unlit: f32,
+    /// Walking on unpaved surfaces
+    pub unpaved: f32,
+    /// Taking steps
+    pub steps: f32,
+    /// Per percent of incline climbed
+    pub climb: f32,
+}
+
+impl RouteWeights {
+    /// Weights that rank routes by length alone.
+    pub fn distance_only() -> Self {
+        RouteWeights {
+            major_road: 0.0,
+            no_sidewalk: 0.0,
+            unlit: 0.0,
+            unpaved: 0.0,
+            steps: 0.0,
+            climb: 0.0,
+        }
+    }
+
+    /// Copy of these weights with each scaled by an independent factor drawn uniformly from
+    /// `1 - spread` to `1 + spread`, to vary preferences across a population of agents.
+    pub fn varied(&self, spread: f32, rng: &mut impl Rng) -> Self {
+        let spread = spread.clamp(0.0, 1.0);
+        let mut vary = |weight: f32| weight * (1.0 + spread * rng.gen_range(-1.0..=1.0));
+        RouteWeights {
+            major_road: vary(self.major_road),
+            no_sidewalk: vary(self.no_sidewalk),
+            unlit: vary(self.unlit),
+            unpaved: vary(self.unpaved),
+            steps: vary(self.steps),
+            climb: vary(self.climb),
+        }
+    }
+
+    /// Generalised cost of walking the whole of an edge, in metres.
+    pub fn edge_cost(&self, label: &StreetEdgeLabel) -> f32 {
+        let mut penalty = 0.0;
+        if matches!(
+            label.highway,
+            HighwayClass::Tertiary
+                | HighwayClass::Secondary
+                | HighwayClass::Primary
+                | HighwayClass::Trunk
+        ) {
+            penalty += self.major_road;
+        }
+        if label.sidewalk == Sidewalk::No {
+            penalty += self.no_sidewalk;
+        }
+        if label.lit == Some(false) {
+            penalty += self.unlit;
+        }
+        if label
+            .surface
+            .as_deref()
+            .is_some_and(|surface| UNPAVED_SURFACES.contains(&surface))
+        {
+            penalty += self.unpaved;
+        }
+        if label.highway == HighwayClass::Steps {
+            penalty += self.steps;
+        }
+        penalty += self.climb * label.incline.unwrap_or(0.0).max(0.0);
+        label.len * (1.0 + penalty.max(0.0))
+    }
+
+    fn is_distance_only(&self) -> bool {
+        *self == RouteWeights::distance_only()
+    }
+}
+
+impl Default for RouteWeights {
+    /// Moderate aversion to traffic, missing sidewalks, rough ground, steps and hills; unlit
+    /// streets only matter after dark, so are not penalised.
+    fn default() -> Self {
+        RouteWeights {
+            major_road: 0.2,
+            no_sidewalk: 0.3,
+            unlit: 0.0,
+            unpaved: 0.1,
+            steps: 0.5,
+            climb: 0.05,
+        }
+    }
+}
+
+/// How an agent picks among the routes to its destination.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum RouteChoiceModel {
+    /// Always the least-cost route
+    LeastCost,
+    /// Path-size logit over the `k` least-cost routes. A route's utility is `-scale` times its
+    /// cost relative to the cheapest, plus `path_size` times the log of its path size, which
+    /// discounts routes for the share of their length that overlaps the others.
+    PathSizeLogit {
+        k: usize,
+        scale: f32,
+        path_size: f32,
+    },
+    /// Least-cost route after multiplying the cost of each street segment by log-normal noise,
+    /// with `sigma` the standard deviation of its log, drawn afresh for every route planned
+    Perturbed { sigma: f32 },
+}
+
+/// An agent's route choice: its preferences over street attributes and how it chooses.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct RouteChoice {
+    pub weights: RouteWeights,
+    pub model: RouteChoiceModel,
+}
+
+impl RouteChoice {
+    /// Plain shortest paths.
+    pub fn shortest() -> Self {
+        RouteChoice {
+            weights: RouteWeights::distance_only(),
+            model: RouteChoiceModel::LeastCost,
+        }
+    }
+
+    /// Default street preferences, choosing by path-size logit among the five least-cost routes.
+    pub fn path_size_logit() -> Self {
+        RouteChoice {
+            weights: RouteWeights::default(),
+            model: RouteChoiceModel::PathSizeLogit {
+                k: 5,
+                scale: 10.0,
+                path_size: 1.0,
+            },
+        }
+    }
+
+    /// True if this choice always takes the shortest path, so any shortest-path search will do.
+    pub fn is_shortest(&self) -> bool {
+        self.model == RouteChoiceModel::LeastCost && self.weights.is_distance_only()
+    }
+
+    /// Draw a route between two positions, counting the expected waits at nodes along the way when
+    /// `delays` are given. `max_scale` is the projection's maximum scale factor, which keeps the
+    /// directed searches exact. Returns `None` if the destination cannot be reached.
+    pub fn choose(
+        &self,
+        network: &StreetNetwork,
+        from: &StreetNetworkPosition,
+        to: &StreetNetworkPosition,
+        delays: Option<&IntersectionDelays>,
+        max_scale: f32,
+        rng: &mut impl Rng,
+    ) -> Option<Route> {
+        let weights = self.weights;
+        let cost = move |label: &StreetEdgeLabel| weights.edge_cost(label);
+        // No edge costs less than its length, so the searches may be directed
+        let target = to.location(network).map(|loc| (loc, max_scale));
+        match self.model {
+            RouteChoiceModel::LeastCost => network.route_by_cost(from, to, &cost, delays, target),
+            RouteChoiceModel::PathSizeLogit {
+                k,
+                scale,
+                path_size,
+            } => {
+                let routes = network.k_shortest_routes(from, to, k.max(1), &cost, delays, target);
+                let probabilities = path_size_logit(network, &routes, scale, path_size);
+                let mut draw = rng.gen::<f64>();
+                let chosen = probabilities
+                    .iter()
+                    .position(|p| {
+                        draw -= p;
+                        draw < 0.0
+                    })
+                    .unwrap_or(probabilities.len().saturating_sub(1));
+                routes.into_iter().nth(chosen)
+            }
+            RouteChoiceModel::Perturbed { sigma } => {
+                let seed: u64 = rng.gen();
+                let perturbed = |label: &StreetEdgeLabel| {
+                    cost(label) * (sigma * standard_normal(label.id, seed)).exp()
+                };
+                // Noise can make an edge cost less than its length, so the search is undirected
+                let route = network.route_by_cost(from, to, &perturbed, delays, None)?;
+                Some(Route {
+                    cost: route_cost(network, &route, from, to, &cost, delays),
+                    ..route
+                })
+            }
+        }
+    }
+}
+
+impl Default for RouteChoice {
+    /// Plain shortest paths, which may be answered by a contraction hierarchy; stochastic choice
+    /// is opted into by choosing another model.
+    fn default() -> Self {
+        RouteChoice::shortest()
+    }
+}
+
+impl StreetNetwork {
+    /// Up to `k` least-cost routes between two positions that visit no node twice, cheapest first,
+    /// found by Yen's algorithm. `delays` and `target` are as in `route_by_cost`.
+    pub fn k_shortest_routes(
+        &self,
+        from: &StreetNetworkPosition,
+        to: &StreetNetworkPosition,
+        k: usize,
+        cost: &dyn Fn(&StreetEdgeLabel) -> f32,
+        delays: Option<&IntersectionDelays>,
+        target: Option<(Real2D, f32)>,
+    ) -> Vec<Route> {
+        let departures = self.departures(from, cost);
+        let arrivals = self.arrivals(to, cost);
+        let direct = self.direct_route(from, to, cost);
+        let Some(first) = self.route_by_cost(from, to, cost, delays, target) else {
+            return Vec::new();
+        };
+        let mut candidates: Vec<Route> = direct
+            .filter(|direct| direct.edges != first.edges)
+            .into_iter()
+            .collect();
+        let mut routes = vec![first];
+
+        while routes.len() < k {
+            let last = routes.last().unwrap();
+            let skipped = departure_edges(last);
+            let prefix = self.prefix_costs(last, &departures, cost, delays);
+
+            // Leave the start position towards a node no route found so far leaves it to
+            let used_first: HashSet<u32> = routes
+                .iter()
+                .filter_map(|r| r.nodes.first())
+                .copied()
+                .collect();
+            let spur_departures: Vec<PartialLeg> = departures
+                .iter()
+                .filter(|leg| !used_first.contains(&leg.node))
+                .copied()
+                .collect();
+            let mut spurs: Vec<Route> = self
+                .search(
+                    spur_departures,
+                    &arrivals,
+                    cost,
+                    delays,
+                    &|_, _| true,
+                    target,
+                )
+                .into_iter()
+                .collect();
+
+            // Branch off the last route at each of its nodes in turn
+            for (j, &spur) in last.nodes.iter().enumerate() {
+                let root_nodes = &last.nodes[..=j];
+                let root_edges = &last.edges[..skipped + j];
+                let mut blocked_edges: HashSet<(u32, u32)> = HashSet::new();
+                let mut ends_here = false;
+                for route in routes.iter() {
+                    if route.nodes.get(..=j) != Some(root_nodes)
+                        || route.edges.get(..skipped + j) != Some(root_edges)
+                    {
+                        continue;
+                    }
+                    match route.nodes.get(j + 1) {
+                        Some(next) => {
+                            blocked_edges.insert((spur, *next));
+                        }
+                        None => ends_here = true,
+                    }
+                }
+                let root_set: HashSet<u32> = last.nodes[..j].iter().copied().collect();
+                let passable =
+                    |u: u32, v: u32| !root_set.contains(&v) && !blocked_edges.contains(&(u, v));
+                let spur_arrivals: Vec<PartialLeg> = arrivals
+                    .iter()
+                    .filter(|leg| !(root_set.contains(&leg.node) || ends_here && leg.node == spur))
+                    .copied()
+                    .collect();
+                let start = PartialLeg {
+                    node: spur,
+                    edge: (spur, spur),
+                    length: 0.0,
+                    cost: 0.0,
+                };
+                let Some(spur_route) =
+                    self.search(vec![start], &spur_arrivals, cost, delays, &passable, target)
+                else {
+                    continue;
+                };
+                let (root_length, root_cost) = prefix[j];
+                spurs.push(Route {
+                    edges: root_edges
+                        .iter()
+                        .chain(spur_route.edges.iter())
+                        .copied()
+                        .collect(),
+                    nodes: last.nodes[..j]
+                        .iter()
+                        .chain(spur_route.nodes.iter())
+                        .copied()
+                        .collect(),
+                    length: root_length + spur_route.length,
+                    cost: root_cost + spur_route.cost,
+                });
+            }
+
+            for spur in spurs {
+                let known = |route: &Route| route.edges == spur.edges;
+                if !routes.iter().any(known) && !candidates.iter().any(known) {
+                    candidates.push(spur);
+                }
+            }
+            let Some(cheapest) = (0..candidates.len())
+                .min_by(|a, b| candidates[*a].cost.total_cmp(&candidates[*b].cost))
+            else {
+                break;
+            };
+            routes.push(candidates.swap_remove(cheapest));
+        }
+        routes
+    }
+
+    /// Length and cost walked from the start of a route to each of its nodes, before waiting there.
+    fn prefix_costs(
+        &self,
+        route: &Route,
+        departures: &[PartialLeg],
+        cost: &dyn Fn(&StreetEdgeLabel) -> f32,
+        delays: Option<&IntersectionDelays>,
+    ) -> Vec<(f32, f32)> {
+        let mut walked = match departure_edges(route) {
+            0 => (0.0, 0.0),
+            _ => departures
+                .iter()
+                .find(|leg| leg.edge == route.edges[0])
+                .map_or((0.0, 0.0), |leg| (leg.length, leg.cost)),
+        };
+        let mut prefix = vec![walked];
+        for pair in route.nodes.windows(2) {
+            walked.1 += self.delay_cost(pair[0], delays);
+            if let Some(label) = self.get_edge_by_ids(pair[0], pair[1]).and_then(|e| e.label) {
+                walked = (walked.0 + label.len, walked.1 + cost(&label));
+            }
+            prefix.push(walked);
+        }
+        prefix
+    }
+}
+
+/// Number of edges a route walks before its first node: one if it starts partway along an edge.
+fn departure_edges(route: &Route) -> usize {
+    match (route.edges.first(), route.nodes.first()) {
+        (Some(edge), Some(node)) if edge.1 == *node => 1,
+        _ => 0,
+    }
+}
+
+/// Cost of a route under another cost function than it was found with.
+fn route_cost(
+    network: &StreetNetwork,
+    route: &Route,
+    from: &StreetNetworkPosition,
+    to: &StreetNetworkPosition,
+    cost: &dyn Fn(&StreetEdgeLabel) -> f32,
+    delays: Option<&IntersectionDelays>,
+) -> f32 {
+    if route.nodes.is_empty() {
+        return network
+            .direct_route(from, to, cost)
+            .map_or(route.cost, |direct| direct.cost);
+    }
+    let departures = network.departures(from, cost);
+    let (_, mut total) = *network
+        .prefix_costs(route, &departures, cost, delays)
+        .last()
+        .unwrap();
+    let last = route.nodes.last().unwrap();
+    total += network.delay_cost(*last, delays);
+    if route.edges.last().is_some_and(|edge| edge.0 == *last) {
+        total += network
+            .arrivals(to, cost)
+            .iter()
+            .find(|leg| Some(&leg.edge) == route.edges.last())
+            .map_or(0.0, |leg| leg.cost);
+    }
+    total
+}
+
+/// Choice probabilities of routes under a path-size logit model. Overlap is measured by street
+/// segment, so walking a street in either direction counts as the same, and each edge counts at its
+/// full length.
+fn path_size_logit(
+    network: &StreetNetwork,
+    routes: &[Route],
+    scale: f32,
+    path_size: f32,
+) -> Vec<f64> {
+    let segments: Vec<Vec<(u64, f32)>> = routes
+        .iter()
+        .map(|route| {
+            route
+                .edges
+                .iter()
+                .filter_map(|(u, v)| network.get_edge_by_ids(*u, *v).and_then(|e| e.label))
+                .map(|label| (label.id, label.len))
+                .collect()
+        })
+        .collect();
+    let mut routes_per_segment: HashMap<u64, usize> = HashMap::new();
+    for route_segments in segments.iter() {
+        let unique: HashSet<u64> = route_segments.iter().map(|(id, _)| *id).collect();
+        for id in unique {
+            *routes_per_segment.entry(id).or_default() += 1;
+        }
+    }
+
+    let min_cost = routes
+        .iter()
+        .map(|route| route.cost)
+        .fold(f32::INFINITY, f32::min)
+        .max(f32::EPSILON);
+    let utilities: Vec<f64> = routes
+        .iter()
+        .zip(segments.iter())
+        .map(|(route, route_segments)| {
+            let total: f32 = route_segments.iter().map(|(_, len)| len).sum();
+            let size: f32 = if total > 0.0 {
+                route_segments
+                    .iter()
+                    .map(|(id, len)| len / total / routes_per_segment[id] as f32)
+                    .sum()
+            } else {
+                1.0
+            };
+            (-scale * route.cost / min_cost + path_size * size.ln()) as f64
+        })
+        .collect();
+    let max_utility = utilities.iter().copied().fold(f64::NEG_INFINITY, f64::max);
+    let weights: Vec<f64> = utilities.iter().map(|u| (u - max_utility).exp()).collect();
+    let sum: f64 = weights.iter().sum();
+    weights.iter().map(|w| w / sum).collect()
+}
+
+/// Standard normal deviate determined by a segment ID and seed, so that every edge of a segment
+/// gets the same noise within one perturbed search.
+fn standard_normal(id: u64, seed: u64) -> f32 {
+    let mut hasher = DefaultHasher::new();
+    (id, seed).hash(&mut hasher);
+    let bits = hasher.finish();
+    let u1 = ((bits >> 32) as f64 + 1.0) / (u32::MAX as f64 + 2.0);
+    let u2 = (bits & 0xffff_ffff) as f64 / (u32::MAX as f64 + 1.0);
+    ((-2.0 * u1.ln()).sqrt() * (TAU * u2).cos()) as f32
+}
+
+#[cfg(test)]
+mod tests {
+    use krabmaga::rand::{rngs::StdRng, SeedableRng};
+
+    use super::*;
+    use crate::model::urban_network::test_support::{
+        assert_close, assert_connected, organic_network, random_pairs,
+    };
+
+    #[test]
+    fn k_shortest_routes_are_distinct_loopless_and_sorted() {
+        let mut rng = StdRng::seed_from_u64(24);
+        let network = organic_network(&mut rng);
+        let weights = RouteWeights::default();
+        let cost = |label: &StreetEdgeLabel| weights.edge_cost(label);
+        let delays = IntersectionDelays::default();
+        for (from, to) in random_pairs(&network, 50, &mut rng) {
+            let routes = network.k_shortest_routes(&from, &to, 5, &cost, Some(&delays), None);
+            assert!(!routes.is_empty() && routes.len() <= 5);
+
+            let cheapest = network
+                .route_by_cost(&from, &to, &cost, Some(&delays), None)
+                .unwrap();
+            assert_close(routes[0].cost, cheapest.cost);
+            for pair in routes.windows(2) {
+                assert!(pair[0].cost <= pair[1].cost + 1e-3, "routes out of order");
+            }
+            for (i, route) in routes.iter().enumerate() {
+                assert_connected(route);
+                let visited: HashSet<u32> = route.nodes.iter().copied().collect();
+                assert_eq!(
+                    visited.len(),
+                    route.nodes.len(),
+                    "route visits a node twice"
+                );
+                assert_close(
+                    route_cost(&network, route, &from, &to, &cost, Some(&delays)),
+                    route.cost,
+                );
+                for other in routes[..i].iter() {
+                    assert_ne!(route.edges, other.edges, "route found twice");
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn directed_searches_find_the_least_cost_routes() {
+        let mut rng = StdRng::seed_from_u64(25);
+        let network = organic_network(&mut rng);
+        let weights = RouteWeights::default();
+        let cost = |label: &StreetEdgeLabel| weights.edge_cost(label);
+        for (from, to) in random_pairs(&network, 50, &mut rng) {
+            let target = to.location(&network).map(|loc| (loc, 1.0));
+            let undirected = network.k_shortest_routes(&from, &to, 3, &cost, None, None);
+            let directed = network.k_shortest_routes(&from, &to, 3, &cost, None, target);
+            assert_eq!(undirected.len(), directed.len());
+            for (a, b) in undirected.iter().zip(directed.iter()) {
+                assert_close(a.cost, b.cost);
+            }
+        }
+    }
+
+    #[test]
+    fn least_cost_choice_is_the_cheapest_route() {
+        let mut rng = StdRng::seed_from_u64(26);
+        let network = organic_network(&mut rng);
+        let choice = RouteChoice {
+            model: RouteChoiceModel::LeastCost,
+            ..RouteChoice::path_size_logit()
+        };
+        let delays = IntersectionDelays::default();
+        let cost = |label: &StreetEdgeLabel| choice.weights.edge_cost(label);
+        for (from, to) in random_pairs(&network, 30, &mut rng) {
+            let chosen = choice
+                .choose(&network, &from, &to, Some(&delays), 1.0, &mut rng)
+                .unwrap();
+            let cheapest = network
+                .route_by_cost(&from, &to, &cost, Some(&delays), None)
+                .unwrap();
+            assert_close(chosen.cost, cheapest.cost);
+        }
+    }
+}
@@ -0,0 +1,500 @@
+use std::cmp::Ordering;
+use std::collections::{BinaryHeap, HashMap};
+
+use krabmaga::engine::location::Real2D;
+
+use super::{IntersectionDelays, StreetEdgeLabel, StreetNetwork, StreetNetworkPosition};
+use crate::WALKING_SPEED;
+
+/// Search strategy for `StreetNetwork::route`.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub enum RoutingAlgorithm {
+    #[default]
+    Dijkstra,
+    /// A* guided by the straight-line distance to the destination. Edge lengths are measured along
+    /// the ground but node locations are projected, so the distance is shrunk by `max_scale`, the
+    /// most the projection stretches distances (1 for networks that are not projected), to keep the
+    /// heuristic from overestimating.
+    AStar { max_scale: f32 },
+}
+
+/// A walk between two positions on the street network.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Route {
+    /// Edges walked, in order, as (source, target) network node IDs. The first and last edges
+    /// may be walked only in part, from or to a point along them.
+    pub edges: Vec<(u32, u32)>,
+    /// Network nodes passed through, in order
+    pub nodes: Vec<u32>,
+    /// Distance walked, in metres
+    pub length: f32,
+    /// Total cost of the route under the cost function it was found with; equal to `length` for
+    /// shortest paths
+    pub cost: f32,
+}
+
+/// Heap entry ordered so that `BinaryHeap` pops the lowest priority first.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub(crate) struct QueueEntry {
+    pub priority: f32,
+    pub node: u32,
+}
+
+impl Eq for QueueEntry {}
+
+impl Ord for QueueEntry {
+    fn cmp(&self, other: &Self) -> Ordering {
+        other
+            .priority
+            .total_cmp(&self.priority)
+            .then_with(|| other.node.cmp(&self.node))
+    }
+}
+
+impl PartialOrd for QueueEntry {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+/// One way of leaving the start position or reaching the destination along its edge: the node at
+/// the end of the partial walk, the partial edge as (source, target), and the length and cost of
+/// the walk along it.
+#[derive(Copy, Clone, Debug)]
+pub(crate) struct PartialLeg {
+    pub node: u32,
+    pub edge: (u32, u32),
+    pub length: f32,
+    pub cost: f32,
+}
+
+impl StreetNetwork {
+    /// Label of the edge from `u` to `v`, if the network has one.
+    pub(crate) fn edge_label(&self, u: u32, v: u32) -> Option<StreetEdgeLabel> {
+        self.get_edge_by_ids(u, v).and_then(|edge| edge.label)
+    }
+
+    /// Length of the shortest of the edges from `u` to `v`, which is the one a search by length
+    /// takes.
+    pub(crate) fn shortest_edge_length(&self, u: u32, v: u32) -> Option<f32> {
+        let network = &self.0;
+        network.edges[network.read]
+            .borrow()
+            .get(&u)?
+            .iter()
+            .filter(|edge| edge.v == v)
+            .filter_map(|edge| edge.label.as_ref().map(|label| label.len))
+            .min_by(f32::total_cmp)
+    }
+
+    /// Expected wait at a node's crossing or signal, as the distance that could be walked in that
+    /// time, or zero without delays to charge.
+    pub fn delay_cost(&self, node: u32, delays: Option<&IntersectionDelays>) -> f32 {
+        match (delays, self.0.get_object(node)) {
+            (Some(delays), Some(node)) => delays.expected_delay(node.control) * WALKING_SPEED,
+            _ => 0.0,
+        }
+    }
+
+    /// Ways of walking off the edge a position lies on, towards either end where the street may be
+    /// walked in that direction.
+    pub(crate) fn departures(
+        &self,
+        from: &StreetNetworkPosition,
+        cost: &dyn Fn(&StreetEdgeLabel) -> f32,
+    ) -> Vec<PartialLeg> {
+        let (a, b) = (from.from_node, from.to_node);
+        let mut legs = Vec::new();
+        if let Some(label) = self.edge_label(a, b) {
+            let length = (label.len - from.edge_dist).max(0.0);
+            legs.push(PartialLeg {
+                node: b,
+                edge: (a, b),
+                length,
+                cost: cost(&label) * fraction(length, label.len),
+            });
+        }
+        if let Some(label) = self.edge_label(b, a) {
+            let length = from.edge_dist.min(label.len);
+            legs.push(PartialLeg {
+                node: a,
+                edge: (b, a),
+                length,
+                cost: cost(&label) * fraction(length, label.len),
+            });
+        }
+        legs
+    }
+
+    /// Ways of reaching a position from either end of its edge, where the street may be walked in
+    /// that direction.
+    pub(crate) fn arrivals(
+        &self,
+        to: &StreetNetworkPosition,
+        cost: &dyn Fn(&StreetEdgeLabel) -> f32,
+    ) -> Vec<PartialLeg> {
+        let (c, d) = (to.from_node, to.to_node);
+        let mut legs = Vec::new();
+        if let Some(label) = self.edge_label(c, d) {
+            let length = to.edge_dist.min(label.len);
+            legs.push(PartialLeg {
+                node: c,
+                edge: (c, d),
+                length,
+                cost: cost(&label) * fraction(length, label.len),
+            });
+        }
+        if let Some(label) = self.edge_label(d, c) {
+            let length = (label.len - to.edge_dist).max(0.0);
+            legs.push(PartialLeg {
+                node: d,
+                edge: (d, c),
+                length,
+                cost: cost(&label) * fraction(length, label.len),
+            });
+        }
+        legs
+    }
+
+    /// A walk between two positions on the same street that stays on it, if the street may be
+    /// walked in the direction needed.
+    pub(crate) fn direct_route(
+        &self,
+        from: &StreetNetworkPosition,
+        to: &StreetNetworkPosition,
+        cost: &dyn Fn(&StreetEdgeLabel) -> f32,
+    ) -> Option<Route> {
+        let (a, b) = (from.from_node, from.to_node);
+        let to_dist = if (to.from_node, to.to_node) == (a, b) {
+            to.edge_dist
+        } else if (to.from_node, to.to_node) == (b, a) {
+            let reverse_length = self.edge_label(b, a)?.len;
+            reverse_length - to.edge_dist
+        } else {
+            return None;
+        };
+
+        // Walk forward along (a, b), or backward along (b, a)
+        let (edge, length) = if to_dist >= from.edge_dist {
+            ((a, b), to_dist - from.edge_dist)
+        } else {
+            ((b, a), from.edge_dist - to_dist)
+        };
+        let label = self.edge_label(edge.0, edge.1)?;
+        Some(Route {
+            edges: vec![edge],
+            nodes: Vec::new(),
+            length,
+            cost: cost(&label) * fraction(length, label.len),
+        })
+    }
+
+    /// Shortest route by length between two positions on the network, which may lie partway along
+    /// edges, counting the expected waits at the nodes passed if `delays` are given. Returns `None`
+    /// if the destination cannot be reached.
+    pub fn route(
+        &self,
+        from: &StreetNetworkPosition,
+        to: &StreetNetworkPosition,
+        algorithm: RoutingAlgorithm,
+        delays: Option<&IntersectionDelays>,
+    ) -> Option<Route> {
+        let target = match algorithm {
+            RoutingAlgorithm::Dijkstra => None,
+            RoutingAlgorithm::AStar { max_scale } => to.location(self).map(|loc| (loc, max_scale)),
+        };
+        self.route_by_cost(from, to, &|label| label.len, delays, target)
+    }
+
+    /// Least-cost route between two positions under an edge cost function, charged in proportion
+    /// to the part of an edge walked. With `delays`, each node passed through also costs its
+    /// expected wait, as `delay_cost` gives it.
+    ///
+    /// When `target` is given, as the destination's location and the projection's maximum scale
+    /// factor, the search is directed by the straight-line distance from each node to it shrunk by
+    /// that factor, which only finds least-cost routes if no edge costs less than its length.
+    pub fn route_by_cost(
+        &self,
+        from: &StreetNetworkPosition,
+        to: &StreetNetworkPosition,
+        cost: &dyn Fn(&StreetEdgeLabel) -> f32,
+        delays: Option<&IntersectionDelays>,
+        target: Option<(Real2D, f32)>,
+    ) -> Option<Route> {
+        let found = self.search(
+            self.departures(from, cost),
+            &self.arrivals(to, cost),
+            cost,
+            delays,
+            &|_, _| true,
+            target,
+        );
+        // Staying on the street passes no node, so may beat any route through one
+        match (self.direct_route(from, to, cost), found) {
+            (Some(direct), Some(found)) if found.cost < direct.cost => Some(found),
+            (Some(direct), _) => Some(direct),
+            (None, found) => found,
+        }
+    }
+
+    /// Least-cost route from any of `departures` to any of `arrivals` through at least one node,
+    /// walking only edges (source, target) that `passable` allows. The wait at each node is
+    /// charged on leaving it, so a search starting at a node counts its wait once.
+    pub(crate) fn search(
+        &self,
+        departures: Vec<PartialLeg>,
+        arrivals: &[PartialLeg],
+        cost: &dyn Fn(&StreetEdgeLabel) -> f32,
+        delays: Option<&IntersectionDelays>,
+        passable: &dyn Fn(u32, u32) -> bool,
+        target: Option<(Real2D, f32)>,
+    ) -> Option<Route> {
+        let mut best_arrival: Option<(PartialLeg, f32)> = None;
+        let estimate = |node: u32| match (target, self.0.get_object(node)) {
+            (Some((target, max_scale)), Some(node)) => {
+                ((node.loc.x - target.x).powi(2) + (node.loc.y - target.y).powi(2)).sqrt()
+                    / max_scale
+            }
+            _ => 0.0,
+        };
+
+        let mut costs: HashMap<u32, f32> = HashMap::new();
+        let mut lengths: HashMap<u32, f32> = HashMap::new();
+        // How each node was reached: from another node, or straight from the start position
+        let mut previous: HashMap<u32, Result<u32, PartialLeg>> = HashMap::new();
+        let mut queue = BinaryHeap::new();
+        for leg in departures {
+            if costs.get(&leg.node).is_none_or(|c| leg.cost < *c) {
+                costs.insert(leg.node, leg.cost);
+                lengths.insert(leg.node, leg.length);
+                previous.insert(leg.node, Err(leg));
+                queue.push(QueueEntry {
+                    priority: leg.cost + estimate(leg.node),
+                    node: leg.node,
+                });
+            }
+        }
+
+        let network = &self.0;
+        let edges = network.edges[network.read].borrow();
+        while let Some(QueueEntry { priority, node }) = queue.pop() {
+            let bound = best_arrival.map(|(_, total)| total);
+            if bound.is_some_and(|bound| priority >= bound) {
+                break;
+            }
+            let cost_here = costs[&node];
+            if priority > cost_here + estimate(node) {
+                // Stale entry for a node since reached more cheaply
+                continue;
+            }
+            let leaving = cost_here + self.delay_cost(node, delays);
+
+            for leg in arrivals.iter().filter(|leg| leg.node == node) {
+                let total = leaving + leg.cost;
+                if best_arrival.is_none_or(|(_, best)| total < best) {
+                    best_arrival = Some((*leg, total));
+                }
+            }
+
+            for edge in edges.get(&node).map(Vec::as_slice).unwrap_or_default() {
+                let Some(label) = edge.label.as_ref().filter(|_| passable(node, edge.v)) else {
+                    continue;
+                };
+                let next_cost = leaving + cost(label);
+                if costs.get(&edge.v).is_none_or(|c| next_cost < *c) {
+                    costs.insert(edge.v, next_cost);
+                    lengths.insert(edge.v, lengths[&node] + label.len);
+                    previous.insert(edge.v, Ok(node));
+                    queue.push(QueueEntry {
+                        priority: next_cost + estimate(edge.v),
+                        node: edge.v,
+                    });
+                }
+            }
+        }
+
+        let (arrival, total) = best_arrival?;
+
+        // Walk back from the node the destination was reached from
+        let mut nodes = vec![arrival.node];
+        let mut edges_walked = Vec::new();
+        let departure = loop {
+            let node = *nodes.last().unwrap();
+            match previous[&node] {
+                Ok(prev) => {
+                    edges_walked.push((prev, node));
+                    nodes.push(prev);
+                }
+                Err(leg) => break leg,
+            }
+        };
+        nodes.reverse();
+        edges_walked.reverse();
+
+        let mut route_edges = Vec::with_capacity(edges_walked.len() + 2);
+        if departure.length > 0.0 {
+            route_edges.push(departure.edge);
+        }
+        route_edges.extend(edges_walked);
+        if arrival.length > 0.0 {
+            route_edges.push(arrival.edge);
+        }
+        Some(Route {
+            edges: route_edges,
+            nodes,
+            length: lengths[&arrival.node] + arrival.length,
+            cost: total,
+        })
+    }
+}
+
+/// Share of an edge of length `total` covered by walking `part` of it.
+fn fraction(part: f32, total: f32) -> f32 {
+    if total > 0.0 {
+        part / total
+    } else {
+        0.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use krabmaga::engine::fields::field::Field;
+    use krabmaga::engine::fields::network::{EdgeOptions, Network};
+    use krabmaga::rand::{rngs::StdRng, Rng, SeedableRng};
+    use petgraph::algo::dijkstra;
+    use petgraph::graph::{DiGraph, NodeIndex};
+
+    use super::*;
+    use crate::model::urban_network::test_support::{
+        assert_close, assert_connected, organic_network, random_pairs,
+    };
+    use crate::model::urban_network::{NodeControl, StreetNode, SyntheticLayout};
+
+    #[test]
+    fn routes_between_nodes_match_petgraph_dijkstra() {
+        let mut rng = StdRng::seed_from_u64(22);
+        let network = organic_network(&mut rng);
+        let edges = network.edge_list();
+        let mut graph = DiGraph::<u32, f32>::new();
+        let index: HashMap<u32, NodeIndex> = network
+            .node_ids()
+            .into_iter()
+            .map(|id| (id, graph.add_node(id)))
+            .collect();
+        for edge in edges.iter() {
+            graph.add_edge(
+                index[&edge.u],
+                index[&edge.v],
+                edge.label.as_ref().unwrap().len,
+            );
+        }
+
+        for _ in 0..50 {
+            // Positions at the start of an edge stand on its first node
+            let start = &edges[rng.gen_range(0..edges.len())];
+            let from = StreetNetworkPosition::new(start.u, start.v, 0.0);
+            let expected = dijkstra(&graph, index[&start.u], None, |e| *e.weight());
+            for _ in 0..10 {
+                let end = &edges[rng.gen_range(0..edges.len())];
+                let to = StreetNetworkPosition::new(end.u, end.v, 0.0);
+                for algorithm in [
+                    RoutingAlgorithm::Dijkstra,
+                    RoutingAlgorithm::AStar { max_scale: 1.0 },
+                ] {
+                    let route = network.route(&from, &to, algorithm, None).unwrap();
+                    assert_close(route.length, expected[&index[&end.u]]);
+                    assert_close(route.cost, route.length);
+                    assert_connected(&route);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn a_star_matches_dijkstra_between_points_along_edges() {
+        let mut rng = StdRng::seed_from_u64(22);
+        let network = organic_network(&mut rng);
+        for (from, to) in random_pairs(&network, 200, &mut rng) {
+            let dijkstra = network.route(&from, &to, RoutingAlgorithm::Dijkstra, None);
+            let a_star =
+                network.route(&from, &to, RoutingAlgorithm::AStar { max_scale: 1.0 }, None);
+            let (Some(dijkstra), Some(a_star)) = (dijkstra, a_star) else {
+                panic!("no route from {} to {}", from, to);
+            };
+            assert_close(a_star.length, dijkstra.length);
+            assert_connected(&dijkstra);
+            assert_connected(&a_star);
+        }
+    }
+
+    #[test]
+    fn route_along_one_street_stays_on_it() {
+        let mut rng = StdRng::seed_from_u64(22);
+        let network = StreetNetwork::synthetic(
+            &SyntheticLayout::Grid {
+                columns: 4,
+                rows: 4,
+            },
+            (400.0, 400.0),
+            &mut rng,
+        );
+        let edge = network.edge_list()[0].clone();
+        let from = StreetNetworkPosition::new(edge.u, edge.v, 80.0);
+        let to = StreetNetworkPosition::new(edge.u, edge.v, 30.0);
+        let route = network
+            .route(&from, &to, RoutingAlgorithm::Dijkstra, None)
+            .unwrap();
+        assert_eq!(route.edges, vec![(edge.v, edge.u)]);
+        assert!(route.nodes.is_empty());
+        assert_close(route.length, 50.0);
+    }
+
+    #[test]
+    fn expected_waits_steer_routes_round_signals() {
+        // Two equally long ways round a square from a to c, one through signals at b
+        let mut network = Network::new(true);
+        let node = |id, x, y| StreetNode::new(id, Real2D { x, y });
+        let (a, d, c) = (
+            node(1, 0.0, 0.0),
+            node(4, 0.0, 100.0),
+            node(3, 100.0, 100.0),
+        );
+        let b = StreetNode {
+            control: NodeControl::TrafficSignals,
+            ..node(2, 100.0, 0.0)
+        };
+        for n in [a, b, c, d] {
+            network.add_node(n);
+        }
+        for (way_id, (u, v)) in (1..).zip([(a, b), (b, c), (c, d), (d, a)]) {
+            let label = StreetEdgeLabel::new(100.0, way_id, 0);
+            network.add_edge(u, v, EdgeOptions::WeightedLabeled(label.reversed(), 100.0));
+            network.add_edge(v, u, EdgeOptions::WeightedLabeled(label, 100.0));
+        }
+        network.lazy_update();
+        let ids = |n: StreetNode| network.nodes2id[network.read].borrow()[&n];
+        let (a_id, b_id, c_id, d_id) = (ids(a), ids(b), ids(c), ids(d));
+        let network = StreetNetwork(network);
+
+        let from = StreetNetworkPosition::new(b_id, a_id, 100.0);
+        let to = StreetNetworkPosition::new(c_id, d_id, 0.0);
+        let delays = IntersectionDelays::default();
+        let route = network
+            .route(&from, &to, RoutingAlgorithm::Dijkstra, Some(&delays))
+            .unwrap();
+        assert!(!route.nodes.contains(&b_id), "{:?}", route);
+        assert_eq!(route.nodes.first(), Some(&a_id));
+        assert_close(route.length, 200.0);
+        assert_close(route.cost, 200.0);
+
+        // Past the signals the other way round, their expected wait is charged
+        let from = StreetNetworkPosition::new(a_id, b_id, 50.0);
+        let route = network
+            .route(&from, &to, RoutingAlgorithm::Dijkstra, Some(&delays))
+            .unwrap();
+        assert_eq!(route.nodes, vec![b_id, c_id]);
+        let wait = delays.expected_delay(NodeControl::TrafficSignals) * WALKING_SPEED;
+        assert_close(route.cost, 150.0 + wait);
+    }
+}
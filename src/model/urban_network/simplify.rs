@@ -0,0 +1,264 @@
+use std::collections::{HashMap, HashSet};
+
+use indicatif::ProgressBar;
+use krabmaga::engine::fields::{
+    field::Field,
+    network::{Edge, EdgeOptions, Network},
+};
+use krabmaga::engine::location::Real2D;
+
+use super::{NodeControl, StreetEdgeLabel, StreetNetwork};
+
+impl StreetNetwork {
+    /// Collapse interstitial nodes into single edges, OSMnx-style.
+    ///
+    /// A node is interstitial if it merely continues one street: it links exactly two neighbours,
+    /// either as a one-way chain (in from one, out to the other) or in both directions with both,
+    /// and the edges on either side carry matching attributes, and no crossing or signal sits on
+    /// it. Each resulting edge keeps the summed length of the edges it replaces and their full
+    /// polyline in `geometry`, and is identified by the lowest segment ID among them.
+    pub fn simplified(&self) -> StreetNetwork {
+        let edges = self.edge_list();
+        let mut outgoing: HashMap<u32, Vec<&Edge<StreetEdgeLabel>>> = HashMap::new();
+        let mut incoming: HashMap<u32, Vec<&Edge<StreetEdgeLabel>>> = HashMap::new();
+        for edge in edges.iter() {
+            outgoing.entry(edge.u).or_default().push(edge);
+            incoming.entry(edge.v).or_default().push(edge);
+        }
+
+        let node_ids = self.node_ids();
+        let is_interstitial = |node: u32| {
+            let outs = outgoing.get(&node).map(Vec::as_slice).unwrap_or_default();
+            let ins = incoming.get(&node).map(Vec::as_slice).unwrap_or_default();
+            let out_nbrs: HashSet<u32> = outs.iter().map(|e| e.v).collect();
+            let in_nbrs: HashSet<u32> = ins.iter().map(|e| e.u).collect();
+
+            let one_way_chain = outs.len() == 1
+                && ins.len() == 1
+                && out_nbrs != in_nbrs
+                && !out_nbrs.contains(&node);
+            let two_way_chain = outs.len() == 2
+                && ins.len() == 2
+                && out_nbrs.len() == 2
+                && out_nbrs == in_nbrs
+                && !out_nbrs.contains(&node);
+            let labels_match = outs
+                .iter()
+                .chain(ins.iter())
+                .filter_map(|e| e.label.as_ref())
+                .collect::<Vec<_>>()
+                .windows(2)
+                .all(|pair| pair[0].attributes_match(pair[1]));
+
+            let uncontrolled = self
+                .0
+                .get_object(node)
+                .is_none_or(|n| n.control == NodeControl::None);
+
+            (one_way_chain || two_way_chain) && labels_match && uncontrolled
+        };
+
+        let mut endpoints: HashSet<u32> = node_ids
+            .iter()
+            .copied()
+            .filter(|&n| !is_interstitial(n))
+            .collect();
+
+        let simplified = StreetNetwork(Network::new(self.0.direct));
+        let mut added_nodes: HashSet<u32> = HashSet::new();
+        let mut visited: HashSet<u32> = HashSet::new();
+        let mut add_node = |id: u32| {
+            if added_nodes.insert(id) {
+                if let Some(node) = self.0.get_object(id) {
+                    simplified.0.add_node(node);
+                }
+            }
+        };
+
+        println!("Simplifying street network topology...");
+        let pb = ProgressBar::new(node_ids.len() as u64);
+        let mut pending: Vec<u32> = endpoints.iter().copied().collect();
+        let mut remaining = node_ids.iter().copied();
+        // Once endpoints run out, only isolated loops made entirely of interstitial nodes remain;
+        // break each open at an arbitrary node
+        while let Some(start) = pending
+            .pop()
+            .or_else(|| remaining.find(|n| !visited.contains(n) && !endpoints.contains(n)))
+        {
+            endpoints.insert(start);
+            visited.insert(start);
+            pb.inc(1);
+            add_node(start);
+
+            for first_edge in outgoing.get(&start).map(Vec::as_slice).unwrap_or_default() {
+                let mut label = first_edge.label.clone().unwrap_or_default();
+                let mut geometry = self.edge_points(first_edge);
+                let (mut prev, mut current) = (start, first_edge.v);
+
+                while !endpoints.contains(&current) {
+                    if visited.insert(current) {
+                        pb.inc(1);
+                    }
+                    let Some(next_edge) = outgoing
+                        .get(&current)
+                        .and_then(|outs| outs.iter().find(|e| e.v != prev || outs.len() == 1))
+                    else {
+                        break;
+                    };
+                    let next_label = next_edge.label.clone().unwrap_or_default();
+                    label.len += next_label.len;
+                    // Keep the lowest segment ID along the chain, which both directions share
+                    if next_label.id < label.id {
+                        label.id = next_label.id;
+                        label.way_id = next_label.way_id;
+                        label.segment = next_label.segment;
+                    }
+                    geometry.extend(self.edge_points(next_edge).into_iter().skip(1));
+                    (prev, current) = (current, next_edge.v);
+                }
+
+                add_node(current);
+                label.geometry = geometry;
+                let weight = label.len;
+                if let (Some(u), Some(v)) = (self.0.get_object(start), self.0.get_object(current)) {
+                    simplified
+                        .0
+                        .add_edge(u, v, EdgeOptions::WeightedLabeled(label, weight));
+                }
+            }
+        }
+        pb.finish();
+
+        let mut simplified = simplified;
+        simplified.0.lazy_update();
+        simplified
+    }
+
+    /// Polyline of an edge from its source to its target node.
+    fn edge_points(&self, edge: &Edge<StreetEdgeLabel>) -> Vec<Real2D> {
+        match &edge.label {
+            Some(label) if label.geometry.len() >= 2 => label.geometry.clone(),
+            _ => [edge.u, edge.v]
+                .iter()
+                .filter_map(|id| self.0.get_object(*id).map(|n| n.loc))
+                .collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::urban_network::StreetNode;
+
+    /// Network of two-way streets between nodes at the given locations, named per street.
+    fn street_network(locs: &[(f32, f32)], streets: &[(usize, usize, &str)]) -> StreetNetwork {
+        let mut network = Network::new(true);
+        let nodes: Vec<StreetNode> = locs
+            .iter()
+            .enumerate()
+            .map(|(i, &(x, y))| StreetNode::new(i as i64, Real2D { x, y }))
+            .collect();
+        for node in &nodes {
+            network.add_node(*node);
+        }
+        for (i, &(u, v, name)) in streets.iter().enumerate() {
+            let (a, b) = (nodes[u].loc, nodes[v].loc);
+            let label = StreetEdgeLabel {
+                name: Some(name.to_string()),
+                ..StreetEdgeLabel::new(
+                    ((b.x - a.x).powi(2) + (b.y - a.y).powi(2)).sqrt(),
+                    i as i64,
+                    0,
+                )
+            };
+            let reverse = label.reversed();
+            network.add_edge(
+                nodes[u],
+                nodes[v],
+                EdgeOptions::WeightedLabeled(label.clone(), label.len),
+            );
+            network.add_edge(
+                nodes[v],
+                nodes[u],
+                EdgeOptions::WeightedLabeled(reverse, label.len),
+            );
+        }
+        network.lazy_update();
+        StreetNetwork(network)
+    }
+
+    fn osm_ids(network: &StreetNetwork) -> Vec<i64> {
+        let mut ids: Vec<i64> = network
+            .node_ids()
+            .into_iter()
+            .filter_map(|id| network.0.get_object(id).map(|node| node.osm_id))
+            .collect();
+        ids.sort_unstable();
+        ids
+    }
+
+    #[test]
+    fn chains_of_matching_edges_collapse_into_one() {
+        let network = street_network(
+            &[(0.0, 0.0), (10.0, 0.0), (20.0, 0.0), (20.0, 10.0)],
+            &[(0, 1, "Main"), (1, 2, "Main"), (2, 3, "Main")],
+        );
+        let simplified = network.simplified();
+        assert_eq!(osm_ids(&simplified), vec![0, 3]);
+
+        let edges = simplified.edge_list();
+        assert_eq!(edges.len(), 2);
+        for edge in edges {
+            let label = edge.label.unwrap();
+            assert!((label.len - 30.0).abs() < 1e-4);
+            assert_eq!(label.geometry.len(), 4);
+            let start = simplified.0.get_object(edge.u).unwrap().loc;
+            assert_eq!(label.geometry[0], start);
+        }
+    }
+
+    #[test]
+    fn attribute_changes_and_junctions_are_kept() {
+        // Node 1 joins differently named streets; node 2 is a junction of three
+        let network = street_network(
+            &[
+                (0.0, 0.0),
+                (10.0, 0.0),
+                (20.0, 0.0),
+                (30.0, 0.0),
+                (20.0, 10.0),
+            ],
+            &[
+                (0, 1, "Main"),
+                (1, 2, "High"),
+                (2, 3, "High"),
+                (2, 4, "High"),
+            ],
+        );
+        let simplified = network.simplified();
+        assert_eq!(osm_ids(&simplified), vec![0, 1, 2, 3, 4]);
+        assert_eq!(simplified.edge_list().len(), 8);
+    }
+
+    #[test]
+    fn isolated_loops_are_broken_open_at_one_node() {
+        let network = street_network(
+            &[(0.0, 0.0), (10.0, 0.0), (10.0, 10.0), (0.0, 10.0)],
+            &[
+                (0, 1, "Ring"),
+                (1, 2, "Ring"),
+                (2, 3, "Ring"),
+                (3, 0, "Ring"),
+            ],
+        );
+        let simplified = network.simplified();
+        assert_eq!(osm_ids(&simplified).len(), 1);
+        let edges = simplified.edge_list();
+        assert_eq!(edges.len(), 2);
+        for edge in edges {
+            assert_eq!(edge.u, edge.v);
+            assert!((edge.label.unwrap().len - 40.0).abs() < 1e-4);
+        }
+    }
+}
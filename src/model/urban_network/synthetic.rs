@@ -0,0 +1,327 @@
+use std::collections::HashMap;
+use std::f32::consts::TAU;
+
+use krabmaga::engine::fields::{field::Field, network::Network};
+use krabmaga::engine::location::Real2D;
+use krabmaga::rand::Rng;
+use spade::{DelaunayTriangulation, Point2, Triangulation};
+
+use super::{
+    network::edge_options, Connectivity, HighwayClass, StreetEdgeLabel, StreetNetwork, StreetNode,
+};
+
+/// Layout of a generated street network, which is scaled to fill the extent it is generated in.
+#[derive(Clone, Debug)]
+pub enum SyntheticLayout {
+    /// Orthogonal grid of `columns` by `rows` blocks; every fourth street is a tertiary road
+    Grid { columns: u32, rows: u32 },
+    /// `rings` concentric ring roads crossed by `spokes` secondary roads radiating from the centre
+    Radial { rings: u32, spokes: u32 },
+    /// Irregular blocks traced by the Voronoi diagram of `cells` randomly placed seeds, mostly
+    /// meeting at three-way junctions as in unplanned street networks
+    Organic { cells: u32 },
+    /// Suburb along a tertiary collector road, with `branches` residential streets leaving it on
+    /// either side, each running `depth` segments to a dead end
+    CulDeSac { branches: u32, depth: u32 },
+}
+
+/// Adds nodes and two-way streets to a network, numbering nodes and streets from 1 in the order
+/// they are added, as OSM IDs would be.
+struct StreetBuilder {
+    network: Network<StreetNode, StreetEdgeLabel>,
+    nodes: u32,
+    streets: i64,
+}
+
+impl StreetBuilder {
+    fn new() -> Self {
+        StreetBuilder {
+            network: Network::new(true),
+            nodes: 0,
+            streets: 0,
+        }
+    }
+
+    fn node(&mut self, x: f32, y: f32) -> StreetNode {
+        self.nodes += 1;
+        let node = StreetNode::new(self.nodes as i64, Real2D { x, y });
+        self.network.add_node(node);
+        node
+    }
+
+    /// Add a street through the given nodes, each segment labelled with its Euclidean length.
+    fn street(&mut self, nodes: &[StreetNode], highway: HighwayClass, name: Option<String>) {
+        self.streets += 1;
+        for (segment, pair) in (0..).zip(nodes.windows(2)) {
+            let (u, v) = (pair[0], pair[1]);
+            let len = ((v.loc.x - u.loc.x).powi(2) + (v.loc.y - u.loc.y).powi(2)).sqrt();
+            let label = StreetEdgeLabel {
+                highway,
+                name: name.clone(),
+                ..StreetEdgeLabel::new(len, self.streets, segment)
+            };
+            let reverse_label = label.reversed();
+            self.network
+                .add_edge(u, v, edge_options(Some(label), Some(len)));
+            self.network
+                .add_edge(v, u, edge_options(Some(reverse_label), Some(len)));
+        }
+    }
+
+    fn finish(mut self) -> StreetNetwork {
+        self.network.lazy_update();
+        StreetNetwork(self.network)
+    }
+}
+
+impl StreetNetwork {
+    /// Generate a street network with the given layout filling an extent of `dim` metres. Edges
+    /// run in both directions and carry labels with their Euclidean lengths, as imported ones do.
+    pub fn synthetic(layout: &SyntheticLayout, dim: (f32, f32), rng: &mut impl Rng) -> Self {
+        match *layout {
+            SyntheticLayout::Grid { columns, rows } => grid(columns.max(1), rows.max(1), dim),
+            SyntheticLayout::Radial { rings, spokes } => radial(rings.max(1), spokes.max(3), dim),
+            SyntheticLayout::Organic { cells } => organic(cells.max(3), dim, rng),
+            SyntheticLayout::CulDeSac { branches, depth } => {
+                cul_de_sac(branches.max(1), depth.max(1), dim)
+            }
+        }
+    }
+}
+
+fn grid(columns: u32, rows: u32, dim: (f32, f32)) -> StreetNetwork {
+    let mut builder = StreetBuilder::new();
+    let (block_x, block_y) = (dim.0 / columns as f32, dim.1 / rows as f32);
+    let nodes: Vec<Vec<StreetNode>> = (0..=rows)
+        .map(|row| {
+            (0..=columns)
+                .map(|column| builder.node(column as f32 * block_x, row as f32 * block_y))
+                .collect()
+        })
+        .collect();
+    let class = |i: u32| {
+        if i.is_multiple_of(4) {
+            HighwayClass::Tertiary
+        } else {
+            HighwayClass::Residential
+        }
+    };
+
+    for (row, street) in (0..).zip(nodes.iter()) {
+        builder.street(street, class(row), Some(format!("{} Street", row + 1)));
+    }
+    for column in 0..=columns {
+        let avenue: Vec<StreetNode> = nodes.iter().map(|row| row[column as usize]).collect();
+        builder.street(
+            &avenue,
+            class(column),
+            Some(format!("{} Avenue", column + 1)),
+        );
+    }
+    builder.finish()
+}
+
+fn radial(rings: u32, spokes: u32, dim: (f32, f32)) -> StreetNetwork {
+    let mut builder = StreetBuilder::new();
+    let centre = (dim.0 / 2.0, dim.1 / 2.0);
+    let spacing = dim.0.min(dim.1) / 2.0 / rings as f32;
+
+    let hub = builder.node(centre.0, centre.1);
+    let ring_nodes: Vec<Vec<StreetNode>> = (1..=rings)
+        .map(|ring| {
+            let radius = ring as f32 * spacing;
+            (0..spokes)
+                .map(|spoke| {
+                    let angle = spoke as f32 * TAU / spokes as f32;
+                    builder.node(
+                        centre.0 + radius * angle.cos(),
+                        centre.1 + radius * angle.sin(),
+                    )
+                })
+                .collect()
+        })
+        .collect();
+
+    for spoke in 0..spokes as usize {
+        let nodes: Vec<StreetNode> = std::iter::once(hub)
+            .chain(ring_nodes.iter().map(|ring| ring[spoke]))
+            .collect();
+        builder.street(
+            &nodes,
+            HighwayClass::Secondary,
+            Some(format!("Spoke {}", spoke + 1)),
+        );
+    }
+    for (ring, nodes) in (1..).zip(ring_nodes.iter()) {
+        let closed: Vec<StreetNode> = nodes.iter().chain(nodes.first()).copied().collect();
+        builder.street(
+            &closed,
+            HighwayClass::Residential,
+            Some(format!("Ring {}", ring)),
+        );
+    }
+    builder.finish()
+}
+
+/// Voronoi edges between seeds scattered over the extent, keeping those with both ends inside it.
+/// Boundary effects can cut off a few edges, so only the largest connected piece is returned.
+/// Should the seeds fail to triangulate, or leave no edge inside the extent, a grid of about as
+/// many blocks is generated instead.
+fn organic(cells: u32, dim: (f32, f32), rng: &mut impl Rng) -> StreetNetwork {
+    let seeds: Vec<Point2<f64>> = (0..cells)
+        .map(|_| {
+            Point2::new(
+                (rng.gen::<f32>() * dim.0) as f64,
+                (rng.gen::<f32>() * dim.1) as f64,
+            )
+        })
+        .collect();
+    let side = (cells as f32).sqrt().ceil() as u32;
+    let Ok(triangulation) = DelaunayTriangulation::<Point2<f64>>::bulk_load(seeds) else {
+        return grid(side, side, dim);
+    };
+
+    let mut builder = StreetBuilder::new();
+    let mut vertices: HashMap<usize, StreetNode> = HashMap::new();
+    let inside = |p: &Point2<f64>| {
+        (0.0..=dim.0 as f64).contains(&p.x) && (0.0..=dim.1 as f64).contains(&p.y)
+    };
+    for edge in triangulation.undirected_voronoi_edges() {
+        let [a, b] = edge.vertices();
+        let (Some(fa), Some(fb)) = (a.as_delaunay_face(), b.as_delaunay_face()) else {
+            continue;
+        };
+        let (pa, pb) = (fa.circumcenter(), fb.circumcenter());
+        if !inside(&pa) || !inside(&pb) {
+            continue;
+        }
+        let mut node_for = |index: usize, p: Point2<f64>| {
+            *vertices
+                .entry(index)
+                .or_insert_with(|| builder.node(p.x as f32, p.y as f32))
+        };
+        let (u, v) = (
+            node_for(fa.fix().index(), pa),
+            node_for(fb.fix().index(), pb),
+        );
+        builder.street(&[u, v], HighwayClass::Residential, None);
+    }
+
+    if builder.streets == 0 {
+        return grid(side, side, dim);
+    }
+    let network = builder.finish();
+    network.largest_component(Connectivity::Weak).0
+}
+
+fn cul_de_sac(branches: u32, depth: u32, dim: (f32, f32)) -> StreetNetwork {
+    let mut builder = StreetBuilder::new();
+    let mid_y = dim.1 / 2.0;
+    let step_x = dim.0 / (branches + 1) as f32;
+    let step_y = mid_y / (depth as f32 + 0.5);
+
+    let collector: Vec<StreetNode> = (0..=branches + 1)
+        .map(|i| builder.node(i as f32 * step_x, mid_y))
+        .collect();
+    builder.street(
+        &collector,
+        HighwayClass::Tertiary,
+        Some("Collector Road".to_string()),
+    );
+
+    for (branch, junction) in (1..).zip(collector[1..=branches as usize].iter()) {
+        for (side, direction) in [("North", 1.0), ("South", -1.0)] {
+            let nodes: Vec<StreetNode> =
+                std::iter::once(*junction)
+                    .chain((1..=depth).map(|d| {
+                        builder.node(junction.loc.x, mid_y + direction * d as f32 * step_y)
+                    }))
+                    .collect();
+            builder.street(
+                &nodes,
+                HighwayClass::Residential,
+                Some(format!("{} {} Close", branch, side)),
+            );
+        }
+    }
+    builder.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use krabmaga::rand::rngs::StdRng;
+    use krabmaga::rand::SeedableRng;
+
+    use super::*;
+
+    fn generate(layout: SyntheticLayout) -> StreetNetwork {
+        StreetNetwork::synthetic(&layout, (1000.0, 800.0), &mut StdRng::seed_from_u64(3))
+    }
+
+    fn counts(network: &StreetNetwork) -> (usize, usize) {
+        (network.node_ids().len(), network.edge_list().len())
+    }
+
+    #[test]
+    fn grids_have_a_node_at_every_corner() {
+        let network = generate(SyntheticLayout::Grid {
+            columns: 4,
+            rows: 3,
+        });
+        // 4 streets of 4 blocks and 5 avenues of 3, walkable both ways
+        assert_eq!(counts(&network), (20, 2 * (4 * 4 + 5 * 3)));
+        // Blocks are stretched to fill the extent
+        for edge in network.edge_list() {
+            let len = edge.label.unwrap().len;
+            assert!((len - 250.0).abs() < 1e-3 || (len - 800.0 / 3.0).abs() < 1e-3);
+        }
+    }
+
+    #[test]
+    fn radial_layouts_join_every_ring_to_the_hub() {
+        let network = generate(SyntheticLayout::Radial {
+            rings: 3,
+            spokes: 6,
+        });
+        assert_eq!(counts(&network), (1 + 3 * 6, 2 * (3 * 6 + 3 * 6)));
+        let (largest, _) = network.largest_component(Connectivity::Strong);
+        assert_eq!(counts(&largest), counts(&network));
+    }
+
+    #[test]
+    fn cul_de_sacs_end_in_dead_ends() {
+        let network = generate(SyntheticLayout::CulDeSac {
+            branches: 3,
+            depth: 2,
+        });
+        assert_eq!(counts(&network), (5 + 2 * 3 * 2, 2 * (4 + 2 * 3 * 2)));
+        let edges = network.edge_list();
+        let dead_ends = network
+            .node_ids()
+            .into_iter()
+            .filter(|&id| edges.iter().filter(|edge| edge.u == id).count() == 1)
+            .count();
+        // Both ends of the collector, and the end of every close
+        assert_eq!(dead_ends, 2 + 2 * 3);
+    }
+
+    #[test]
+    fn organic_layouts_are_connected_and_within_their_extent() {
+        let network = generate(SyntheticLayout::Organic { cells: 50 });
+        let (nodes, edges) = counts(&network);
+        assert!(nodes > 0 && edges > 0);
+        let (largest, _) = network.largest_component(Connectivity::Weak);
+        assert_eq!(counts(&largest), (nodes, edges));
+        for id in network.node_ids() {
+            let loc = network.0.get_object(id).unwrap().loc;
+            assert!((0.0..=1000.0).contains(&loc.x) && (0.0..=800.0).contains(&loc.y));
+        }
+    }
+
+    #[test]
+    fn organic_layouts_fall_back_to_a_grid_without_voronoi_edges() {
+        // Seeds on a line have no Delaunay triangles, so no Voronoi vertices to join
+        let network = organic(5, (1000.0, 0.0), &mut StdRng::seed_from_u64(3));
+        assert_eq!(counts(&network), (16, 2 * (4 * 3 + 4 * 3)));
+    }
+}
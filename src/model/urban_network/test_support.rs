@@ -0,0 +1,50 @@
+//! Helpers shared by the routing tests.
+
+use krabmaga::rand::rngs::StdRng;
+
+use super::{Route, StreetNetwork, StreetNetworkPosition, SyntheticLayout};
+
+/// Assert that two lengths or costs agree to within a millimetre or float rounding.
+pub fn assert_close(a: f32, b: f32) {
+    assert!(
+        (a - b).abs() <= 1e-3 + 1e-5 * a.abs().max(b.abs()),
+        "{} != {}",
+        a,
+        b
+    );
+}
+
+/// Check that a route's edges follow on from one another through its nodes.
+pub fn assert_connected(route: &Route) {
+    for pair in route.edges.windows(2) {
+        assert_eq!(pair[0].1, pair[1].0, "edges {:?} do not join", pair);
+    }
+    let inner: Vec<u32> = route.edges.iter().skip(1).map(|(u, _)| *u).collect();
+    assert!(inner.iter().all(|node| route.nodes.contains(node)));
+}
+
+/// An irregular kilometre-square street network, as routes are least predictable on one.
+pub fn organic_network(rng: &mut StdRng) -> StreetNetwork {
+    StreetNetwork::synthetic(
+        &SyntheticLayout::Organic { cells: 80 },
+        (1000.0, 1000.0),
+        rng,
+    )
+}
+
+/// Random start and end positions along the network's edges.
+pub fn random_pairs(
+    network: &StreetNetwork,
+    count: usize,
+    rng: &mut StdRng,
+) -> Vec<(StreetNetworkPosition, StreetNetworkPosition)> {
+    let edges = network.edge_list();
+    (0..count)
+        .map(|_| {
+            (
+                StreetNetworkPosition::rand_from_edge_list(&edges, rng),
+                StreetNetworkPosition::rand_from_edge_list(&edges, rng),
+            )
+        })
+        .collect()
+}
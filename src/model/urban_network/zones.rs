@@ -0,0 +1,160 @@
+use std::{fs, path::Path};
+
+use geo::{BoundingRect, Contains, Coord, Geometry, MapCoords, MultiPolygon, Point, Rect};
+use geojson::{feature::Id, GeoJson, JsonValue};
+use krabmaga::engine::location::Real2D;
+
+use super::{
+    boundary::{polygons_of, BoundaryError},
+    LocalProjection, StreetNetwork, StreetNetworkPosition,
+};
+
+/// A named area (neighbourhood, census block, ...) in the network's projected coordinates.
+#[derive(Clone, Debug)]
+pub struct Zone {
+    pub name: String,
+    pub area: MultiPolygon<f64>,
+    bounds: Option<Rect<f64>>,
+}
+
+impl Zone {
+    pub fn new(name: String, area: MultiPolygon<f64>) -> Self {
+        let bounds = area.bounding_rect();
+        Zone { name, area, bounds }
+    }
+
+    pub fn contains(&self, loc: Real2D) -> bool {
+        let point = Point::new(loc.x as f64, loc.y as f64);
+        self.bounds.is_some_and(|b| b.contains(&point)) && self.area.contains(&point)
+    }
+}
+
+/// A set of zones the simulation can use to aggregate results by area.
+#[derive(Clone, Debug, Default)]
+pub struct ZoneLayer {
+    pub zones: Vec<Zone>,
+}
+
+impl ZoneLayer {
+    /// Load every (Multi)Polygon feature in a GeoJSON file as a zone, projected into network
+    /// coordinates. Zones are named by the `name_property` of each feature, falling back to the
+    /// feature's ID and then to its index in the file.
+    pub fn from_geojson(
+        filepath: &Path,
+        name_property: &str,
+        projection: &LocalProjection,
+    ) -> Result<Self, BoundaryError> {
+        let contents = fs::read_to_string(filepath).map_err(BoundaryError::Io)?;
+        let geojson = contents.parse::<GeoJson>().map_err(BoundaryError::from)?;
+
+        let features: Vec<(Option<String>, Geometry<f64>)> = match geojson {
+            GeoJson::FeatureCollection(fc) => fc
+                .features
+                .into_iter()
+                .filter_map(|feature| {
+                    let name = feature
+                        .property(name_property)
+                        .map(json_to_name)
+                        .or_else(|| {
+                            feature.id.as_ref().map(|id| match id {
+                                Id::String(s) => s.clone(),
+                                Id::Number(n) => n.to_string(),
+                            })
+                        });
+                    let geometry = feature.geometry.map(Geometry::<f64>::try_from)?;
+                    Some(geometry.map(|g| (name, g)))
+                })
+                .collect::<Result<_, _>>()
+                .map_err(BoundaryError::from)?,
+            other => vec![(
+                None,
+                Geometry::<f64>::try_from(other).map_err(BoundaryError::from)?,
+            )],
+        };
+
+        let zones: Vec<Zone> = features
+            .into_iter()
+            .enumerate()
+            .filter_map(|(i, (name, geometry))| {
+                let polygons = polygons_of(geometry);
+                if polygons.is_empty() {
+                    return None;
+                }
+                let area = MultiPolygon::new(polygons).map_coords(|c| {
+                    let (x, y) = projection.project_f64(c.x, c.y);
+                    Coord { x, y }
+                });
+                Some(Zone::new(
+                    name.unwrap_or_else(|| format!("zone-{}", i)),
+                    area,
+                ))
+            })
+            .collect();
+
+        if zones.is_empty() {
+            return Err(BoundaryError::NoPolygons);
+        }
+        Ok(ZoneLayer { zones })
+    }
+
+    /// The first zone containing the given location.
+    pub fn zone_at(&self, loc: Real2D) -> Option<&Zone> {
+        self.zones.iter().find(|zone| zone.contains(loc))
+    }
+
+    /// The zone containing a position on the street network.
+    pub fn zone_of(
+        &self,
+        position: &StreetNetworkPosition,
+        network: &StreetNetwork,
+    ) -> Option<&Zone> {
+        position.location(network).and_then(|loc| self.zone_at(loc))
+    }
+}
+
+fn json_to_name(value: &JsonValue) -> String {
+    match value {
+        JsonValue::String(s) => s.clone(),
+        other => other.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn zones_are_projected_and_named() {
+        let path =
+            std::env::temp_dir().join(format!("flaneur-zones-{}.geojson", std::process::id()));
+        fs::write(
+            &path,
+            r#"{"type": "FeatureCollection", "features": [
+                {"type": "Feature", "properties": {"name": "west"},
+                 "geometry": {"type": "Polygon", "coordinates": [[[0, 0], [0.01, 0], [0.01, 0.01], [0, 0.01], [0, 0]]]}},
+                {"type": "Feature", "id": 7, "properties": {},
+                 "geometry": {"type": "Polygon", "coordinates": [[[0.01, 0], [0.02, 0], [0.02, 0.01], [0.01, 0.01], [0.01, 0]]]}},
+                {"type": "Feature", "properties": {"name": "a point"},
+                 "geometry": {"type": "Point", "coordinates": [0.005, 0.005]}}
+            ]}"#,
+        )
+        .unwrap();
+        let projection = LocalProjection::new(0.0, 0.0);
+        let layer = ZoneLayer::from_geojson(&path, "name", &projection);
+        fs::remove_file(&path).unwrap();
+        let layer = layer.unwrap();
+
+        // Points are not zones; unnamed features fall back to their ID
+        let names: Vec<&str> = layer.zones.iter().map(|zone| zone.name.as_str()).collect();
+        assert_eq!(names, vec!["west", "7"]);
+
+        let zone_name = |lon, lat| {
+            layer
+                .zone_at(projection.project(lon, lat))
+                .map(|zone| zone.name.as_str())
+        };
+        assert_eq!(zone_name(0.005, 0.005), Some("west"));
+        assert_eq!(zone_name(0.015, 0.005), Some("7"));
+        assert_eq!(zone_name(0.025, 0.005), None);
+    }
+}